@@ -1,23 +1,255 @@
-use anyhow::{anyhow, Result};
 use serde::Deserialize;
 use std::env;
 use url::Url;
 
+use crate::error::ExtensionError;
+use crate::sigv4::AwsCredentials;
+
+type Result<T> = std::result::Result<T, ExtensionError>;
+
+// OpenObserve JSON ingest path, relative to O2_ENDPOINT
+const DEFAULT_PATH_TEMPLATE: &str = "/api/{org}/{stream}/_json";
+
+// Fields the extension writes next to the record, which O2_RECORD_AS_MESSAGE_FIELD can't reuse
+const ENVELOPE_FIELDS: &[&str] = &[
+    "_raw", "_seq", "_timestamp", "depth_truncated", "extension_version", "function_arn", "init_type",
+    "instance_id", "lag_ms", "pid", "requestId", "type",
+];
+
+// Function log lines that continue the previous one: indented or Java/JS "at ..." frames
+const DEFAULT_MULTILINE_PATTERN: &str = r"^(\s|at\s)";
+
+// Invoke timestamps kept for the invocation-rate estimate (O2_MAX_RECENT_INVOCATIONS)
+pub const DEFAULT_MAX_RECENT_INVOCATIONS: usize = 5000;
+
+// Where extracted platform metrics are exported, in addition to the log stream
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum MetricsExport {
+    #[default]
+    None,
+    Prometheus,
+}
+
+// HTTP protocol version used for requests to OpenObserve
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum HttpVersion {
+    // Negotiated via TLS ALPN
+    #[default]
+    Auto,
+    Http1,
+    Http2,
+}
+
+// IP family used to reach OpenObserve, for dual-stack networks where one isn't routed
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum IpVersion {
+    #[default]
+    Auto,
+    V4,
+    V6,
+}
+
+// Lowest TLS version accepted for egress
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum TlsVersion {
+    #[default]
+    Tls12,
+    Tls13,
+}
+
+impl IpVersion {
+    // Whether a resolved address may be connected to
+    pub fn allows(&self, addr: &std::net::SocketAddr) -> bool {
+        match self {
+            IpVersion::Auto => true,
+            IpVersion::V4 => addr.is_ipv4(),
+            IpVersion::V6 => addr.is_ipv6(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum TimestampUnit {
+    Seconds,
+    Millis,
+    #[default]
+    Micros,
+    Nanos,
+}
+
+impl TimestampUnit {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "seconds" | "s" => Some(TimestampUnit::Seconds),
+            "millis" | "ms" => Some(TimestampUnit::Millis),
+            "micros" | "us" => Some(TimestampUnit::Micros),
+            "nanos" | "ns" => Some(TimestampUnit::Nanos),
+            _ => None,
+        }
+    }
+
+    /// Convert epoch microseconds into this unit
+    pub fn convert_micros(self, micros: i64) -> i64 {
+        match self {
+            TimestampUnit::Seconds => micros / 1_000_000,
+            TimestampUnit::Millis => micros / 1_000,
+            TimestampUnit::Micros => micros,
+            TimestampUnit::Nanos => micros.saturating_mul(1_000),
+        }
+    }
+}
+
+// How `_timestamp` is written: a number in O2_TIMESTAMP_UNIT, or an RFC3339 string
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum TimestampFormat {
+    #[default]
+    Epoch,
+    Rfc3339,
+}
+
+// Batch body format: a JSON array of events, or a CSV document of O2_CSV_COLUMNS
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum OutputEncoding {
+    #[default]
+    Json,
+    Csv,
+}
+
+// Flushing strategy, either chosen from the invocation pattern or pinned by O2_FLUSH_STRATEGY
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum FlushStrategy {
+    #[default]
+    Auto,
+    EndOfInvocation,
+    Continuous,
+    Periodic,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+pub enum AuthMode {
+    // Static O2_AUTHORIZATION_HEADER value
+    #[default]
+    Header,
+    // AWS SigV4 using the execution role credentials
+    SigV4,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub o2_endpoint: String,
     pub o2_organization_id: String,
     pub o2_stream: String,
+    pub o2_path_template: String,
+    pub strip_ingest_path: bool,
+    pub mirror_endpoint: Option<String>,
+    pub mirror_authorization_header: Option<String>,
+    // Let the mirror use the primary's credentials when it has no header of its own
+    pub mirror_use_primary_auth: bool,
     pub o2_authorization_header: String,
+    pub auth_mode: AuthMode,
+    pub sigv4_service: String,
+    // Execution role credentials for O2_AUTH_MODE=sigv4, read once by from_env
+    #[serde(skip)]
+    pub aws_credentials: Option<AwsCredentials>,
+    pub content_type: String,
+    pub health_path: Option<String>,
+    pub health_check_no_write: bool,
+    pub checksum: bool,
     
     // Performance tuning
     pub max_buffer_size_mb: usize,
+    pub target_batch_bytes: usize,
+    pub max_queue_len: usize,
+    pub max_recent_invocations: usize,
+    pub next_event_max_errors: u32,
     pub request_timeout_ms: u64,
+    pub timing_detail: bool,
+    pub max_url_len: usize,
+    pub http_version: HttpVersion,
+    pub ip_version: IpVersion,
+    pub tls_min_version: TlsVersion,
+    pub http_max_body_bytes: usize,
+    pub keepalive_idle_ms: u64,
+    pub pool_max_idle_per_host: usize,
+    pub max_batches_per_flush: usize,
+    pub continuous_flush_interval_ms: u64,
+    pub min_batch_age_ms: u64,
+    pub memory_flush_percent: u64,
+    pub buffer_warn_percent: u64,
+    pub batch_by_request_id: bool,
+    pub buffer_shrink_idle_ms: u64,
+    pub wall_clock_flush_ms: u64,
+    pub flush_strategy: FlushStrategy,
+    pub flush_only_on_shutdown: bool,
+    pub async_invoke_flush: bool,
+    pub min_flush_interval_ms: u64,
+    pub freeze_gap_secs: u64,
+    pub function_memory_size_mb: Option<u64>,
+    pub init_type: Option<String>,
+    pub include_arn: bool,
+    pub aws_region: Option<String>,
+    pub function_name: Option<String>,
+    pub flush_on_types: Vec<String>,
+    pub count_only_types: Vec<String>,
     
     // Retry configuration
     pub max_retries: u32,
+    pub shutdown_max_retries: u32,
+    pub shutdown_precheck: bool,
     pub initial_retry_delay_ms: u64,
     pub max_retry_delay_ms: u64,
+    pub retryable_statuses: Vec<u16>,
+    pub requeue_rejected: bool,
+    
+    // Alerting
+    pub alert_webhook: Option<String>,
+    
+    // Telemetry subscription health
+    pub telemetry_resubscribe_window_ms: u64,
+    
+    // Event filtering
+    pub drop_platform_events: bool,
+    pub exclude_patterns: Vec<String>,
+    pub merge_multiline: bool,
+    pub multiline_pattern: String,
+    
+    // Record shaping
+    pub timestamp_field: Option<String>,
+    pub timestamp_unit: TimestampUnit,
+    pub timestamp_format: TimestampFormat,
+    pub field_renames: Vec<(String, String)>,
+    pub include_fields: Vec<String>,
+    pub type_map: Vec<(String, String)>,
+    pub record_field: Option<String>,
+    pub event_template: Option<String>,
+    pub output_encoding: OutputEncoding,
+    pub pretty_json: bool,
+    pub csv_columns: Vec<String>,
+    pub max_record_depth: usize,
+    pub include_instance_id: bool,
+    pub tag_version: bool,
+    pub include_raw: bool,
+    pub emit_request_id: bool,
+    pub preserve_order: bool,
+    pub propagate_xray: bool,
+    pub track_lag: bool,
+    pub telemetry_dedup: bool,
+    pub telemetry_concurrency: usize,
+    pub telemetry_callback_host: String,
+    pub immediate_forward: bool,
+    
+    // Platform metrics export
+    pub metrics_export: MetricsExport,
+    pub metrics_endpoint: Option<String>,
+    
+    // Lifecycle events
+    pub emit_startup_event: bool,
+    pub self_metrics_interval_ms: u64,
+    pub stats_interval_secs: u64,
+    pub self_log_sample: u64,
+    pub simulated_invoke_delay_ms: u64,
+    pub shutdown_grace_ms: u64,
+    pub first_event_timeout_ms: u64,
 }
 
 impl Default for Config {
@@ -26,12 +258,98 @@ impl Default for Config {
             o2_endpoint: "https://api.openobserve.ai".to_string(),
             o2_organization_id: String::new(),
             o2_stream: "default".to_string(),
+            o2_path_template: DEFAULT_PATH_TEMPLATE.to_string(),
+            strip_ingest_path: false,
+            mirror_endpoint: None,
+            mirror_authorization_header: None,
+            mirror_use_primary_auth: false,
             o2_authorization_header: String::new(),
+            auth_mode: AuthMode::Header,
+            sigv4_service: "execute-api".to_string(),
+            aws_credentials: None,
+            content_type: "application/json".to_string(),
+            health_path: None,
+            health_check_no_write: false,
+            checksum: false,
             max_buffer_size_mb: 10,
+            target_batch_bytes: 0,
+            max_queue_len: 100_000,
+            max_recent_invocations: DEFAULT_MAX_RECENT_INVOCATIONS,
+            next_event_max_errors: 3,
             request_timeout_ms: 30000,
+            timing_detail: false,
+            max_url_len: 2048,
+            http_version: HttpVersion::Auto,
+            ip_version: IpVersion::Auto,
+            tls_min_version: TlsVersion::Tls12,
+            http_max_body_bytes: 0,
+            keepalive_idle_ms: 0,
+            pool_max_idle_per_host: 0,
+            max_batches_per_flush: 0,
+            continuous_flush_interval_ms: 5000,
+            min_batch_age_ms: 0,
+            memory_flush_percent: 0,
+            buffer_warn_percent: 80,
+            batch_by_request_id: false,
+            buffer_shrink_idle_ms: 0,
+            wall_clock_flush_ms: 0,
+            flush_strategy: FlushStrategy::Auto,
+            flush_only_on_shutdown: false,
+            async_invoke_flush: false,
+            min_flush_interval_ms: 0,
+            freeze_gap_secs: 0,
+            function_memory_size_mb: None,
+            init_type: None,
+            include_arn: false,
+            aws_region: None,
+            function_name: None,
+            flush_on_types: Vec::new(),
+            count_only_types: Vec::new(),
             max_retries: 3,
+            shutdown_max_retries: 1,
+            shutdown_precheck: false,
             initial_retry_delay_ms: 1000,
             max_retry_delay_ms: 30000,
+            retryable_statuses: Vec::new(),
+            requeue_rejected: false,
+            alert_webhook: None,
+            telemetry_resubscribe_window_ms: 30000,
+            drop_platform_events: false,
+            exclude_patterns: Vec::new(),
+            merge_multiline: false,
+            multiline_pattern: DEFAULT_MULTILINE_PATTERN.to_string(),
+            timestamp_field: None,
+            timestamp_unit: TimestampUnit::Micros,
+            timestamp_format: TimestampFormat::Epoch,
+            field_renames: Vec::new(),
+            include_fields: Vec::new(),
+            type_map: Vec::new(),
+            record_field: None,
+            event_template: None,
+            output_encoding: OutputEncoding::Json,
+            pretty_json: false,
+            csv_columns: Vec::new(),
+            max_record_depth: 0,
+            include_instance_id: false,
+            tag_version: false,
+            include_raw: false,
+            emit_request_id: true,
+            preserve_order: false,
+            propagate_xray: false,
+            track_lag: false,
+            telemetry_dedup: false,
+            telemetry_concurrency: 0,
+            telemetry_callback_host: crate::telemetry::DEFAULT_CALLBACK_HOST.to_string(),
+            immediate_forward: false,
+            metrics_export: MetricsExport::None,
+            metrics_endpoint: None,
+            emit_startup_event: false,
+            self_metrics_interval_ms: 0,
+            stats_interval_secs: 0,
+            self_log_sample: 1,
+            simulated_invoke_delay_ms: 0,
+            shutdown_grace_ms: 200,
+            first_event_timeout_ms: 0,
         }
     }
 }
@@ -40,16 +358,65 @@ impl Config {
     pub fn from_env() -> Result<Self> {
         // Required environment variables
         let o2_organization_id = env::var("O2_ORGANIZATION_ID")
-            .map_err(|_| anyhow!("O2_ORGANIZATION_ID environment variable is required"))?;
+            .map_err(|_| ExtensionError::Config("O2_ORGANIZATION_ID environment variable is required".to_string()))?;
         
-        let o2_authorization_header = env::var("O2_AUTHORIZATION_HEADER")
-            .map_err(|_| anyhow!("O2_AUTHORIZATION_HEADER environment variable is required"))?;
+        let auth_mode = auth_mode_from_env()?;
         
-        let mut config = Config {
+        // SigV4 signs with the execution role instead of a static header
+        // The inline header wins; O2_AUTHORIZATION_HEADER_FILE is for credentials mounted as a file
+        let inline_header = match env::var("O2_AUTHORIZATION_HEADER") {
+            Ok(header) => Ok(header),
+            Err(e) => match env::var("O2_AUTHORIZATION_HEADER_FILE") {
+                Ok(path) => Ok(read_authorization_header_file(&path)?),
+                Err(_) => Err(e),
+            },
+        };
+        let o2_authorization_header = match (inline_header, auth_mode) {
+            (Ok(header), _) => header,
+            (Err(_), AuthMode::SigV4) => String::new(),
+            (Err(_), AuthMode::Header) => {
+                return Err(ExtensionError::Config("O2_AUTHORIZATION_HEADER environment variable is required".to_string()));
+            }
+        };
+        
+        let mut config = Self::with_optional_env(Config {
             o2_organization_id,
             o2_authorization_header,
+            auth_mode,
             ..Default::default()
-        };
+        })?;
+        if auth_mode == AuthMode::SigV4 {
+            config.aws_credentials = Some(AwsCredentials::from_env()?);
+        }
+        
+        // Validate configuration
+        config.validate()?;
+        
+        Ok(config)
+    }
+    
+    // Settings for --parse-file, which sends nothing and so needs no organization or
+    // credentials. Every other O2_* variable is read and checked as in from_env.
+    pub fn from_env_offline() -> Result<Self> {
+        let config = Self::with_optional_env(Config {
+            auth_mode: auth_mode_from_env()?,
+            ..Default::default()
+        })?;
+        
+        // Checked like a real config, with stand-ins for the credentials that aren't read
+        Config {
+            o2_organization_id: "offline".to_string(),
+            o2_authorization_header: "offline".to_string(),
+            ..config.clone()
+        }.validate()?;
+        
+        Ok(config)
+    }
+    
+    fn with_optional_env(mut config: Config) -> Result<Self> {
+        if let Ok(service) = env::var("O2_SIGV4_SERVICE") {
+            config.sigv4_service = service;
+        }
         
         // Optional environment variables with defaults
         if let Ok(endpoint) = env::var("O2_ENDPOINT") {
@@ -60,35 +427,514 @@ impl Config {
             config.o2_stream = stream;
         }
         
+        // Optional second destination receiving a copy of every batch
+        if let Ok(mirror_endpoint) = env::var("O2_MIRROR_ENDPOINT") {
+            if !mirror_endpoint.trim().is_empty() {
+                config.mirror_endpoint = Some(mirror_endpoint);
+            }
+        }
+        
+        if let Ok(mirror_header) = env::var("O2_MIRROR_AUTHORIZATION_HEADER") {
+            config.mirror_authorization_header = Some(mirror_header);
+        }
+        
+        if let Ok(use_primary_auth) = env::var("O2_MIRROR_USE_PRIMARY_AUTH") {
+            config.mirror_use_primary_auth = use_primary_auth.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_MIRROR_USE_PRIMARY_AUTH: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(path_template) = env::var("O2_PATH_TEMPLATE") {
+            config.o2_path_template = path_template;
+        }
+        
+        // O2_ENDPOINT set to the full ingest URL: trim it back to the base URL
+        if let Ok(strip) = env::var("O2_STRIP_INGEST_PATH") {
+            config.strip_ingest_path = strip.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_STRIP_INGEST_PATH: must be true or false".to_string()))?;
+        }
+        if config.strip_ingest_path {
+            if let Some(start) = ingest_path_start(&config.o2_endpoint) {
+                config.o2_endpoint.truncate(start);
+            }
+        }
+        
+        if let Ok(content_type) = env::var("O2_CONTENT_TYPE") {
+            config.content_type = content_type;
+        }
+        
+        if let Ok(no_write) = env::var("O2_HEALTH_CHECK_NO_WRITE") {
+            config.health_check_no_write = no_write.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_HEALTH_CHECK_NO_WRITE: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(health_path) = env::var("O2_HEALTH_PATH") {
+            let health_path = health_path.trim();
+            if !health_path.is_empty() {
+                config.health_path = Some(health_path.to_string());
+            }
+        }
+        
+        if let Ok(checksum) = env::var("O2_CHECKSUM") {
+            config.checksum = checksum.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_CHECKSUM: must be true or false".to_string()))?;
+        }
+        
         // Performance tuning variables
         if let Ok(max_buffer_size) = env::var("O2_MAX_BUFFER_SIZE_MB") {
             config.max_buffer_size_mb = max_buffer_size.parse()
-                .map_err(|_| anyhow!("Invalid O2_MAX_BUFFER_SIZE_MB: must be a positive integer"))?;
+                .map_err(|_| ExtensionError::Config("Invalid O2_MAX_BUFFER_SIZE_MB: must be a positive integer".to_string()))?;
+        }
+        
+        if let Ok(target) = env::var("O2_TARGET_BATCH_BYTES") {
+            config.target_batch_bytes = target.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_TARGET_BATCH_BYTES: must be a non-negative integer".to_string()))?;
+        }
+        
+        if let Ok(max_queue_len) = env::var("O2_MAX_QUEUE_LEN") {
+            config.max_queue_len = max_queue_len.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_MAX_QUEUE_LEN: must be a positive integer".to_string()))?;
+        }
+        
+        if let Ok(max_errors) = env::var("O2_NEXT_EVENT_MAX_ERRORS") {
+            config.next_event_max_errors = max_errors.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_NEXT_EVENT_MAX_ERRORS: must be a non-negative integer".to_string()))?;
+        }
+        
+        if let Ok(max_recent_invocations) = env::var("O2_MAX_RECENT_INVOCATIONS") {
+            config.max_recent_invocations = max_recent_invocations.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_MAX_RECENT_INVOCATIONS: must be a positive integer".to_string()))?;
         }
         
         if let Ok(request_timeout) = env::var("O2_REQUEST_TIMEOUT_MS") {
             config.request_timeout_ms = request_timeout.parse()
-                .map_err(|_| anyhow!("Invalid O2_REQUEST_TIMEOUT_MS: must be a positive integer"))?;
+                .map_err(|_| ExtensionError::Config("Invalid O2_REQUEST_TIMEOUT_MS: must be a positive integer".to_string()))?;
+        }
+        
+        if let Ok(timing_detail) = env::var("O2_TIMING_DETAIL") {
+            config.timing_detail = timing_detail.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_TIMING_DETAIL: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(max_url_len) = env::var("O2_MAX_URL_LEN") {
+            config.max_url_len = max_url_len.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_MAX_URL_LEN: must be a positive integer".to_string()))?;
+        }
+        
+        if let Ok(http_version) = env::var("O2_HTTP_VERSION") {
+            config.http_version = match http_version.trim().to_ascii_lowercase().as_str() {
+                "auto" => HttpVersion::Auto,
+                "http1" => HttpVersion::Http1,
+                "http2" => HttpVersion::Http2,
+                _ => return Err(ExtensionError::Config("Invalid O2_HTTP_VERSION: must be auto, http1 or http2".to_string())),
+            };
+        }
+        
+        if let Ok(ip_version) = env::var("O2_IP_VERSION") {
+            config.ip_version = match ip_version.trim().to_ascii_lowercase().as_str() {
+                "auto" => IpVersion::Auto,
+                "v4" => IpVersion::V4,
+                "v6" => IpVersion::V6,
+                _ => return Err(ExtensionError::Config("Invalid O2_IP_VERSION: must be auto, v4 or v6".to_string())),
+            };
+        }
+        
+        if let Ok(tls_min_version) = env::var("O2_TLS_MIN_VERSION") {
+            config.tls_min_version = match tls_min_version.trim() {
+                "1.2" => TlsVersion::Tls12,
+                "1.3" => TlsVersion::Tls13,
+                _ => return Err(ExtensionError::Config("Invalid O2_TLS_MIN_VERSION: must be 1.2 or 1.3".to_string())),
+            };
+        }
+        
+        if let Ok(keepalive_idle) = env::var("O2_KEEPALIVE_IDLE_MS") {
+            config.keepalive_idle_ms = keepalive_idle.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_KEEPALIVE_IDLE_MS: must be a non-negative integer".to_string()))?;
+        }
+        
+        if let Ok(pool_size) = env::var("O2_POOL_MAX_IDLE_PER_HOST") {
+            config.pool_max_idle_per_host = pool_size.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_POOL_MAX_IDLE_PER_HOST: must be a non-negative integer".to_string()))?;
+        }
+        
+        if let Ok(max_body) = env::var("O2_HTTP_MAX_BODY_BYTES") {
+            config.http_max_body_bytes = max_body.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_HTTP_MAX_BODY_BYTES: must be a non-negative integer".to_string()))?;
+        }
+        
+        if let Ok(max_batches) = env::var("O2_MAX_BATCHES_PER_FLUSH") {
+            config.max_batches_per_flush = max_batches.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_MAX_BATCHES_PER_FLUSH: must be a non-negative integer".to_string()))?;
+        }
+        
+        if let Ok(flush_interval) = env::var("O2_CONTINUOUS_FLUSH_INTERVAL_MS") {
+            config.continuous_flush_interval_ms = flush_interval.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_CONTINUOUS_FLUSH_INTERVAL_MS: must be a positive integer".to_string()))?;
+        }
+        
+        if let Ok(min_batch_age) = env::var("O2_MIN_BATCH_AGE_MS") {
+            config.min_batch_age_ms = min_batch_age.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_MIN_BATCH_AGE_MS: must be a non-negative integer".to_string()))?;
         }
         
         // Retry configuration
         if let Ok(max_retries) = env::var("O2_MAX_RETRIES") {
             config.max_retries = max_retries.parse()
-                .map_err(|_| anyhow!("Invalid O2_MAX_RETRIES: must be a positive integer"))?;
+                .map_err(|_| ExtensionError::Config("Invalid O2_MAX_RETRIES: must be a positive integer".to_string()))?;
+        }
+        
+        if let Ok(precheck) = env::var("O2_SHUTDOWN_PRECHECK") {
+            config.shutdown_precheck = precheck.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_SHUTDOWN_PRECHECK: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(max_retries) = env::var("O2_SHUTDOWN_MAX_RETRIES") {
+            config.shutdown_max_retries = max_retries.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_SHUTDOWN_MAX_RETRIES: must be a non-negative integer".to_string()))?;
         }
         
         if let Ok(initial_delay) = env::var("O2_INITIAL_RETRY_DELAY_MS") {
             config.initial_retry_delay_ms = initial_delay.parse()
-                .map_err(|_| anyhow!("Invalid O2_INITIAL_RETRY_DELAY_MS: must be a positive integer"))?;
+                .map_err(|_| ExtensionError::Config("Invalid O2_INITIAL_RETRY_DELAY_MS: must be a positive integer".to_string()))?;
         }
         
         if let Ok(max_delay) = env::var("O2_MAX_RETRY_DELAY_MS") {
             config.max_retry_delay_ms = max_delay.parse()
-                .map_err(|_| anyhow!("Invalid O2_MAX_RETRY_DELAY_MS: must be a positive integer"))?;
+                .map_err(|_| ExtensionError::Config("Invalid O2_MAX_RETRY_DELAY_MS: must be a positive integer".to_string()))?;
         }
         
-        // Validate configuration
-        config.validate()?;
+        if let Ok(statuses) = env::var("O2_RETRYABLE_STATUSES") {
+            config.retryable_statuses = statuses
+                .split(',')
+                .map(str::trim)
+                .filter(|status| !status.is_empty())
+                .map(|status| status.parse::<u16>())
+                .collect::<std::result::Result<_, _>>()
+                .map_err(|_| ExtensionError::Config("Invalid O2_RETRYABLE_STATUSES: must be a comma-separated list of HTTP status codes".to_string()))?;
+        }
+        
+        if let Ok(requeue_rejected) = env::var("O2_REQUEUE_REJECTED") {
+            config.requeue_rejected = requeue_rejected.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_REQUEUE_REJECTED: must be true or false".to_string()))?;
+        }
+        
+        // Alerting
+        if let Ok(webhook) = env::var("O2_ALERT_WEBHOOK") {
+            if !webhook.trim().is_empty() {
+                config.alert_webhook = Some(webhook);
+            }
+        }
+        
+        // Telemetry subscription health
+        if let Ok(resubscribe_window) = env::var("O2_TELEMETRY_RESUBSCRIBE_WINDOW_MS") {
+            config.telemetry_resubscribe_window_ms = resubscribe_window.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_TELEMETRY_RESUBSCRIBE_WINDOW_MS: must be a non-negative integer".to_string()))?;
+        }
+        
+        // Memory pressure flushing
+        if let Ok(percent) = env::var("O2_BUFFER_WARN_PERCENT") {
+            config.buffer_warn_percent = percent.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_BUFFER_WARN_PERCENT: must be a non-negative integer".to_string()))?;
+        }
+        
+        if let Ok(flush_strategy) = env::var("O2_FLUSH_STRATEGY") {
+            config.flush_strategy = match flush_strategy.trim().to_ascii_lowercase().as_str() {
+                "auto" => FlushStrategy::Auto,
+                "end_of_invocation" => FlushStrategy::EndOfInvocation,
+                "continuous" => FlushStrategy::Continuous,
+                "periodic" => FlushStrategy::Periodic,
+                _ => return Err(ExtensionError::Config("Invalid O2_FLUSH_STRATEGY: must be auto, end_of_invocation, continuous or periodic".to_string())),
+            };
+        }
+        
+        if let Ok(flush_only_on_shutdown) = env::var("O2_FLUSH_ONLY_ON_SHUTDOWN") {
+            config.flush_only_on_shutdown = flush_only_on_shutdown.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_FLUSH_ONLY_ON_SHUTDOWN: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(async_invoke_flush) = env::var("O2_ASYNC_INVOKE_FLUSH") {
+            config.async_invoke_flush = async_invoke_flush.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_ASYNC_INVOKE_FLUSH: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(min_interval) = env::var("O2_MIN_FLUSH_INTERVAL_MS") {
+            config.min_flush_interval_ms = min_interval.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_MIN_FLUSH_INTERVAL_MS: must be a non-negative integer".to_string()))?;
+        }
+        
+        if let Ok(freeze_gap) = env::var("O2_FREEZE_GAP_SECS") {
+            config.freeze_gap_secs = freeze_gap.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_FREEZE_GAP_SECS: must be a non-negative integer".to_string()))?;
+        }
+        
+        if let Ok(wall_clock_flush) = env::var("O2_WALL_CLOCK_FLUSH_MS") {
+            config.wall_clock_flush_ms = wall_clock_flush.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_WALL_CLOCK_FLUSH_MS: must be a non-negative integer".to_string()))?;
+        }
+        
+        if let Ok(shrink_idle) = env::var("O2_BUFFER_SHRINK_IDLE_MS") {
+            config.buffer_shrink_idle_ms = shrink_idle.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_BUFFER_SHRINK_IDLE_MS: must be a non-negative integer".to_string()))?;
+        }
+        
+        if let Ok(percent) = env::var("O2_MEMORY_FLUSH_PERCENT") {
+            config.memory_flush_percent = percent.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_MEMORY_FLUSH_PERCENT: must be a non-negative integer".to_string()))?;
+        }
+        
+        if let Ok(types) = env::var("O2_FLUSH_ON_TYPES") {
+            config.flush_on_types = types
+                .split(',')
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        
+        if let Ok(types) = env::var("O2_COUNT_ONLY_TYPES") {
+            config.count_only_types = types
+                .split(',')
+                .map(str::trim)
+                .filter(|event_type| !event_type.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        
+        // Set by the Lambda runtime
+        config.function_memory_size_mb = env::var("AWS_LAMBDA_FUNCTION_MEMORY_SIZE")
+            .ok()
+            .and_then(|size| size.trim().parse().ok());
+        // on-demand, provisioned-concurrency or snap-start
+        config.init_type = env::var("AWS_LAMBDA_INITIALIZATION_TYPE")
+            .ok()
+            .map(|init_type| init_type.trim().to_string())
+            .filter(|init_type| !init_type.is_empty());
+        config.aws_region = env::var("AWS_REGION").ok().filter(|region| !region.is_empty());
+        config.function_name = env::var("AWS_LAMBDA_FUNCTION_NAME").ok().filter(|name| !name.is_empty());
+        
+        if let Ok(include_arn) = env::var("O2_INCLUDE_ARN") {
+            config.include_arn = include_arn.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_INCLUDE_ARN: must be true or false".to_string()))?;
+        }
+        
+        // Event filtering
+        if let Ok(drop_platform) = env::var("O2_DROP_PLATFORM") {
+            config.drop_platform_events = drop_platform.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_DROP_PLATFORM: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(patterns) = env::var("O2_EXCLUDE_PATTERNS") {
+            config.exclude_patterns = parse_exclude_patterns(&patterns);
+        }
+        
+        if let Ok(merge_multiline) = env::var("O2_MERGE_MULTILINE") {
+            config.merge_multiline = merge_multiline.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_MERGE_MULTILINE: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(pattern) = env::var("O2_MULTILINE_PATTERN") {
+            if !pattern.is_empty() {
+                config.multiline_pattern = pattern;
+            }
+        }
+        
+        // Record shaping
+        if let Ok(timestamp_field) = env::var("O2_TIMESTAMP_FIELD") {
+            if !timestamp_field.trim().is_empty() {
+                config.timestamp_field = Some(timestamp_field);
+            }
+        }
+        
+        if let Ok(timestamp_unit) = env::var("O2_TIMESTAMP_UNIT") {
+            config.timestamp_unit = TimestampUnit::parse(&timestamp_unit)
+                .ok_or_else(|| ExtensionError::Config("Invalid O2_TIMESTAMP_UNIT: must be one of seconds, millis, micros, nanos".to_string()))?;
+        }
+        
+        if let Ok(timestamp_format) = env::var("O2_TIMESTAMP_FORMAT") {
+            config.timestamp_format = match timestamp_format.trim().to_ascii_lowercase().as_str() {
+                "epoch" => TimestampFormat::Epoch,
+                "rfc3339" => TimestampFormat::Rfc3339,
+                _ => return Err(ExtensionError::Config("Invalid O2_TIMESTAMP_FORMAT: must be epoch or rfc3339".to_string())),
+            };
+        }
+        
+        if let Ok(encoding) = env::var("O2_OUTPUT_ENCODING") {
+            config.output_encoding = match encoding.trim().to_ascii_lowercase().as_str() {
+                "json" => OutputEncoding::Json,
+                "csv" => OutputEncoding::Csv,
+                _ => return Err(ExtensionError::Config("Invalid O2_OUTPUT_ENCODING: must be json or csv".to_string())),
+            };
+            // CSV bodies need a matching default content type
+            if config.output_encoding == OutputEncoding::Csv && env::var("O2_CONTENT_TYPE").is_err() {
+                config.content_type = "text/csv".to_string();
+            }
+        }
+        
+        // Only affects batches printed by --parse-file; what's sent stays compact
+        if let Ok(pretty) = env::var("O2_PRETTY_JSON") {
+            config.pretty_json = pretty.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_PRETTY_JSON: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(columns) = env::var("O2_CSV_COLUMNS") {
+            config.csv_columns = columns
+                .split(',')
+                .map(str::trim)
+                .filter(|column| !column.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        
+        if let Ok(renames) = env::var("O2_FIELD_RENAMES") {
+            config.field_renames = parse_field_renames(&renames)
+                .ok_or_else(|| ExtensionError::Config("Invalid O2_FIELD_RENAMES: must be a comma-separated list of from:to pairs".to_string()))?;
+        }
+        
+        if let Ok(fields) = env::var("O2_INCLUDE_FIELDS") {
+            config.include_fields = fields
+                .split(',')
+                .map(str::trim)
+                .filter(|field| !field.is_empty())
+                .map(str::to_string)
+                .collect();
+        }
+        
+        if let Ok(type_map) = env::var("O2_TYPE_MAP") {
+            config.type_map = parse_field_renames(&type_map)
+                .ok_or_else(|| ExtensionError::Config("Invalid O2_TYPE_MAP: must be a comma-separated list of from:to pairs".to_string()))?;
+        }
+        
+        if let Ok(record_field) = env::var("O2_RECORD_AS_MESSAGE_FIELD") {
+            let record_field = record_field.trim();
+            if !record_field.is_empty() {
+                config.record_field = Some(record_field.to_string());
+            }
+        }
+        
+        if let Ok(template) = env::var("O2_EVENT_TEMPLATE") {
+            let template = template.trim();
+            if !template.is_empty() {
+                config.event_template = Some(template.to_string());
+            }
+        }
+        
+        if let Ok(max_depth) = env::var("O2_MAX_RECORD_DEPTH") {
+            config.max_record_depth = max_depth.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_MAX_RECORD_DEPTH: must be a non-negative integer".to_string()))?;
+        }
+        
+        if let Ok(include_instance_id) = env::var("O2_INCLUDE_INSTANCE_ID") {
+            config.include_instance_id = include_instance_id.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_INCLUDE_INSTANCE_ID: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(tag_version) = env::var("O2_TAG_VERSION") {
+            config.tag_version = tag_version.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_TAG_VERSION: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(batch_by_request_id) = env::var("O2_BATCH_BY_REQUEST_ID") {
+            config.batch_by_request_id = batch_by_request_id.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_BATCH_BY_REQUEST_ID: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(immediate_forward) = env::var("O2_IMMEDIATE_FORWARD") {
+            config.immediate_forward = immediate_forward.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_IMMEDIATE_FORWARD: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(callback_host) = env::var("O2_TELEMETRY_CALLBACK_HOST") {
+            let callback_host = callback_host.trim();
+            if !callback_host.is_empty() {
+                config.telemetry_callback_host = callback_host.to_string();
+            }
+        }
+        
+        if let Ok(concurrency) = env::var("O2_TELEMETRY_CONCURRENCY") {
+            config.telemetry_concurrency = concurrency.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_TELEMETRY_CONCURRENCY: must be a non-negative integer".to_string()))?;
+        }
+        
+        if let Ok(dedup) = env::var("O2_TELEMETRY_DEDUP") {
+            config.telemetry_dedup = dedup.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_TELEMETRY_DEDUP: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(propagate_xray) = env::var("O2_PROPAGATE_XRAY") {
+            config.propagate_xray = propagate_xray.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_PROPAGATE_XRAY: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(track_lag) = env::var("O2_TRACK_LAG") {
+            config.track_lag = track_lag.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_TRACK_LAG: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(preserve_order) = env::var("O2_PRESERVE_ORDER") {
+            config.preserve_order = preserve_order.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_PRESERVE_ORDER: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(emit_request_id) = env::var("O2_EMIT_REQUEST_ID") {
+            config.emit_request_id = emit_request_id.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_EMIT_REQUEST_ID: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(include_raw) = env::var("O2_INCLUDE_RAW") {
+            config.include_raw = include_raw.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_INCLUDE_RAW: must be true or false".to_string()))?;
+        }
+        
+        // Platform metrics export
+        if let Ok(export) = env::var("O2_METRICS_EXPORT") {
+            config.metrics_export = match export.trim().to_ascii_lowercase().as_str() {
+                "" | "none" => MetricsExport::None,
+                "prometheus" => MetricsExport::Prometheus,
+                _ => return Err(ExtensionError::Config("Invalid O2_METRICS_EXPORT: must be none or prometheus".to_string())),
+            };
+        }
+        
+        if let Ok(metrics_endpoint) = env::var("O2_METRICS_ENDPOINT") {
+            if !metrics_endpoint.trim().is_empty() {
+                config.metrics_endpoint = Some(metrics_endpoint);
+            }
+        }
+        
+        // Lifecycle events
+        if let Ok(emit_startup) = env::var("O2_EMIT_STARTUP_EVENT") {
+            config.emit_startup_event = emit_startup.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_EMIT_STARTUP_EVENT: must be true or false".to_string()))?;
+        }
+        
+        if let Ok(interval) = env::var("O2_SELF_METRICS_INTERVAL_MS") {
+            config.self_metrics_interval_ms = interval.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_SELF_METRICS_INTERVAL_MS: must be a non-negative integer".to_string()))?;
+        }
+        
+        if let Ok(interval) = env::var("O2_STATS_INTERVAL_SECS") {
+            config.stats_interval_secs = interval.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_STATS_INTERVAL_SECS: must be a non-negative integer".to_string()))?;
+        }
+        
+        if let Ok(sample) = env::var("O2_SELF_LOG_SAMPLE") {
+            config.self_log_sample = sample.trim().parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_SELF_LOG_SAMPLE: must be a positive integer".to_string()))?;
+        }
+        
+        if let Ok(grace) = env::var("O2_SHUTDOWN_GRACE_MS") {
+            config.shutdown_grace_ms = grace.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_SHUTDOWN_GRACE_MS: must be a non-negative integer".to_string()))?;
+        }
+        
+        if let Ok(first_event_timeout) = env::var("O2_FIRST_EVENT_TIMEOUT_MS") {
+            config.first_event_timeout_ms = first_event_timeout.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_FIRST_EVENT_TIMEOUT_MS: must be a non-negative integer".to_string()))?;
+        }
+        
+        // Artificial per-invoke delay, only useful when exercising the extension locally
+        if let Ok(delay) = env::var("O2_SIMULATED_INVOKE_DELAY_MS") {
+            config.simulated_invoke_delay_ms = delay.parse()
+                .map_err(|_| ExtensionError::Config("Invalid O2_SIMULATED_INVOKE_DELAY_MS: must be a non-negative integer".to_string()))?;
+        }
         
         Ok(config)
     }
@@ -96,51 +942,363 @@ impl Config {
     pub fn validate(&self) -> Result<()> {
         // Validate endpoint URL
         Url::parse(&self.o2_endpoint)
-            .map_err(|e| anyhow!("Invalid O2_ENDPOINT URL: {}", e))?;
+            .map_err(|e| ExtensionError::Config(format!("Invalid O2_ENDPOINT URL: {}", e)))?;
+        
+        // The ingest path is appended to O2_ENDPOINT, so a full ingest URL would get it twice
+        if ingest_path_start(&self.o2_endpoint).is_some() {
+            return Err(ExtensionError::Config(format!(
+                "O2_ENDPOINT {:?} looks like a full ingest URL; set it to the base URL only (e.g. https://api.openobserve.ai), \
+                 the extension appends {} itself, or set O2_STRIP_INGEST_PATH=true to drop the extra path",
+                self.o2_endpoint, self.o2_path_template
+            )));
+        }
         
         // Validate organization ID is not empty
         if self.o2_organization_id.trim().is_empty() {
-            return Err(anyhow!("O2_ORGANIZATION_ID cannot be empty"));
+            return Err(ExtensionError::Config("O2_ORGANIZATION_ID cannot be empty".to_string()));
         }
         
         // Validate stream name is not empty
         if self.o2_stream.trim().is_empty() {
-            return Err(anyhow!("O2_STREAM cannot be empty"));
+            return Err(ExtensionError::Config("O2_STREAM cannot be empty".to_string()));
+        }
+        
+        if let Some(mirror_endpoint) = &self.mirror_endpoint {
+            Url::parse(mirror_endpoint)
+                .map_err(|e| ExtensionError::Config(format!("Invalid O2_MIRROR_ENDPOINT URL: {}", e)))?;
+            // Copies only go out with the primary's credentials when that is asked for
+            if self.mirror_authorization_header.is_none() && !self.mirror_use_primary_auth {
+                return Err(ExtensionError::Config(
+                    "O2_MIRROR_ENDPOINT requires O2_MIRROR_AUTHORIZATION_HEADER or O2_MIRROR_USE_PRIMARY_AUTH=true".to_string(),
+                ));
+            }
+        }
+        
+        for pattern in &self.exclude_patterns {
+            regex::Regex::new(pattern)
+                .map_err(|e| ExtensionError::Config(format!("Invalid O2_EXCLUDE_PATTERNS entry {:?}: {}", pattern, e)))?;
+        }
+        
+        if self.merge_multiline {
+            regex::Regex::new(&self.multiline_pattern)
+                .map_err(|e| ExtensionError::Config(format!("Invalid O2_MULTILINE_PATTERN {:?}: {}", self.multiline_pattern, e)))?;
+        }
+        
+        if let Some(field) = self.record_field.as_deref().filter(|field| ENVELOPE_FIELDS.contains(field)) {
+            return Err(ExtensionError::Config(format!("O2_RECORD_AS_MESSAGE_FIELD cannot be {:?}, the extension already writes that field", field)));
+        }
+        
+        // Goes into the subscription's http://host:port URI, so no ports, paths or bare IPv6
+        url::Host::parse(&self.telemetry_callback_host)
+            .map_err(|e| ExtensionError::Config(format!("Invalid O2_TELEMETRY_CALLBACK_HOST {:?}: {}", self.telemetry_callback_host, e)))?;
+        
+        if let Some(template) = &self.event_template {
+            parse_event_template(template)
+                .map_err(|e| ExtensionError::Config(format!("Invalid O2_EVENT_TEMPLATE: {}", e)))?;
+            if self.output_encoding == OutputEncoding::Csv {
+                return Err(ExtensionError::Config("O2_EVENT_TEMPLATE is not supported with O2_OUTPUT_ENCODING=csv".to_string()));
+            }
+        }
+        
+        if self.output_encoding == OutputEncoding::Csv {
+            if self.csv_columns.is_empty() {
+                return Err(ExtensionError::Config("O2_CSV_COLUMNS is required when O2_OUTPUT_ENCODING=csv".to_string()));
+            }
+            // Both rework batches as JSON
+            if self.track_lag || self.http_max_body_bytes > 0 {
+                return Err(ExtensionError::Config("O2_TRACK_LAG and O2_HTTP_MAX_BODY_BYTES are not supported with O2_OUTPUT_ENCODING=csv".to_string()));
+            }
+        }
+        
+        // Re-queuing puts the whole batch back, so it must be the body that was rejected
+        if self.requeue_rejected && (self.output_encoding == OutputEncoding::Csv || self.http_max_body_bytes > 0) {
+            return Err(ExtensionError::Config("O2_REQUEUE_REJECTED is not supported with O2_OUTPUT_ENCODING=csv or O2_HTTP_MAX_BODY_BYTES".to_string()));
+        }
+        
+        if let Some(health_path) = self.health_path.as_deref().filter(|path| !path.starts_with('/')) {
+            return Err(ExtensionError::Config(format!("O2_HEALTH_PATH {:?} must start with /", health_path)));
+        }
+        
+        // Validate the ingest path can address the org and stream
+        if !self.o2_path_template.contains("{org}") || !self.o2_path_template.contains("{stream}") {
+            return Err(ExtensionError::Config("O2_PATH_TEMPLATE must contain {org} and {stream} placeholders".to_string()));
+        }
+        
+        // Validate the metrics destination when exporting
+        if self.metrics_export == MetricsExport::Prometheus {
+            let endpoint = self.metrics_endpoint.as_deref()
+                .ok_or_else(|| ExtensionError::Config("O2_METRICS_ENDPOINT is required when O2_METRICS_EXPORT=prometheus".to_string()))?;
+            Url::parse(endpoint)
+                .map_err(|e| ExtensionError::Config(format!("Invalid O2_METRICS_ENDPOINT URL: {}", e)))?;
+        }
+        
+        if let Some(webhook) = &self.alert_webhook {
+            Url::parse(webhook)
+                .map_err(|e| ExtensionError::Config(format!("Invalid O2_ALERT_WEBHOOK URL: {}", e)))?;
         }
         
         // Validate authorization header is not empty
-        if self.o2_authorization_header.trim().is_empty() {
-            return Err(anyhow!("O2_AUTHORIZATION_HEADER cannot be empty"));
+        if self.auth_mode == AuthMode::Header && self.o2_authorization_header.trim().is_empty() {
+            return Err(ExtensionError::Config("O2_AUTHORIZATION_HEADER cannot be empty".to_string()));
+        }
+        
+        // Validate content type is not empty
+        if self.content_type.trim().is_empty() {
+            return Err(ExtensionError::Config("O2_CONTENT_TYPE cannot be empty".to_string()));
         }
         
         // Validate numeric constraints
         
         if self.max_buffer_size_mb == 0 {
-            return Err(anyhow!("O2_MAX_BUFFER_SIZE_MB must be greater than 0"));
+            return Err(ExtensionError::Config("O2_MAX_BUFFER_SIZE_MB must be greater than 0".to_string()));
+        }
+        
+        if self.target_batch_bytes > self.max_buffer_size_bytes() {
+            return Err(ExtensionError::Config("O2_TARGET_BATCH_BYTES must not exceed O2_MAX_BUFFER_SIZE_MB".to_string()));
+        }
+        
+        if self.max_queue_len == 0 {
+            return Err(ExtensionError::Config("O2_MAX_QUEUE_LEN must be greater than 0".to_string()));
+        }
+        
+        if self.self_log_sample == 0 {
+            return Err(ExtensionError::Config("O2_SELF_LOG_SAMPLE must be greater than 0".to_string()));
+        }
+        
+        if self.max_recent_invocations == 0 {
+            return Err(ExtensionError::Config("O2_MAX_RECENT_INVOCATIONS must be greater than 0".to_string()));
         }
         
         if self.request_timeout_ms == 0 {
-            return Err(anyhow!("O2_REQUEST_TIMEOUT_MS must be greater than 0"));
+            return Err(ExtensionError::Config("O2_REQUEST_TIMEOUT_MS must be greater than 0".to_string()));
+        }
+        
+        if self.continuous_flush_interval_ms == 0 {
+            return Err(ExtensionError::Config("O2_CONTINUOUS_FLUSH_INTERVAL_MS must be greater than 0".to_string()));
+        }
+        
+        if self.buffer_warn_percent > 100 {
+            return Err(ExtensionError::Config("O2_BUFFER_WARN_PERCENT must be between 0 and 100".to_string()));
+        }
+        
+        if self.memory_flush_percent > 100 {
+            return Err(ExtensionError::Config("O2_MEMORY_FLUSH_PERCENT must be between 0 and 100".to_string()));
         }
         
         if self.initial_retry_delay_ms > self.max_retry_delay_ms {
-            return Err(anyhow!("O2_INITIAL_RETRY_DELAY_MS cannot be greater than O2_MAX_RETRY_DELAY_MS"));
+            return Err(ExtensionError::Config("O2_INITIAL_RETRY_DELAY_MS cannot be greater than O2_MAX_RETRY_DELAY_MS".to_string()));
+        }
+        
+        if let Some(status) = self.retryable_statuses.iter().find(|status| !(100..=599).contains(*status)) {
+            return Err(ExtensionError::Config(format!("O2_RETRYABLE_STATUSES contains invalid HTTP status {}", status)));
+        }
+        
+        // Proxies commonly answer 414 for overly long request lines
+        let url_len = self.openobserve_url().len();
+        if url_len > self.max_url_len {
+            return Err(ExtensionError::Config(format!(
+                "Ingest URL is {} characters, over O2_MAX_URL_LEN ({}); shorten O2_ORGANIZATION_ID or O2_STREAM, or raise O2_MAX_URL_LEN",
+                url_len, self.max_url_len
+            )));
         }
         
         Ok(())
     }
     
     pub fn openobserve_url(&self) -> String {
-        format!("{}/api/{}/{}/_json", 
-            self.o2_endpoint, 
-            self.o2_organization_id, 
-            self.o2_stream
-        )
+        let path = self.o2_path_template
+            .replace("{org}", &self.o2_organization_id)
+            .replace("{stream}", &crate::sigv4::uri_encode(&self.o2_stream));
+        self.endpoint_url(&path)
+    }
+    
+    // URL of `path` on the configured endpoint
+    pub fn endpoint_url(&self, path: &str) -> String {
+        if self.unix_socket_path().is_some() {
+            // Requests are carried over the socket, so the host only fills the Host header
+            return format!("http://localhost{}", path);
+        }
+        format!("{}{}", self.o2_endpoint, path)
+    }
+    
+    // Same settings aimed at the mirror destination, if one is configured. The mirror sends
+    // O2_MIRROR_AUTHORIZATION_HEADER in header mode when it is set; otherwise it takes the
+    // primary's auth mode and credentials only with O2_MIRROR_USE_PRIMARY_AUTH, and sends an
+    // empty header if neither is given (validate rejects that).
+    pub fn mirror_config(&self) -> Option<Config> {
+        let endpoint = self.mirror_endpoint.clone()?;
+        let (auth_mode, authorization_header) = match &self.mirror_authorization_header {
+            Some(header) => (AuthMode::Header, header.clone()),
+            None if self.mirror_use_primary_auth => (self.auth_mode, self.o2_authorization_header.clone()),
+            None => (AuthMode::Header, String::new()),
+        };
+        Some(Config {
+            o2_endpoint: endpoint,
+            o2_authorization_header: authorization_header,
+            auth_mode,
+            mirror_endpoint: None,
+            // A copy is best-effort, so don't hold up the flush retrying it
+            max_retries: 0,
+            ..self.clone()
+        })
+    }
+    
+    // Settings for the SHUTDOWN flush, which retries less and isn't rate-limited to stay
+    // within the deadline
+    pub fn shutdown_config(&self) -> Config {
+        Config {
+            max_retries: self.shutdown_max_retries,
+            min_flush_interval_ms: 0,
+            ..self.clone()
+        }
+    }
+    
+    // The function's unqualified ARN, from the runtime's region and function name and the account
+    // ID returned at registration. None if any of them is missing.
+    pub fn function_arn(&self, account_id: Option<&str>) -> Option<String> {
+        let region = self.aws_region.as_deref()?;
+        let function_name = self.function_name.as_deref()?;
+        let account_id = account_id.filter(|id| !id.is_empty())?;
+        let partition = if region.starts_with("cn-") {
+            "aws-cn"
+        } else if region.starts_with("us-gov-") {
+            "aws-us-gov"
+        } else {
+            "aws"
+        };
+        Some(format!("arn:{partition}:lambda:{region}:{account_id}:function:{function_name}"))
+    }
+    
+    // Socket path when O2_ENDPOINT is a unix:///path/to.sock URL
+    pub fn unix_socket_path(&self) -> Option<&str> {
+        self.o2_endpoint.strip_prefix("unix://")
     }
     
     pub fn max_buffer_size_bytes(&self) -> usize {
         self.max_buffer_size_mb * 1024 * 1024
     }
+    
+    // Memory used (MB) above which a flush is forced, if enabled and the limit is known
+    pub fn memory_flush_threshold_mb(&self) -> Option<f64> {
+        if self.memory_flush_percent == 0 {
+            return None;
+        }
+        self.function_memory_size_mb
+            .map(|size| size as f64 * self.memory_flush_percent as f64 / 100.0)
+    }
+}
+
+fn auth_mode_from_env() -> Result<AuthMode> {
+    match env::var("O2_AUTH_MODE") {
+        Ok(mode) => match mode.trim().to_ascii_lowercase().as_str() {
+            "header" => Ok(AuthMode::Header),
+            "sigv4" => Ok(AuthMode::SigV4),
+            _ => Err(ExtensionError::Config("Invalid O2_AUTH_MODE: must be header or sigv4".to_string())),
+        },
+        Err(_) => Ok(AuthMode::Header),
+    }
+}
+
+// Read an authorization header value from a mounted file, dropping the trailing newline
+fn read_authorization_header_file(path: &str) -> Result<String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|e| ExtensionError::Config(format!("Failed to read O2_AUTHORIZATION_HEADER_FILE {}: {}", path, e)))?;
+    Ok(contents.trim_end_matches(['\r', '\n']).to_string())
+}
+
+// Parse an O2_EVENT_TEMPLATE into a JSON object whose strings may hold `$name` placeholders.
+// A bare placeholder (`"msg": $record`) is quoted first so the template is valid JSON.
+pub fn parse_event_template(template: &str) -> std::result::Result<serde_json::Value, String> {
+    let mut quoted = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+    let (mut in_string, mut escaped) = (false, false);
+    while let Some(c) = chars.next() {
+        if in_string {
+            in_string = escaped || c != '"';
+            escaped = !escaped && c == '\\';
+            quoted.push(c);
+        } else if c == '$' {
+            if !chars.peek().is_some_and(|&next| is_placeholder_start(next)) {
+                return Err("a $ placeholder name must start with a letter or _".to_string());
+            }
+            quoted.push_str("\"$");
+            while let Some(&next) = chars.peek().filter(|&&next| is_placeholder_char(next)) {
+                quoted.push(next);
+                chars.next();
+            }
+            quoted.push('"');
+        } else {
+            in_string = c == '"';
+            quoted.push(c);
+        }
+    }
+    
+    match serde_json::from_str(&quoted).map_err(|e| e.to_string())? {
+        template @ serde_json::Value::Object(_) => Ok(template),
+        _ => Err("must be a JSON object".to_string()),
+    }
+}
+
+// Placeholder names start with a letter or `_`, then run on over letters, digits and `_`
+pub fn is_placeholder_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_'
+}
+
+pub fn is_placeholder_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+// Split comma-separated regexes; `\,` is a literal comma inside a pattern (e.g. `\d{2\,4}`)
+fn parse_exclude_patterns(value: &str) -> Vec<String> {
+    let mut patterns = Vec::new();
+    let mut current = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&',') => {
+                chars.next();
+                current.push(',');
+            }
+            ',' => patterns.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    patterns.push(current);
+    patterns
+        .iter()
+        .map(|pattern| pattern.trim())
+        .filter(|pattern| !pattern.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// Parse "from:to,from:to" pairs, rejecting empty keys
+fn parse_field_renames(value: &str) -> Option<Vec<(String, String)>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (from, to) = pair.split_once(':')?;
+            let (from, to) = (from.trim(), to.trim());
+            (!from.is_empty() && !to.is_empty()).then(|| (from.to_string(), to.to_string()))
+        })
+        .collect()
+}
+
+// Offset in `endpoint` where an ingest path starts: the first "/api/" in the URL path, or the
+// whole path if it ends in "/_json". None for a base URL or a unix socket.
+pub fn ingest_path_start(endpoint: &str) -> Option<usize> {
+    if endpoint.starts_with("unix://") {
+        return None;
+    }
+    let host_start = endpoint.find("://").map_or(0, |i| i + 3);
+    let path_start = host_start + endpoint[host_start..].find('/')?;
+    let path = &endpoint[path_start..];
+    if let Some(api) = path.find("/api/") {
+        return Some(path_start + api);
+    }
+    path.trim_end_matches('/').ends_with("/_json").then_some(path_start)
 }
 
 #[cfg(test)]
@@ -180,4 +1338,269 @@ mod tests {
             "https://api.openobserve.ai/api/my_org/my_stream/_json"
         );
     }
+    
+    #[test]
+    fn test_openobserve_url_encodes_stream() {
+        let mut config = Config {
+            o2_endpoint: "https://api.openobserve.ai".to_string(),
+            o2_organization_id: "my_org".to_string(),
+            o2_stream: "app/logs".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.openobserve_url(),
+            "https://api.openobserve.ai/api/my_org/app%2Flogs/_json"
+        );
+        
+        config.o2_stream = "my logs".to_string();
+        assert_eq!(
+            config.openobserve_url(),
+            "https://api.openobserve.ai/api/my_org/my%20logs/_json"
+        );
+    }
+    
+    #[test]
+    fn test_openobserve_url_unix_socket() {
+        let config = Config {
+            o2_endpoint: "unix:///tmp/collector.sock".to_string(),
+            o2_organization_id: "my_org".to_string(),
+            o2_stream: "my_stream".to_string(),
+            ..Default::default()
+        };
+        
+        assert_eq!(config.unix_socket_path(), Some("/tmp/collector.sock"));
+        assert_eq!(config.openobserve_url(), "http://localhost/api/my_org/my_stream/_json");
+    }
+    
+    #[test]
+    fn test_openobserve_url_custom_path_template() {
+        let config = Config {
+            o2_endpoint: "https://proxy.example.com".to_string(),
+            o2_organization_id: "my_org".to_string(),
+            o2_stream: "my_stream".to_string(),
+            o2_path_template: "/observe/api/{org}/{stream}/_json".to_string(),
+            ..Default::default()
+        };
+        
+        assert_eq!(
+            config.openobserve_url(),
+            "https://proxy.example.com/observe/api/my_org/my_stream/_json"
+        );
+        
+        let missing_stream = Config {
+            o2_organization_id: "my_org".to_string(),
+            o2_authorization_header: "Basic dGVzdDp0ZXN0".to_string(),
+            o2_path_template: "/api/{org}/_json".to_string(),
+            ..Default::default()
+        };
+        assert!(missing_stream.validate().is_err());
+    }
+    
+    #[test]
+    fn test_endpoint_with_ingest_path() {
+        let base = Config {
+            o2_endpoint: "https://o2.example.com/observe".to_string(),
+            o2_organization_id: "my_org".to_string(),
+            o2_authorization_header: "Basic dGVzdDp0ZXN0".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(ingest_path_start(&base.o2_endpoint), None);
+        assert!(base.validate().is_ok());
+        
+        let full = Config {
+            o2_endpoint: "https://o2.example.com/observe/api/my_org/default/_json".to_string(),
+            ..base.clone()
+        };
+        let err = full.validate().unwrap_err().to_string();
+        assert!(err.contains("base URL"), "{err}");
+        
+        // O2_STRIP_INGEST_PATH trims it back to the base URL
+        let start = ingest_path_start(&full.o2_endpoint).unwrap();
+        assert_eq!(&full.o2_endpoint[..start], "https://o2.example.com/observe");
+        assert_eq!(ingest_path_start("https://o2.example.com/my_org/default/_json/"), Some(22));
+        assert_eq!(ingest_path_start("unix:///var/api/collector.sock"), None);
+    }
+    
+    #[test]
+    fn test_retryable_statuses_validation() {
+        let mut config = Config {
+            o2_organization_id: "my_org".to_string(),
+            o2_authorization_header: "Basic dGVzdDp0ZXN0".to_string(),
+            retryable_statuses: vec![409, 425],
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+        
+        config.retryable_statuses = vec![425, 999];
+        assert!(config.validate().is_err());
+    }
+    
+    #[test]
+    fn test_max_url_len_validation() {
+        let mut config = Config {
+            o2_organization_id: "o".repeat(1000),
+            o2_authorization_header: "Basic dGVzdDp0ZXN0".to_string(),
+            max_url_len: 512,
+            ..Default::default()
+        };
+        let error = config.validate().unwrap_err().to_string();
+        assert!(error.contains("O2_MAX_URL_LEN (512)"), "{}", error);
+        assert!(error.contains("O2_ORGANIZATION_ID"), "{}", error);
+        
+        config.max_url_len = 2048;
+        assert!(config.validate().is_ok());
+    }
+    
+    #[test]
+    fn test_mirror_auth() {
+        let mut config = Config {
+            o2_organization_id: "my_org".to_string(),
+            auth_mode: AuthMode::SigV4,
+            mirror_endpoint: Some("https://mirror.example.com".to_string()),
+            ..Default::default()
+        };
+        // The primary's credentials aren't used for the mirror unless asked for
+        assert!(config.validate().is_err());
+        assert_eq!(config.mirror_config().unwrap().o2_authorization_header, "");
+        
+        config.mirror_use_primary_auth = true;
+        assert!(config.validate().is_ok());
+        assert_eq!(config.mirror_config().unwrap().auth_mode, AuthMode::SigV4);
+        
+        // Its own header always means header mode
+        config.mirror_authorization_header = Some("Basic bWlycm9y".to_string());
+        let mirror = config.mirror_config().unwrap();
+        assert_eq!(mirror.auth_mode, AuthMode::Header);
+        assert_eq!(mirror.o2_authorization_header, "Basic bWlycm9y");
+    }
+    
+    #[test]
+    fn test_record_field_validation() {
+        let mut config = Config {
+            o2_organization_id: "my_org".to_string(),
+            o2_authorization_header: "Basic dGVzdDp0ZXN0".to_string(),
+            record_field: Some("message".to_string()),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+        
+        config.record_field = Some("_timestamp".to_string());
+        assert!(config.validate().is_err());
+    }
+    
+    #[test]
+    fn test_parse_event_template() {
+        assert_eq!(
+            parse_event_template(r#"{"ts": "$time", "msg": $record, "tags": [$type, "x"]}"#),
+            Ok(serde_json::json!({"ts": "$time", "msg": "$record", "tags": ["$type", "x"]}))
+        );
+        // Dollar signs inside strings are left alone, escaped quotes included
+        assert_eq!(
+            parse_event_template(r#"{"note": "say \"$5\"", "id": $request_id}"#),
+            Ok(serde_json::json!({"note": "say \"$5\"", "id": "$request_id"}))
+        );
+        assert!(parse_event_template(r#"{"msg": $record"#).is_err());
+        assert!(parse_event_template("[$record]").is_err());
+        // Names outside strings follow the same rule as placeholders inside them
+        assert!(parse_event_template(r#"{"n": $5}"#).is_err());
+    }
+    
+    #[test]
+    fn test_telemetry_callback_host_validation() {
+        let mut config = Config {
+            o2_organization_id: "my_org".to_string(),
+            o2_authorization_header: "Basic dGVzdDp0ZXN0".to_string(),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+        
+        for host in ["127.0.0.1", "[::1]", "extension.internal"] {
+            config.telemetry_callback_host = host.to_string();
+            assert!(config.validate().is_ok(), "{host} rejected");
+        }
+        for host in ["", "::1", "localhost:8080", "http://localhost", "bad host"] {
+            config.telemetry_callback_host = host.to_string();
+            assert!(config.validate().is_err(), "{host} accepted");
+        }
+    }
+    
+    #[test]
+    fn test_function_arn() {
+        let config = Config {
+            aws_region: Some("eu-west-1".to_string()),
+            function_name: Some("checkout-api".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.function_arn(Some("123456789012")).as_deref(),
+            Some("arn:aws:lambda:eu-west-1:123456789012:function:checkout-api")
+        );
+        
+        let china = Config {
+            aws_region: Some("cn-north-1".to_string()),
+            ..config.clone()
+        };
+        assert_eq!(
+            china.function_arn(Some("123456789012")).as_deref(),
+            Some("arn:aws-cn:lambda:cn-north-1:123456789012:function:checkout-api")
+        );
+        
+        // Any missing part leaves the ARN out
+        assert_eq!(config.function_arn(None), None);
+        assert_eq!(config.function_arn(Some("")), None);
+        let unnamed = Config {
+            function_name: None,
+            ..config
+        };
+        assert_eq!(unnamed.function_arn(Some("123456789012")), None);
+    }
+    
+    #[test]
+    fn test_read_authorization_header_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"Basic dGVzdDp0ZXN0\n").unwrap();
+        
+        let path = file.path().to_str().unwrap();
+        assert_eq!(read_authorization_header_file(path).unwrap(), "Basic dGVzdDp0ZXN0");
+        
+        let missing = read_authorization_header_file("/nonexistent/o2-auth-header");
+        assert!(matches!(missing, Err(ExtensionError::Config(message)) if message.contains("O2_AUTHORIZATION_HEADER_FILE")));
+    }
+    
+    #[test]
+    fn test_parse_exclude_patterns() {
+        assert_eq!(parse_exclude_patterns("health ping, ^DEBUG"), vec!["health ping", "^DEBUG"]);
+        // An escaped comma stays in the pattern
+        let patterns = parse_exclude_patterns(r"code \d{2\,4}, ^START ,");
+        assert_eq!(patterns, vec![r"code \d{2,4}", "^START"]);
+        let config = Config {
+            o2_organization_id: "my_org".to_string(),
+            o2_authorization_header: "Basic dGVzdDp0ZXN0".to_string(),
+            exclude_patterns: patterns,
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+    }
+    
+    #[test]
+    fn test_parse_field_renames() {
+        assert_eq!(
+            parse_field_renames("type:log_type, time:log_time"),
+            Some(vec![
+                ("type".to_string(), "log_type".to_string()),
+                ("time".to_string(), "log_time".to_string()),
+            ])
+        );
+        assert_eq!(parse_field_renames("type"), None);
+        assert_eq!(parse_field_renames("type:"), None);
+    }
+    
+    #[test]
+    fn test_timestamp_unit_parse() {
+        assert_eq!(TimestampUnit::parse("seconds"), Some(TimestampUnit::Seconds));
+        assert_eq!(TimestampUnit::parse("MILLIS"), Some(TimestampUnit::Millis));
+        assert_eq!(TimestampUnit::parse("micros"), Some(TimestampUnit::Micros));
+        assert_eq!(TimestampUnit::parse("nanos"), Some(TimestampUnit::Nanos));
+        assert_eq!(TimestampUnit::parse("minutes"), None);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,74 @@
+use reqwest::StatusCode;
+use thiserror::Error;
+
+// Typed errors so callers can react to the failure kind instead of parsing strings
+#[derive(Debug, Error)]
+pub enum ExtensionError {
+    // Invalid or missing configuration
+    #[error("{0}")]
+    Config(String),
+
+    // Connection, timeout or other transport-level failure
+    #[error("Request failed: {0}")]
+    Network(#[from] reqwest::Error),
+
+    // Transport failure outside reqwest, e.g. on a Unix socket connection
+    #[error("Request failed: {0}")]
+    Transport(String),
+
+    // OpenObserve answered with a non-success status
+    #[error("OpenObserve returned status {status}: {message}")]
+    Server { status: StatusCode, message: String },
+
+    // Payload could not be encoded or decoded
+    #[error("{0}")]
+    Serialization(String),
+
+    // OpenObserve accepted the request but rejected every record in it (O2_REQUEUE_REJECTED)
+    #[error("OpenObserve rejected all {count} records: {message}")]
+    Rejected { count: u64, message: String },
+}
+
+impl ExtensionError {
+    /// Whether retrying the same request may succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            ExtensionError::Network(_) | ExtensionError::Transport(_) => true,
+            ExtensionError::Server { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            ExtensionError::Config(_) | ExtensionError::Serialization(_) | ExtensionError::Rejected { .. } => false,
+        }
+    }
+    
+    /// Like `is_retryable`, also retrying server responses with one of `extra_statuses`
+    pub fn is_retryable_with(&self, extra_statuses: &[u16]) -> bool {
+        match self {
+            ExtensionError::Server { status, .. } if extra_statuses.contains(&status.as_u16()) => true,
+            _ => self.is_retryable(),
+        }
+    }
+    
+    /// Whether the connection was reset or closed under us, typical of a stale first TLS connection
+    pub fn is_connection_reset(&self) -> bool {
+        let ExtensionError::Network(error) = self else {
+            return false;
+        };
+        
+        let mut source: Option<&(dyn std::error::Error + 'static)> = Some(error);
+        while let Some(current) = source {
+            if let Some(io_error) = current.downcast_ref::<std::io::Error>() {
+                if matches!(
+                    io_error.kind(),
+                    std::io::ErrorKind::ConnectionReset
+                        | std::io::ErrorKind::BrokenPipe
+                        | std::io::ErrorKind::ConnectionAborted
+                ) {
+                    return true;
+                }
+            }
+            source = current.source();
+        }
+        false
+    }
+}
@@ -1,15 +1,18 @@
 use anyhow::{anyhow, Result};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
-use std::sync::Arc;
+use std::collections::{HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
 use tokio::sync::Mutex;
 use std::time::{Duration, Instant};
 use tracing::{debug, info, warn};
 use tokio::time::timeout;
 
-use crate::telemetry::TelemetryAggregator;
-use crate::config::Config;
+use crate::error::ExtensionError;
+use crate::openobserve::{IngestState, SendContext};
+use crate::telemetry::{PendingBatch, TelemetryAggregator};
+use crate::config::{Config, FlushStrategy, MetricsExport, DEFAULT_MAX_RECENT_INVOCATIONS};
 
 const LAMBDA_EXTENSION_IDENTIFIER_HEADER: &str = "Lambda-Extension-Identifier";
 const LAMBDA_EXTENSION_NAME_HEADER: &str = "Lambda-Extension-Name";
@@ -20,14 +23,64 @@ const LAMBDA_EXTENSION_FEATURES: &str = "accountId";
 const HIGH_FREQUENCY_THRESHOLD: f64 = 10.0; // ≥10 invocations/minute
 const LONG_RUNNING_THRESHOLD_SECS: u64 = 30; // >30s since last invocation
 const PERIODIC_FLUSH_INTERVAL_SECS: u64 = 5; // Periodic flush every 5 seconds
+const SHUTDOWN_PRECHECK_TIMEOUT: Duration = Duration::from_millis(100);
+const NEXT_EVENT_RETRY_DELAY: Duration = Duration::from_millis(50); // Doubled per consecutive error
+const NEXT_EVENT_MAX_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Base URL of the Lambda Runtime API from AWS_LAMBDA_RUNTIME_API
+pub fn runtime_api_base_url_from_env() -> String {
+    let raw = std::env::var("AWS_LAMBDA_RUNTIME_API")
+        .unwrap_or_else(|_| "localhost:9001".to_string());
+    runtime_api_base_url(&raw)
+}
+
+/// Normalize a Runtime API address into a base URL without a trailing slash.
+/// Accepts host:port, a full URL with scheme, or an IPv6 address with or without brackets.
+pub fn runtime_api_base_url(raw: &str) -> String {
+    let raw = raw.trim().trim_end_matches('/');
+    
+    if raw.contains("://") {
+        return raw.to_string();
+    }
+    
+    // Bare IPv6 needs brackets to be usable in a URL
+    if !raw.starts_with('[') && raw.matches(':').count() > 1 {
+        if raw.parse::<std::net::Ipv6Addr>().is_ok() {
+            return format!("http://[{raw}]");
+        }
+        if let Some((host, port)) = raw.rsplit_once(':') {
+            if host.parse::<std::net::Ipv6Addr>().is_ok() && port.parse::<u16>().is_ok() {
+                return format!("http://[{host}]:{port}");
+            }
+        }
+    }
+    
+    format!("http://{raw}")
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum FlushingStrategy {
     EndOfInvocation,  // Low-frequency: <10 invocations/minute
     Continuous,       // High-frequency: ≥10 invocations/minute  
     Periodic,         // Long-running: >30s since last invocation
+    ShutdownOnly,     // O2_FLUSH_ONLY_ON_SHUTDOWN: everything waits for SHUTDOWN
+}
+
+impl FlushingStrategy {
+    /// Name sent with each flushed batch (X-O2-Flush-Strategy)
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FlushingStrategy::EndOfInvocation => "end_of_invocation",
+            FlushingStrategy::Continuous => "continuous",
+            FlushingStrategy::Periodic => "periodic",
+            FlushingStrategy::ShutdownOnly => "shutdown_only",
+        }
+    }
 }
 
+// Tag for flushes from the wall-clock timer, which runs whatever the strategy
+const WALL_CLOCK_FLUSH_TAG: &str = "wall_clock";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RegisterRequest {
     pub events: Vec<String>,
@@ -37,6 +90,9 @@ pub struct RegisterRequest {
 pub struct RegisterResponse {
     #[serde(skip)]
     pub extension_id: String,
+    // Returned because registration accepts the accountId feature
+    #[serde(rename = "accountId", default)]
+    pub account_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -48,6 +104,10 @@ pub enum NextEventResponse {
         request_id: String,
         #[serde(rename = "deadlineMs")]
         deadline_ms: u64,
+        #[serde(rename = "invokedFunctionArn", default)]
+        invoked_function_arn: Option<String>,
+        #[serde(default)]
+        tracing: Option<InvokeTracing>,
     },
     #[serde(rename = "SHUTDOWN")]
     Shutdown {
@@ -58,6 +118,12 @@ pub enum NextEventResponse {
 
 
 
+// X-Ray tracing for an invocation; `value` is the X-Amzn-Trace-Id header
+#[derive(Debug, Clone, Deserialize)]
+pub struct InvokeTracing {
+    pub value: String,
+}
+
 pub struct ExtensionClient {
     client: Client,
     extension_name: String,
@@ -71,12 +137,29 @@ pub struct ExtensionClient {
     pub current_strategy: FlushingStrategy,
     last_periodic_flush: Instant,
     continuous_flush_task: Option<tokio::task::JoinHandle<()>>,
+    continuous_flush_stop: Option<tokio::sync::watch::Sender<bool>>,
+    // Reused across flushes so pooled connections (and their TLS sessions) stay warm
+    flush_client: Option<Client>,
+    self_metrics_task: Option<tokio::task::JoinHandle<()>>,
+    wall_clock_flush_task: Option<tokio::task::JoinHandle<()>>,
+    // End-of-invocation flush running in the background (O2_ASYNC_INVOKE_FLUSH)
+    invoke_flush_task: Option<tokio::task::JoinHandle<()>>,
+    // Held for the duration of every flush so the background tasks never flush concurrently.
+    // Guards when the last flush started, for O2_MIN_FLUSH_INTERVAL_MS.
+    flush_lock: Arc<Mutex<Option<Instant>>>,
+    missing_components_warned: AtomicBool,
+    started_at: Instant,
+    // Set by whichever flush path first delivers events
+    first_flush_at: Arc<OnceLock<Instant>>,
+    // Retry backoff for the destinations flushes send to, shared by every flush path
+    ingest_state: Arc<IngestState>,
+    // X-Ray trace header of the latest INVOKE, for O2_PROPAGATE_XRAY
+    trace_id: Arc<std::sync::Mutex<Option<String>>>,
 }
 
 impl ExtensionClient {
     pub fn new(extension_name: String) -> Self {
-        let runtime_api_endpoint = std::env::var("AWS_LAMBDA_RUNTIME_API")
-            .unwrap_or_else(|_| "localhost:9001".to_string());
+        let runtime_api_endpoint = runtime_api_base_url_from_env();
         
         let now = Instant::now();
         Self {
@@ -92,20 +175,76 @@ impl ExtensionClient {
             current_strategy: FlushingStrategy::EndOfInvocation, // Start with safe default
             last_periodic_flush: now,
             continuous_flush_task: None,
+            continuous_flush_stop: None,
+            flush_client: None,
+            self_metrics_task: None,
+            wall_clock_flush_task: None,
+            invoke_flush_task: None,
+            flush_lock: Arc::new(Mutex::new(None)),
+            missing_components_warned: AtomicBool::new(false),
+            started_at: now,
+            first_flush_at: Arc::new(OnceLock::new()),
+            ingest_state: Arc::new(IngestState::default()),
+            trace_id: Arc::new(std::sync::Mutex::new(None)),
         }
     }
     
+    /// Time from startup to the first flush that delivered events, if there was one
+    pub fn time_to_first_flush(&self) -> Option<Duration> {
+        self.first_flush_at.get().map(|flushed_at| flushed_at.duration_since(self.started_at))
+    }
+    
+    // Time spent in requests to the primary destination across every flush
+    pub fn flush_timing_totals(&self) -> Option<crate::openobserve::FlushTimingTotals> {
+        self.ingest_state.primary.timings.totals()
+    }
+    
+    // Sizes of the batches flushes delivered to the primary destination
+    pub fn batch_size_percentiles(&self) -> Option<crate::openobserve::BatchSizePercentiles> {
+        self.ingest_state.primary.batch_sizes.percentiles()
+    }
+    
     pub fn set_telemetry_components(
         &mut self,
         aggregator: Arc<Mutex<TelemetryAggregator>>,
         config: Arc<Config>,
     ) {
+        match Self::build_flush_client(&config, Duration::from_millis(1900)) { // 1.9 seconds max
+            Ok(client) => self.flush_client = Some(client),
+            Err(e) => warn!("⚠️ Failed to create flush HTTP client: {}", e),
+        }
+        // Nothing to transition from, so this applies from the first invoke
+        if config.flush_only_on_shutdown {
+            self.current_strategy = FlushingStrategy::ShutdownOnly;
+        }
+        self.ingest_state = Arc::new(IngestState::new(&config));
         self.aggregator = Some(aggregator);
         self.config = Some(config);
     }
+    
+    /// HTTP client for flushes, keeping idle connections for O2_KEEPALIVE_IDLE_MS when set,
+    /// and at most O2_POOL_MAX_IDLE_PER_HOST of them
+    fn build_flush_client(config: &Config, request_timeout: Duration) -> Result<Client> {
+        crate::openobserve::client_builder(config)
+            .timeout(request_timeout)
+            .build()
+            .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))
+    }
 
     /// Determine the appropriate flushing strategy based on invocation patterns
     fn determine_flushing_strategy(&self) -> FlushingStrategy {
+        if self.config.as_ref().is_some_and(|config| config.flush_only_on_shutdown) {
+            return FlushingStrategy::ShutdownOnly;
+        }
+        
+        // A strategy pinned by O2_FLUSH_STRATEGY overrides the automatic choice
+        match self.config.as_ref().map(|config| config.flush_strategy) {
+            Some(FlushStrategy::EndOfInvocation) => return FlushingStrategy::EndOfInvocation,
+            Some(FlushStrategy::Continuous) => return FlushingStrategy::Continuous,
+            Some(FlushStrategy::Periodic) => return FlushingStrategy::Periodic,
+            Some(FlushStrategy::Auto) | None => {}
+        }
+        
         let now = Instant::now();
         
         // Check for long-running (>30s since last invocation)
@@ -149,11 +288,8 @@ impl ExtensionClient {
             // Handle strategy transitions
             match (&self.current_strategy, &new_strategy) {
                 (FlushingStrategy::Continuous, _) => {
-                    // Stop continuous flushing task
-                    if let Some(task) = self.continuous_flush_task.take() {
-                        task.abort();
-                        debug!("🛑 Stopped continuous flush task");
-                    }
+                    // Stop continuous flushing task, letting an in-flight flush complete
+                    self.signal_continuous_flush_stop();
                 },
                 (_, FlushingStrategy::Continuous) => {
                     // Start continuous flushing task
@@ -173,22 +309,54 @@ impl ExtensionClient {
         if let (Some(aggregator), Some(config)) = (self.aggregator.clone(), self.config.clone()) {
             let aggregator_clone = Arc::clone(&aggregator);
             let config_clone = Arc::clone(&config);
+            let flush_lock = Arc::clone(&self.flush_lock);
+            let first_flush_at = Arc::clone(&self.first_flush_at);
+            let ingest_state = Arc::clone(&self.ingest_state);
+            let trace_id = Arc::clone(&self.trace_id);
+            
+            let flush_interval = Duration::from_millis(config.continuous_flush_interval_ms);
+            
+            let client = Self::build_flush_client(&config, Duration::from_millis(1000))?; // 1 second timeout for async
+            
+            let (stop_tx, mut stop_rx) = tokio::sync::watch::channel(false);
             
             let task = tokio::spawn(async move {
                 debug!("🚀 Started continuous flush task");
-                let mut interval = tokio::time::interval(Duration::from_secs(PERIODIC_FLUSH_INTERVAL_SECS));
+                let mut interval = tokio::time::interval(flush_interval);
                 
                 loop {
-                    interval.tick().await;
+                    // Only stop between flushes so an in-flight request is never cut off
+                    tokio::select! {
+                        _ = interval.tick() => {},
+                        _ = stop_rx.changed() => break,
+                    }
+                    
+                    // Nothing buffered - skip the flush (and HTTP client setup) entirely
+                    {
+                        let mut guard = aggregator_clone.lock().await;
+                        if guard.is_empty() {
+                            guard.shrink_if_idle();
+                            continue;
+                        }
+                    }
                     
                     // Try to flush with a short timeout to avoid blocking
+                    let mut last_flush_at = flush_lock.lock().await;
+                    Self::wait_for_min_flush_interval(&mut last_flush_at, &aggregator_clone, &config_clone).await;
+                    let current_trace_id = Self::current_trace_id(&trace_id);
                     let flush_result = timeout(
                         Duration::from_millis(500), // 500ms timeout for async flush
-                        Self::flush_telemetry_async(&aggregator_clone, &config_clone)
+                        Self::flush_telemetry_async(&aggregator_clone, &config_clone, &client, SendContext {
+                            state: &ingest_state,
+                            flush_strategy: Some(FlushingStrategy::Continuous.as_str()),
+                            trace_id: current_trace_id.as_deref(),
+                            batch_seq: None,
+                        })
                     ).await;
                     
                     match flush_result {
                         Ok(Ok(events_sent)) if events_sent > 0 => {
+                            first_flush_at.get_or_init(Instant::now);
                             debug!("📤 Continuous flush: {} events sent", events_sent);
                         },
                         Ok(Err(e)) => {
@@ -203,18 +371,258 @@ impl ExtensionClient {
             });
             
             self.continuous_flush_task = Some(task);
+            self.continuous_flush_stop = Some(stop_tx);
             info!("✅ Continuous flush task started");
         }
         
         Ok(())
     }
 
+    /// Ask the continuous flush task to stop after its current flush, without waiting for it
+    fn signal_continuous_flush_stop(&mut self) {
+        if let Some(stop) = self.continuous_flush_stop.take() {
+            let _ = stop.send(true);
+        }
+        if self.continuous_flush_task.take().is_some() {
+            debug!("🛑 Continuous flush task asked to stop");
+        }
+    }
+
+    /// Stop the continuous flush task, letting an in-flight flush finish within `grace`.
+    /// Returns false if the task had to be aborted.
+    pub async fn stop_continuous_flush(&mut self, grace: Duration) -> bool {
+        let Some(mut task) = self.continuous_flush_task.take() else {
+            return true;
+        };
+        
+        if let Some(stop) = self.continuous_flush_stop.take() {
+            let _ = stop.send(true);
+        }
+        
+        match timeout(grace, &mut task).await {
+            Ok(_) => {
+                debug!("🛑 Continuous flush task finished");
+                true
+            }
+            Err(_) => {
+                warn!("⚠️ Continuous flush still running after {}ms grace, aborting", grace.as_millis());
+                task.abort();
+                false
+            }
+        }
+    }
+
+    /// Periodically enqueue the extension's own resource usage, if O2_SELF_METRICS_INTERVAL_MS is set
+    pub fn start_self_metrics_task(&mut self) {
+        let (Some(aggregator), Some(config)) = (self.aggregator.clone(), self.config.clone()) else {
+            return;
+        };
+        if config.self_metrics_interval_ms == 0 {
+            return;
+        }
+        
+        let sample_interval = Duration::from_millis(config.self_metrics_interval_ms);
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(sample_interval);
+            loop {
+                interval.tick().await;
+                match crate::openobserve::create_self_metrics_event() {
+                    Some(event) => aggregator.lock().await.add_batch(vec![event]),
+                    None => {
+                        debug!("Self metrics unavailable on this platform, stopping sampler");
+                        break;
+                    }
+                }
+            }
+        });
+        self.self_metrics_task = Some(task);
+    }
+    
+    /// Stop sampling the extension's own resource usage
+    pub fn stop_self_metrics_task(&mut self) {
+        if let Some(task) = self.self_metrics_task.take() {
+            task.abort();
+        }
+    }
+
+    /// Flush everything buffered every O2_WALL_CLOCK_FLUSH_MS, whatever the flushing strategy.
+    /// A tick is skipped while another flush is running.
+    pub fn start_wall_clock_flush_task(&mut self) {
+        let (Some(aggregator), Some(config)) = (self.aggregator.clone(), self.config.clone()) else {
+            return;
+        };
+        if config.wall_clock_flush_ms == 0 || config.flush_only_on_shutdown {
+            return;
+        }
+        let client = match Self::build_flush_client(&config, Duration::from_millis(1900)) {
+            Ok(client) => client,
+            Err(e) => {
+                warn!("⚠️ Wall-clock flush disabled: {}", e);
+                return;
+            }
+        };
+        
+        let flush_lock = Arc::clone(&self.flush_lock);
+        let first_flush_at = Arc::clone(&self.first_flush_at);
+        let ingest_state = Arc::clone(&self.ingest_state);
+        let trace_id = Arc::clone(&self.trace_id);
+        let flush_interval = Duration::from_millis(config.wall_clock_flush_ms);
+        let task = tokio::spawn(async move {
+            let mut interval = tokio::time::interval(flush_interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                let Ok(mut last_flush_at) = flush_lock.try_lock() else {
+                    debug!("⏭️ Wall-clock flush skipped, another flush is in progress");
+                    continue;
+                };
+                if aggregator.lock().await.is_empty() {
+                    continue;
+                }
+                Self::wait_for_min_flush_interval(&mut last_flush_at, &aggregator, &config).await;
+                
+                let current_trace_id = Self::current_trace_id(&trace_id);
+                match Self::flush_all_batches(&aggregator, &config, &client, SendContext {
+                    state: &ingest_state,
+                    flush_strategy: Some(WALL_CLOCK_FLUSH_TAG),
+                    trace_id: current_trace_id.as_deref(),
+                    batch_seq: None,
+                }).await {
+                    Ok(events_sent) => {
+                        if events_sent > 0 {
+                            first_flush_at.get_or_init(Instant::now);
+                        }
+                        debug!("⏰ Wall-clock flush: {} events sent", events_sent);
+                    }
+                    Err(e) => warn!("⚠️ Wall-clock flush failed: {}", e),
+                }
+            }
+        });
+        self.wall_clock_flush_task = Some(task);
+    }
+    
+    /// Stop the wall-clock flush timer
+    pub fn stop_wall_clock_flush_task(&mut self) {
+        if let Some(task) = self.wall_clock_flush_task.take() {
+            task.abort();
+        }
+    }
+
+    /// A flush without telemetry components means set_telemetry_components was never called.
+    /// Panics in debug builds; release builds warn once and carry on without flushing.
+    fn warn_missing_components(&self, context: &str) {
+        if !self.missing_components_warned.swap(true, Ordering::Relaxed) {
+            warn!("⚠️ {} skipped: telemetry components not set (set_telemetry_components was not called)", context);
+        }
+        debug_assert!(false, "{context} attempted before telemetry components were set");
+    }
+
+    fn current_trace_id(trace_id: &std::sync::Mutex<Option<String>>) -> Option<String> {
+        trace_id.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+    
     /// Perform end-of-invocation flush for low-frequency functions
-    pub async fn flush_end_of_invocation(&self) -> Result<u64> {
+    pub async fn flush_end_of_invocation(&mut self) -> Result<u64> {
         if let (Some(aggregator), Some(config)) = (&self.aggregator, &self.config) {
+            if config.async_invoke_flush {
+                self.start_invoke_flush_task();
+                return Ok(0);
+            }
             debug!("📤 End-of-invocation flush");
             self.flush_telemetry_synchronously(aggregator, config).await
         } else {
+            self.warn_missing_components("end-of-invocation flush");
+            Ok(0)
+        }
+    }
+
+    /// Flush in a background task so the invoke isn't held up (O2_ASYNC_INVOKE_FLUSH). A flush
+    /// still running keeps taking batches until the queue is empty, so it isn't started twice;
+    /// events arriving after its last batch wait for the next flush.
+    fn start_invoke_flush_task(&mut self) {
+        if self.invoke_flush_task.as_ref().is_some_and(|task| !task.is_finished()) {
+            debug!("⏭️ Background flush still running, it will pick up the new events");
+            return;
+        }
+        let (Some(aggregator), Some(config)) = (self.aggregator.clone(), self.config.clone()) else {
+            return;
+        };
+        let client = match &self.flush_client {
+            Some(client) => client.clone(),
+            None => match Self::build_flush_client(&config, Duration::from_millis(1900)) {
+                Ok(client) => client,
+                Err(e) => {
+                    warn!("⚠️ Background flush skipped: {}", e);
+                    return;
+                }
+            },
+        };
+        
+        let flush_lock = Arc::clone(&self.flush_lock);
+        let first_flush_at = Arc::clone(&self.first_flush_at);
+        let ingest_state = Arc::clone(&self.ingest_state);
+        let trace_id = Self::current_trace_id(&self.trace_id);
+        debug!("📤 End-of-invocation flush (background)");
+        self.invoke_flush_task = Some(tokio::spawn(async move {
+            let mut last_flush_at = flush_lock.lock().await;
+            Self::wait_for_min_flush_interval(&mut last_flush_at, &aggregator, &config).await;
+            match Self::flush_all_batches(&aggregator, &config, &client, SendContext {
+                state: &ingest_state,
+                flush_strategy: Some(FlushingStrategy::EndOfInvocation.as_str()),
+                trace_id: trace_id.as_deref(),
+                batch_seq: None,
+            }).await {
+                Ok(events_sent) => {
+                    if events_sent > 0 {
+                        first_flush_at.get_or_init(Instant::now);
+                    }
+                    debug!("📤 Background flush: {} events sent", events_sent);
+                }
+                Err(e) => warn!("⚠️ Background end-of-invocation flush failed: {}", e),
+            }
+        }));
+    }
+    
+    /// Final flush on SHUTDOWN, retrying at most O2_SHUTDOWN_MAX_RETRIES times
+    pub async fn flush_for_shutdown(&mut self) -> Result<u64> {
+        // Let a background invoke flush finish first; it holds the flush lock anyway. A hung one
+        // is aborted after O2_SHUTDOWN_GRACE_MS so the final flush still gets the deadline.
+        if let Some(mut task) = self.invoke_flush_task.take() {
+            let grace = Duration::from_millis(self.config.as_ref().map_or(0, |config| config.shutdown_grace_ms));
+            if timeout(grace, &mut task).await.is_err() {
+                warn!("⚠️ Background flush still running after {}ms grace, aborting", grace.as_millis());
+                task.abort();
+            }
+        }
+        if let (Some(aggregator), Some(config)) = (&self.aggregator, &self.config) {
+            // Don't spend the deadline retrying against an endpoint that isn't there
+            if config.shutdown_precheck {
+                if let Err(e) = crate::openobserve::probe_endpoint(config, SHUTDOWN_PRECHECK_TIMEOUT).await {
+                    warn!("⚠️ Skipping SHUTDOWN flush: {}", e);
+                    return Err(e.into());
+                }
+            }
+            
+            // The current minute's counts won't get another chance
+            aggregator.lock().await.release_counts(true);
+            let shutdown_config = Arc::new(config.shutdown_config());
+            self.flush_telemetry_synchronously(aggregator, &shutdown_config).await
+        } else {
+            self.warn_missing_components("SHUTDOWN flush");
+            Ok(0)
+        }
+    }
+
+    /// Flush right away if the aggregator asked for it (memory pressure or an O2_FLUSH_ON_TYPES match)
+    pub async fn flush_if_requested(&self) -> Result<u64> {
+        if let (Some(aggregator), Some(config)) = (&self.aggregator, &self.config) {
+            if !aggregator.lock().await.take_flush_request() || config.flush_only_on_shutdown {
+                return Ok(0);
+            }
+            debug!("📤 Immediate flush");
+            self.flush_telemetry_synchronously(aggregator, config).await
+        } else {
+            self.warn_missing_components("immediate flush");
             Ok(0)
         }
     }
@@ -226,9 +634,19 @@ impl ExtensionClient {
             self.last_periodic_flush = now;
             
             if let (Some(aggregator), Some(config)) = (&self.aggregator, &self.config) {
+                // Coalesce small idle-period writes until the batch is full or old enough
+                if config.min_batch_age_ms > 0 {
+                    let min_age = Duration::from_millis(config.min_batch_age_ms);
+                    if !aggregator.lock().await.is_batch_ready(min_age) {
+                        debug!("⏳ Holding partial batch until it reaches {}ms", config.min_batch_age_ms);
+                        return Ok(0);
+                    }
+                }
+                
                 debug!("📤 Periodic flush");
                 self.flush_telemetry_synchronously(aggregator, config).await
             } else {
+                self.warn_missing_components("periodic flush");
                 Ok(0)
             }
         } else {
@@ -240,38 +658,75 @@ impl ExtensionClient {
     async fn flush_telemetry_async(
         aggregator: &Arc<Mutex<TelemetryAggregator>>,
         config: &Arc<Config>,
+        client: &Client,
+        context: SendContext<'_>,
     ) -> Result<u64> {
         let mut total_events = 0;
         
         // Only process one batch at a time to avoid blocking
         let batch = {
             let mut guard = aggregator.lock().await;
-            guard.get_batch()
+            guard.take_batch()
         };
         
-        if !batch.is_empty() {
-            let client = reqwest::Client::builder()
-                .timeout(Duration::from_millis(1000)) // 1 second timeout for async
-                .build()
-                .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?;
-            
-            match crate::openobserve::send_batch_to_openobserve(&client, config, &batch).await {
+        if !batch.body.is_empty() {
+            let context = SendContext { batch_seq: Some(batch.seq), ..context };
+            match crate::openobserve::send_flush_batch(client, config, &batch.body, context).await {
                 Ok(events_sent) => {
                     total_events += events_sent;
+                    context.state.primary.batch_sizes.record(batch.body.len(), batch.entries() as u64);
                     debug!("✅ Async flush: {} events sent", events_sent);
                 },
                 Err(e) => {
                     warn!("❌ Async flush failed: {}", e);
-                    return Err(e);
+                    if let ExtensionError::Rejected { .. } = e {
+                        Self::requeue_rejected(aggregator, batch).await;
+                    }
+                    crate::openobserve::send_flush_failure_alert(config, 1, &e.to_string()).await;
+                    return Err(e.into());
                 }
             }
         }
         
+        Self::export_platform_metrics(aggregator, config, client).await;
+        
         Ok(total_events)
     }
     
+    /// Put a batch OpenObserve rejected back in the queue (O2_REQUEUE_REJECTED)
+    async fn requeue_rejected(aggregator: &Arc<Mutex<TelemetryAggregator>>, batch: PendingBatch) {
+        let dropped = aggregator.lock().await.requeue_batch(batch);
+        if dropped > 0 {
+            warn!("⚠️ Dropped {} events rejected on every attempt", dropped);
+        }
+    }
+    
+    /// Send collected platform metrics to the metrics endpoint, keeping them buffered on failure
+    async fn export_platform_metrics(
+        aggregator: &Arc<Mutex<TelemetryAggregator>>,
+        config: &Config,
+        client: &reqwest::Client,
+    ) {
+        if config.metrics_export == MetricsExport::None {
+            return;
+        }
+        
+        let samples = aggregator.lock().await.take_metric_samples();
+        if samples.is_empty() {
+            return;
+        }
+        
+        match crate::openobserve::send_metrics_remote_write(client, config, &samples).await {
+            Ok(()) => debug!("📈 Exported {} platform metric samples", samples.len()),
+            Err(e) => {
+                warn!("⚠️ Platform metrics export failed: {}", e);
+                aggregator.lock().await.restore_metric_samples(samples);
+            }
+        }
+    }
+    
     pub async fn register(&mut self) -> Result<RegisterResponse> {
-        let url = format!("http://{}/2020-01-01/extension/register", self.runtime_api_endpoint);
+        let url = format!("{}/2020-01-01/extension/register", self.runtime_api_endpoint);
         
         let register_request = RegisterRequest {
             events: vec!["INVOKE".to_string(), "SHUTDOWN".to_string()],
@@ -317,12 +772,43 @@ impl ExtensionClient {
         Ok(register_response)
     }
     
+    /// Wait for the first event, failing with a diagnostic if the Runtime API never answers
+    pub async fn first_event(&mut self, limit: Duration) -> Result<NextEventResponse> {
+        match timeout(limit, self.next_event()).await {
+            Ok(event) => event,
+            Err(_) => Err(anyhow!(
+                "No event from the Runtime API at {} within {}ms - check AWS_LAMBDA_RUNTIME_API and that the runtime or emulator is running",
+                self.runtime_api_endpoint,
+                limit.as_millis()
+            )),
+        }
+    }
+    
+    /// next_event, retried with a short backoff after up to `max_errors` consecutive failures
+    /// (O2_NEXT_EVENT_MAX_ERRORS), so a transient runtime API error doesn't end the extension
+    pub async fn next_event_with_retries(&mut self, max_errors: u32) -> Result<NextEventResponse> {
+        let mut delay = NEXT_EVENT_RETRY_DELAY;
+        for attempt in 0..=max_errors {
+            match self.next_event().await {
+                Ok(event) => return Ok(event),
+                Err(e) if attempt < max_errors => {
+                    warn!("⚠️ Next event request failed ({}/{}): {}. Retrying in {:?}", attempt + 1, max_errors, e, delay);
+                    tokio::time::sleep(delay).await;
+                    delay = (delay * 2).min(NEXT_EVENT_MAX_RETRY_DELAY);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        
+        unreachable!("retry loop always returns on the final attempt")
+    }
+    
     pub async fn next_event(&mut self) -> Result<NextEventResponse> {
         let extension_id = self.extension_id.as_ref()
             .ok_or_else(|| anyhow!("Extension not registered"))?;
         
         let url = format!(
-            "http://{}/2020-01-01/extension/event/next", 
+            "{}/2020-01-01/extension/event/next",
             self.runtime_api_endpoint
         );
         
@@ -350,40 +836,18 @@ impl ExtensionClient {
             .map_err(|e| anyhow!("Failed to parse next event response: {}", e))?;
         
         match &event {
-            NextEventResponse::Invoke { request_id: _, deadline_ms: _ } => {
-                let now = std::time::Instant::now();
-                self.invocation_count += 1;
-                self.last_invocation_time = now;
-                
-                // Track recent invocations for frequency calculation
-                self.recent_invocations.push_back(now);
-                
-                // Keep only invocations from the last 5 minutes for frequency calculation
-                let five_minutes_ago = now - std::time::Duration::from_secs(300);
-                while let Some(&front_time) = self.recent_invocations.front() {
-                    if front_time < five_minutes_ago {
-                        self.recent_invocations.pop_front();
-                    } else {
-                        break;
-                    }
-                }
-                
-                // Update flushing strategy based on current patterns
-                if let Err(e) = self.update_flushing_strategy().await {
-                    warn!("⚠️ Failed to update flushing strategy: {}", e);
-                }
-                
+            NextEventResponse::Invoke { invoked_function_arn, tracing, .. } => {
+                *self.trace_id.lock().unwrap_or_else(|e| e.into_inner()) = tracing.as_ref()
+                    .map(|tracing| tracing.value.clone())
+                    .filter(|value| !value.is_empty());
+                self.record_invoke(invoked_function_arn.as_deref()).await;
             },
             NextEventResponse::Shutdown { deadline_ms: _ } => {
                 debug!("🔄 SHUTDOWN event received - triggering immediate synchronous flush");
                 
-                if let (Some(aggregator), Some(config)) = (&self.aggregator, &self.config) {
-                    match self.flush_telemetry_synchronously(aggregator, config).await {
-                        Ok(events_sent) => debug!("✅ Emergency flush completed: {} events sent", events_sent),
-                        Err(e) => debug!("❌ Emergency flush failed: {}", e),
-                    }
-                } else {
-                    debug!("⚠️ SHUTDOWN received but telemetry components not set");
+                match self.flush_for_shutdown().await {
+                    Ok(events_sent) => debug!("✅ Emergency flush completed: {} events sent", events_sent),
+                    Err(e) => debug!("❌ Emergency flush failed: {}", e),
                 }
             },
         }
@@ -391,38 +855,149 @@ impl ExtensionClient {
         Ok(event)
     }
     
+    /// Bookkeeping for a new INVOKE: invocation rate, flushing strategy, draining events
+    /// buffered before a long freeze, and the invoked ARN for O2_INCLUDE_ARN
+    async fn record_invoke(&mut self, invoked_function_arn: Option<&str>) {
+        let now = std::time::Instant::now();
+        let gap = now.duration_since(self.last_invocation_time);
+        self.invocation_count += 1;
+        self.last_invocation_time = now;
+        
+        // Track recent invocations for frequency calculation
+        self.recent_invocations.push_back(now);
+        
+        // Keep only invocations from the last 5 minutes for frequency calculation
+        let five_minutes_ago = now - std::time::Duration::from_secs(300);
+        while let Some(&front_time) = self.recent_invocations.front() {
+            if front_time < five_minutes_ago {
+                self.recent_invocations.pop_front();
+            } else {
+                break;
+            }
+        }
+        
+        // At high rates, estimate from the newest invocations only
+        let max_recent = self.config.as_ref()
+            .map_or(DEFAULT_MAX_RECENT_INVOCATIONS, |config| config.max_recent_invocations);
+        let excess = self.recent_invocations.len().saturating_sub(max_recent);
+        self.recent_invocations.drain(..excess);
+        
+        // Update flushing strategy based on current patterns
+        if let Err(e) = self.update_flushing_strategy().await {
+            warn!("⚠️ Failed to update flushing strategy: {}", e);
+        }
+        
+        // The runtime's own ARN beats the one pieced together at registration
+        if let (Some(aggregator), Some(config), Some(arn)) = (&self.aggregator, &self.config, invoked_function_arn) {
+            if config.include_arn {
+                aggregator.lock().await.set_function_arn(Some(arn.to_string()));
+            }
+        }
+        
+        // Events from before the freeze are already stale, so don't wait on the strategy
+        if let (Some(aggregator), Some(config)) = (&self.aggregator, &self.config) {
+            let thawed = config.freeze_gap_secs > 0 && gap > Duration::from_secs(config.freeze_gap_secs);
+            if thawed && !config.flush_only_on_shutdown && !aggregator.lock().await.is_empty() {
+                debug!("🧊 Invoke after {}s without one, flushing events from before the freeze", gap.as_secs());
+                match self.flush_telemetry_synchronously(aggregator, config).await {
+                    Ok(events_sent) => debug!("📤 Thaw flush: {} events sent", events_sent),
+                    Err(e) => warn!("⚠️ Thaw flush failed: {}", e),
+                }
+            }
+        }
+    }
+    
     async fn flush_telemetry_synchronously(
         &self,
         aggregator: &Arc<Mutex<TelemetryAggregator>>,
         config: &Arc<Config>,
+    ) -> Result<u64> {
+        let client = match &self.flush_client {
+            Some(client) => client.clone(),
+            None => Self::build_flush_client(config, Duration::from_millis(1900))?,
+        };
+        
+        let mut last_flush_at = self.flush_lock.lock().await;
+        Self::wait_for_min_flush_interval(&mut last_flush_at, aggregator, config).await;
+        let trace_id = Self::current_trace_id(&self.trace_id);
+        let events_sent = Self::flush_all_batches(aggregator, config, &client, SendContext {
+            state: &self.ingest_state,
+            flush_strategy: Some(self.current_strategy.as_str()),
+            trace_id: trace_id.as_deref(),
+            batch_seq: None,
+        }).await?;
+        if events_sent > 0 {
+            self.first_flush_at.get_or_init(Instant::now);
+        }
+        Ok(events_sent)
+    }
+    
+    /// Hold a flush back until O2_MIN_FLUSH_INTERVAL_MS has passed since the previous one
+    /// started. Called with flush_lock held, so every flush path shares `last_flush_at`.
+    async fn wait_for_min_flush_interval(
+        last_flush_at: &mut Option<Instant>,
+        aggregator: &Arc<Mutex<TelemetryAggregator>>,
+        config: &Config,
+    ) {
+        if config.min_flush_interval_ms == 0 {
+            return;
+        }
+        // Nothing will be sent, so don't take up the slot
+        if aggregator.lock().await.is_empty() {
+            return;
+        }
+        
+        if let Some(last) = *last_flush_at {
+            let earliest = last + Duration::from_millis(config.min_flush_interval_ms);
+            if earliest > Instant::now() {
+                debug!("⏳ Deferring flush to respect O2_MIN_FLUSH_INTERVAL_MS");
+                tokio::time::sleep_until(earliest.into()).await;
+            }
+        }
+        *last_flush_at = Some(Instant::now());
+    }
+    
+    /// Send batches until the aggregator is drained (or O2_MAX_BATCHES_PER_FLUSH is reached)
+    async fn flush_all_batches(
+        aggregator: &Arc<Mutex<TelemetryAggregator>>,
+        config: &Config,
+        client: &Client,
+        context: SendContext<'_>,
     ) -> Result<u64> {
         let mut total_events = 0;
         let url = config.openobserve_url();
         
         debug!("🌐 Starting synchronous flush to {}", url);
         
-        // Create HTTP client with timeout
-        let client = reqwest::Client::builder()
-            .timeout(std::time::Duration::from_millis(1900)) // 1.9 seconds max
-            .build()
-            .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?;
+        let mut batches_sent = 0;
+        // Batches already acknowledged this cycle, so a batch handed back again is not posted twice
+        let mut acknowledged_keys = HashSet::new();
+        // Put back once the flush is over, so the rest of the queue isn't held up behind them
+        let mut rejected = Vec::new();
+        let mut rejection = None;
         
         loop {
+            // Bound the flush so a large backlog cannot blow the deadline
+            if config.max_batches_per_flush > 0 && batches_sent >= config.max_batches_per_flush {
+                debug!("⏸️ Reached max of {} batches per flush, leaving remaining events buffered", batches_sent);
+                break;
+            }
+            
             // Get next batch from aggregator
             let batch = {
                 let mut guard = aggregator.lock().await;
-                guard.get_batch()
+                guard.take_batch()
             };
             
             // If no more batches, we're done
-            if batch.is_empty() {
+            if batch.body.is_empty() {
                 break;
             }
             
-            // debug!("📦 Sending batch of {} bytes", batch.len());
+            // debug!("📦 Sending batch of {} bytes", batch.body.len());
             
             // Count events in this batch
-            let _events_in_batch = if let Ok(batch_str) = String::from_utf8(batch.clone()) {
+            let _events_in_batch = if let Ok(batch_str) = String::from_utf8(batch.body.clone()) {
                 if batch_str.trim().starts_with('[') && batch_str.trim().ends_with(']') {
                     batch_str.matches(',').count() as u64 + 1
                 } else {
@@ -432,18 +1007,49 @@ impl ExtensionClient {
                 1
             };
             
+            let idempotency_key = batch.idempotency_key();
+            if acknowledged_keys.contains(&idempotency_key) {
+                debug!("⏭️ Skipping batch already acknowledged this cycle ({})", idempotency_key);
+                continue;
+            }
+            
             // Use the shared HTTP function
-            match crate::openobserve::send_batch_to_openobserve(&client, config, &batch).await {
+            let batch_context = SendContext { batch_seq: Some(batch.seq), ..context };
+            match crate::openobserve::send_flush_batch(client, config, &batch.body, batch_context).await {
                 Ok(events_sent) => {
                     total_events += events_sent;
+                    batches_sent += 1;
+                    context.state.primary.batch_sizes.record(batch.body.len(), batch.entries() as u64);
+                    acknowledged_keys.insert(idempotency_key);
+                }
+                Err(e @ ExtensionError::Rejected { .. }) => {
+                    debug!("❌ Batch rejected: {}", e);
+                    batches_sent += 1;
+                    rejected.push(batch);
+                    crate::openobserve::send_flush_failure_alert(config, 1, &e.to_string()).await;
+                    rejection.get_or_insert(e);
                 }
                 Err(e) => {
                     debug!("❌ Batch failed: {}", e);
-                    return Err(e);
+                    for batch in rejected.into_iter().rev() {
+                        Self::requeue_rejected(aggregator, batch).await;
+                    }
+                    crate::openobserve::send_flush_failure_alert(config, 1, &e.to_string()).await;
+                    return Err(e.into());
                 }
             }
         }
         
+        for batch in rejected.into_iter().rev() {
+            Self::requeue_rejected(aggregator, batch).await;
+        }
+        if let Some(e) = rejection {
+            return Err(e.into());
+        }
+        
+        Self::export_platform_metrics(aggregator, config, client).await;
+        aggregator.lock().await.shrink_if_idle();
+        
         debug!("🎉 Synchronous flush completed: {} total events sent", total_events);
         Ok(total_events)
     }
@@ -456,6 +1062,51 @@ impl ExtensionClient {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::telemetry::TelemetryEvent;
+    use crate::test_support::MockServer;
+    
+    fn queued_aggregator(event_count: usize, max_batch_entries: usize) -> Arc<Mutex<TelemetryAggregator>> {
+        let mut aggregator = TelemetryAggregator::new(1024 * 1024, max_batch_entries);
+        let events = (0..event_count)
+            .map(|i| TelemetryEvent {
+                time: chrono::Utc::now(),
+                event_type: "function".to_string(),
+                record: serde_json::json!(format!("log line {i}")),
+                request_id: None,
+            })
+            .collect();
+        aggregator.add_batch(events);
+        Arc::new(Mutex::new(aggregator))
+    }
+    
+    fn test_config(endpoint: String) -> Config {
+        Config {
+            o2_endpoint: endpoint,
+            o2_organization_id: "test_org".to_string(),
+            o2_authorization_header: "Basic dGVzdA==".to_string(),
+            ..Default::default()
+        }
+    }
+    
+    #[test]
+    fn test_runtime_api_base_url_host_port() {
+        assert_eq!(runtime_api_base_url("127.0.0.1:9001"), "http://127.0.0.1:9001");
+        assert_eq!(runtime_api_base_url("localhost:9001"), "http://localhost:9001");
+    }
+    
+    #[test]
+    fn test_runtime_api_base_url_with_scheme() {
+        assert_eq!(runtime_api_base_url("http://127.0.0.1:9001/"), "http://127.0.0.1:9001");
+        assert_eq!(runtime_api_base_url("https://runtime.example:443"), "https://runtime.example:443");
+    }
+    
+    #[test]
+    fn test_runtime_api_base_url_ipv6() {
+        assert_eq!(runtime_api_base_url("[::1]:9001"), "http://[::1]:9001");
+        assert_eq!(runtime_api_base_url("fd00::1:9001"), "http://[fd00::1:9001]");
+        assert_eq!(runtime_api_base_url("fd00::abcd:9001"), "http://[fd00::abcd:9001]");
+        assert_eq!(runtime_api_base_url("::1"), "http://[::1]");
+    }
     
     #[test]
     fn test_extension_client_creation() {
@@ -463,4 +1114,765 @@ mod tests {
         assert_eq!(client.extension_name, "test-extension");
         assert_eq!(client.invocation_count, 0);
     }
-}
\ No newline at end of file
+    
+    #[tokio::test]
+    async fn test_continuous_flush_skips_empty_buffer() {
+        let server = MockServer::start().await;
+        let aggregator = queued_aggregator(0, 10);
+        let config = Config {
+            continuous_flush_interval_ms: 10,
+            ..test_config(server.endpoint())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(Arc::clone(&aggregator), Arc::new(config));
+        client.start_continuous_flush_task().await.unwrap();
+        
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(server.request_count().await, 0);
+        
+        // Events buffered later are still picked up on the configured interval
+        aggregator.lock().await.add_batch(vec![TelemetryEvent {
+            time: chrono::Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!("late log"),
+            request_id: None,
+        }]);
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(server.request_count().await, 1);
+        
+        client.stop_continuous_flush(Duration::from_secs(1)).await;
+    }
+
+    #[tokio::test]
+    async fn test_flush_carries_strategy_tag() {
+        let server = MockServer::start().await;
+        let config = Config {
+            continuous_flush_interval_ms: 10,
+            ..test_config(server.endpoint())
+        };
+
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(queued_aggregator(1, 10), Arc::new(config));
+        client.current_strategy = FlushingStrategy::Continuous;
+        client.start_continuous_flush_task().await.unwrap();
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        client.stop_continuous_flush(Duration::from_secs(1)).await;
+
+        let requests = server.requests().await;
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].headers[crate::openobserve::FLUSH_STRATEGY_HEADER], "continuous");
+    }
+
+    #[tokio::test]
+    async fn test_flush_sends_identical_batches() {
+        let server = MockServer::start().await;
+        
+        // Two identical deliveries end up as two identical single-event batches
+        let time = chrono::Utc::now();
+        let mut aggregator = TelemetryAggregator::new(1024 * 1024, 1);
+        for _ in 0..2 {
+            aggregator.add_batch(vec![TelemetryEvent {
+                time,
+                event_type: "function".to_string(),
+                record: serde_json::json!("same log line"),
+                request_id: None,
+            }]);
+        }
+        let aggregator = Arc::new(Mutex::new(aggregator));
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(Arc::clone(&aggregator), Arc::new(test_config(server.endpoint())));
+        client.flush_end_of_invocation().await.unwrap();
+        
+        // Separate deliveries are not duplicates of each other, even with equal content
+        let requests = server.requests().await;
+        assert_eq!(requests.len(), 2);
+        assert_ne!(
+            requests[0].headers[crate::openobserve::IDEMPOTENCY_KEY_HEADER],
+            requests[1].headers[crate::openobserve::IDEMPOTENCY_KEY_HEADER]
+        );
+        assert!(aggregator.lock().await.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_flush_does_not_repost_acknowledged_batch() {
+        let server = MockServer::start().await;
+        // The first attempt fails, so the batch goes out again on the retry path
+        server.push_status(http::StatusCode::SERVICE_UNAVAILABLE).await;
+        
+        // A batch that was sent, then handed back twice, e.g. by a caller retrying it
+        let mut aggregator = TelemetryAggregator::new(1024 * 1024, 1);
+        aggregator.add_batch(vec![TelemetryEvent {
+            time: chrono::Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!("log line"),
+            request_id: None,
+        }]);
+        let batch = aggregator.take_batch();
+        let key = batch.idempotency_key();
+        aggregator.requeue_batch(batch.clone());
+        aggregator.requeue_batch(batch);
+        let aggregator = Arc::new(Mutex::new(aggregator));
+        
+        let config = Config {
+            max_retries: 1,
+            initial_retry_delay_ms: 1,
+            max_retry_delay_ms: 1,
+            ..test_config(server.endpoint())
+        };
+        let client = reqwest::Client::new();
+        let state = IngestState::new(&config);
+        let context = SendContext { state: &state, flush_strategy: None, trace_id: None, batch_seq: None };
+        ExtensionClient::flush_all_batches(&aggregator, &config, &client, context).await.unwrap();
+        
+        // Retried with the batch's own key, and not posted again once acknowledged
+        let requests = server.requests().await;
+        assert_eq!(requests.len(), 2);
+        for request in &requests {
+            assert_eq!(request.headers[crate::openobserve::IDEMPOTENCY_KEY_HEADER], key.as_str());
+        }
+        assert!(aggregator.lock().await.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_async_invoke_flush() {
+        let server = MockServer::start().await;
+        server.set_delay(Duration::from_millis(300)).await;
+        let aggregator = queued_aggregator(5, 10);
+        let config = Config {
+            async_invoke_flush: true,
+            shutdown_grace_ms: 1000,
+            ..test_config(server.endpoint())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(Arc::clone(&aggregator), Arc::new(config));
+        
+        let started = Instant::now();
+        client.flush_end_of_invocation().await.unwrap();
+        assert!(started.elapsed() < Duration::from_millis(300));
+        assert_eq!(server.responses_sent(), 0);
+        
+        // SHUTDOWN waits for the background flush, so the data still arrives
+        client.flush_for_shutdown().await.unwrap();
+        assert_eq!(server.responses_sent(), 1);
+        let body: Vec<serde_json::Value> = serde_json::from_slice(&server.requests().await[0].body).unwrap();
+        assert_eq!(body.len(), 5);
+        assert!(aggregator.lock().await.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_shutdown_aborts_hung_background_flush() {
+        let server = MockServer::start().await;
+        server.set_delay(Duration::from_secs(3)).await;
+        let aggregator = queued_aggregator(5, 10);
+        let config = Config {
+            async_invoke_flush: true,
+            shutdown_grace_ms: 100,
+            ..test_config(server.endpoint())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(Arc::clone(&aggregator), Arc::new(config));
+        client.flush_end_of_invocation().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(server.request_count().await, 1);
+        
+        // Events arriving meanwhile still go out with the SHUTDOWN flush
+        server.set_delay(Duration::ZERO).await;
+        aggregator.lock().await.add_batch(vec![TelemetryEvent {
+            time: chrono::Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!("late log line"),
+            request_id: None,
+        }]);
+        let started = Instant::now();
+        client.flush_for_shutdown().await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(1));
+        
+        let requests = server.requests().await;
+        assert_eq!(requests.len(), 2);
+        let body: Vec<serde_json::Value> = serde_json::from_slice(&requests[1].body).unwrap();
+        assert_eq!(body[0]["record"], "late log line");
+    }
+    
+    #[tokio::test]
+    async fn test_rejected_batch_is_requeued() {
+        let server = MockServer::start().await;
+        let rejected = r#"{"code":200,"status":[{"name":"default","successful":0,"failed":3,"error":"schema mismatch"}]}"#;
+        server.push_response(http::StatusCode::OK, rejected).await;
+        let aggregator = queued_aggregator(3, 10);
+        let config = Config {
+            requeue_rejected: true,
+            ..test_config(server.endpoint())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(Arc::clone(&aggregator), Arc::new(config));
+        
+        assert!(client.flush_end_of_invocation().await.is_err());
+        assert!(!aggregator.lock().await.is_empty());
+        
+        // The next flush sends the same records again
+        client.flush_end_of_invocation().await.unwrap();
+        let requests = server.requests().await;
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].body, requests[1].body);
+        assert!(aggregator.lock().await.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_always_rejected_batch_does_not_block_queue() {
+        let server = MockServer::start().await;
+        let rejected = r#"{"code":200,"status":[{"name":"default","successful":0,"failed":1,"error":"schema mismatch"}]}"#;
+        let aggregator = queued_aggregator(3, 1);
+        let config = Config {
+            requeue_rejected: true,
+            ..test_config(server.endpoint())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(Arc::clone(&aggregator), Arc::new(config));
+        
+        // The first event is rejected on every attempt; the ones behind it still go out
+        server.push_response(http::StatusCode::OK, rejected).await;
+        assert!(client.flush_end_of_invocation().await.is_err());
+        let requests = server.requests().await;
+        assert_eq!(requests.len(), 3);
+        assert!(String::from_utf8_lossy(&requests[2].body).contains("log line 2"));
+        
+        // Retried with later flushes until the requeue limit, then dropped
+        for _ in 0..crate::telemetry::MAX_REQUEUE_ATTEMPTS {
+            server.push_response(http::StatusCode::OK, rejected).await;
+            assert!(client.flush_end_of_invocation().await.is_err());
+        }
+        assert!(aggregator.lock().await.is_empty());
+        let requests = server.requests().await;
+        assert_eq!(requests.len(), 3 + crate::telemetry::MAX_REQUEUE_ATTEMPTS as usize);
+        assert!(requests[3..].iter().all(|request| request.body == requests[0].body));
+    }
+    
+    #[tokio::test]
+    async fn test_stop_waits_for_in_flight_flush() {
+        let server = MockServer::start().await;
+        server.set_delay(Duration::from_millis(200)).await;
+        let config = Config {
+            continuous_flush_interval_ms: 10,
+            ..test_config(server.endpoint())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(queued_aggregator(1, 10), Arc::new(config));
+        client.start_continuous_flush_task().await.unwrap();
+        
+        // Let the flush get on the wire, then stop while the response is pending
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(server.request_count().await, 1);
+        assert_eq!(server.responses_sent(), 0);
+        
+        assert!(client.stop_continuous_flush(Duration::from_secs(1)).await);
+        assert_eq!(server.responses_sent(), 1);
+    }
+    
+    #[tokio::test]
+    async fn test_strategy_change_lets_in_flight_flush_finish() {
+        let server = MockServer::start().await;
+        server.set_delay(Duration::from_millis(200)).await;
+        let config = Config {
+            continuous_flush_interval_ms: 10,
+            ..test_config(server.endpoint())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(queued_aggregator(1, 10), Arc::new(config));
+        client.start_continuous_flush_task().await.unwrap();
+        client.current_strategy = FlushingStrategy::Continuous;
+        
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(server.request_count().await, 1);
+        
+        // Going idle switches to periodic flushing while the request is still pending
+        client.last_invocation_time = Instant::now() - Duration::from_secs(LONG_RUNNING_THRESHOLD_SECS + 1);
+        client.update_flushing_strategy().await.unwrap();
+        assert_eq!(client.current_strategy, FlushingStrategy::Periodic);
+        assert!(client.continuous_flush_task.is_none());
+        assert_eq!(server.responses_sent(), 0);
+        
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(server.responses_sent(), 1);
+        assert_eq!(server.request_count().await, 1);
+    }
+    
+    #[tokio::test]
+    async fn test_stop_aborts_after_grace() {
+        let server = MockServer::start().await;
+        server.set_delay(Duration::from_millis(300)).await;
+        let config = Config {
+            continuous_flush_interval_ms: 10,
+            ..test_config(server.endpoint())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(queued_aggregator(1, 10), Arc::new(config));
+        client.start_continuous_flush_task().await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        
+        assert!(!client.stop_continuous_flush(Duration::from_millis(20)).await);
+        assert!(client.continuous_flush_task.is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_max_batches_per_flush() {
+        let server = MockServer::start().await;
+        let aggregator = queued_aggregator(10, 1);
+        let config = Config {
+            max_batches_per_flush: 2,
+            ..test_config(server.endpoint())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(Arc::clone(&aggregator), Arc::new(config));
+        
+        client.flush_end_of_invocation().await.unwrap();
+        assert_eq!(server.request_count().await, 2);
+        
+        // The rest stays buffered for the next flush
+        assert!(!aggregator.lock().await.get_batch().is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_flush_records_batch_entry_counts() {
+        let server = MockServer::start().await;
+        let aggregator = queued_aggregator(6, 3);
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(Arc::clone(&aggregator), Arc::new(test_config(server.endpoint())));
+        assert_eq!(client.batch_size_percentiles(), None);
+        client.flush_end_of_invocation().await.unwrap();
+        
+        // Counted from the batch's events, not from commas in their JSON
+        let batches = client.batch_size_percentiles().unwrap();
+        assert_eq!(batches.batches, 2);
+        assert_eq!(batches.p50_events, 3);
+        assert_eq!(batches.p95_events, 3);
+    }
+    
+    #[tokio::test]
+    async fn test_shutdown_flush_uses_shutdown_max_retries() {
+        let server = MockServer::start().await;
+        server.set_status(http::StatusCode::INTERNAL_SERVER_ERROR).await;
+        let config = Config {
+            max_retries: 5,
+            shutdown_max_retries: 1,
+            initial_retry_delay_ms: 1,
+            max_retry_delay_ms: 5,
+            ..test_config(server.endpoint())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(queued_aggregator(3, 10), Arc::new(config));
+        
+        assert!(client.flush_for_shutdown().await.is_err());
+        assert_eq!(server.request_count().await, 2);
+    }
+    
+    #[tokio::test]
+    async fn test_shutdown_precheck_skips_unreachable_endpoint() {
+        // Bind then drop a listener to get a local port nothing is listening on
+        let closed_port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let aggregator = queued_aggregator(3, 10);
+        let config = Config {
+            shutdown_precheck: true,
+            shutdown_max_retries: 3,
+            initial_retry_delay_ms: 500,
+            ..test_config(format!("http://127.0.0.1:{closed_port}"))
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(Arc::clone(&aggregator), Arc::new(config));
+        
+        let started = Instant::now();
+        let error = client.flush_for_shutdown().await.unwrap_err();
+        assert!(started.elapsed() < SHUTDOWN_PRECHECK_TIMEOUT * 2, "took {:?}", started.elapsed());
+        assert!(error.to_string().contains("unreachable"));
+        
+        // No batch was taken for sending, so nothing was lost to the skipped flush
+        assert!(!aggregator.lock().await.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_shutdown_precheck_passes_for_live_endpoint() {
+        let server = MockServer::start().await;
+        let config = Config {
+            shutdown_precheck: true,
+            ..test_config(server.endpoint())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(queued_aggregator(3, 10), Arc::new(config));
+        
+        assert!(client.flush_for_shutdown().await.is_ok());
+        assert_eq!(server.request_count().await, 1);
+    }
+    
+    #[tokio::test]
+    async fn test_alert_webhook_called_after_failed_flush() {
+        let server = MockServer::start().await;
+        server.set_status(http::StatusCode::INTERNAL_SERVER_ERROR).await;
+        let webhook = MockServer::start().await;
+        let config = Config {
+            max_retries: 0,
+            alert_webhook: Some(format!("{}/alert", webhook.endpoint())),
+            ..test_config(server.endpoint())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(queued_aggregator(3, 10), Arc::new(config));
+        
+        assert!(client.flush_end_of_invocation().await.is_err());
+        
+        let alerts = webhook.requests().await;
+        assert_eq!(alerts.len(), 1);
+        let summary: serde_json::Value = serde_json::from_slice(&alerts[0].body).unwrap();
+        assert_eq!(summary["failed_batches"], 1);
+        assert!(summary["last_error"].as_str().unwrap().contains("500"));
+    }
+    
+    #[tokio::test]
+    async fn test_flushes_reuse_connection_after_idle_gap() {
+        // Connections opened for two flushes 300ms apart
+        async fn connections_for(keepalive_idle_ms: u64) -> usize {
+            let server = MockServer::start().await;
+            let aggregator = queued_aggregator(1, 10);
+            let config = Config {
+                keepalive_idle_ms,
+                ..test_config(server.endpoint())
+            };
+            
+            let mut client = ExtensionClient::new("test-extension".to_string());
+            client.set_telemetry_components(Arc::clone(&aggregator), Arc::new(config));
+            
+            client.flush_end_of_invocation().await.unwrap();
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            aggregator.lock().await.add_batch(vec![TelemetryEvent {
+                time: chrono::Utc::now(),
+                event_type: "function".to_string(),
+                record: serde_json::json!("after idle"),
+                request_id: None,
+            }]);
+            client.flush_end_of_invocation().await.unwrap();
+            
+            assert_eq!(server.request_count().await, 2);
+            server.connection_count()
+        }
+        
+        assert_eq!(connections_for(5000).await, 1);
+        // An idle timeout shorter than the gap closes the connection in between
+        assert_eq!(connections_for(100).await, 2);
+    }
+    
+    #[tokio::test]
+    async fn test_pool_max_idle_per_host() {
+        let server = MockServer::start().await;
+        server.set_delay(Duration::from_millis(50)).await;
+        let config = Config {
+            pool_max_idle_per_host: 4,
+            ..test_config(server.endpoint())
+        };
+        let client = ExtensionClient::build_flush_client(&config, Duration::from_secs(5)).unwrap();
+        let send_concurrently = |count: usize| {
+            let requests = (0..count).map(|_| {
+                crate::openobserve::send_batch_to_openobserve(&client, &config, b"[]")
+            });
+            futures::future::join_all(requests)
+        };
+        
+        // Twice as many requests in flight as the pool keeps, so four connections are dropped
+        for result in send_concurrently(8).await {
+            result.unwrap();
+        }
+        assert_eq!(server.connection_count(), 8);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        
+        // Later waves up to the pool size reuse the kept connections
+        for _ in 0..3 {
+            for result in send_concurrently(4).await {
+                result.unwrap();
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert_eq!(server.connection_count(), 8);
+        assert_eq!(server.request_count().await, 20);
+    }
+    
+    #[tokio::test]
+    async fn test_first_event_times_out_with_diagnostic() {
+        let runtime_api = MockServer::start().await;
+        runtime_api.set_delay(Duration::from_secs(30)).await;
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.runtime_api_endpoint = runtime_api.endpoint();
+        client.extension_id = Some("test-extension-id".to_string());
+        
+        let started = Instant::now();
+        let error = client.first_event(Duration::from_millis(100)).await.unwrap_err();
+        
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(error.to_string().contains("AWS_LAMBDA_RUNTIME_API"));
+    }
+    
+    #[tokio::test]
+    async fn test_next_event_retries_transient_errors() {
+        let runtime_api = MockServer::start().await;
+        runtime_api.push_status(http::StatusCode::INTERNAL_SERVER_ERROR).await;
+        runtime_api.push_response(
+            http::StatusCode::OK,
+            r#"{"eventType": "INVOKE", "requestId": "req-1", "deadlineMs": 1700000000000}"#,
+        ).await;
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.runtime_api_endpoint = runtime_api.endpoint();
+        client.extension_id = Some("test-extension-id".to_string());
+        
+        let event = client.next_event_with_retries(3).await.unwrap();
+        assert!(matches!(event, NextEventResponse::Invoke { request_id, .. } if request_id == "req-1"));
+        assert_eq!(runtime_api.request_count().await, 2);
+        
+        // A persistent failure still gives up once the budget is spent
+        runtime_api.set_status(http::StatusCode::INTERNAL_SERVER_ERROR).await;
+        assert!(client.next_event_with_retries(2).await.is_err());
+        assert_eq!(runtime_api.request_count().await, 5);
+    }
+    
+    #[tokio::test]
+    async fn test_flush_propagates_invoke_trace_id() {
+        let trace_id = "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1";
+        let runtime_api = MockServer::start().await;
+        runtime_api.push_response(
+            http::StatusCode::OK,
+            &format!(r#"{{"eventType": "INVOKE", "requestId": "req-1", "deadlineMs": 1700000000000,
+                "tracing": {{"type": "X-Amzn-Trace-Id", "value": "{trace_id}"}}}}"#),
+        ).await;
+        let server = MockServer::start().await;
+        let config = Config {
+            propagate_xray: true,
+            ..test_config(server.endpoint())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.runtime_api_endpoint = runtime_api.endpoint();
+        client.extension_id = Some("test-extension-id".to_string());
+        client.set_telemetry_components(queued_aggregator(1, 10), Arc::new(config));
+        
+        client.next_event().await.unwrap();
+        client.flush_end_of_invocation().await.unwrap();
+        
+        let requests = server.requests().await;
+        assert_eq!(requests[0].headers[crate::openobserve::XRAY_TRACE_HEADER], trace_id);
+    }
+    
+    #[tokio::test]
+    async fn test_self_metrics_task_enqueues_events() {
+        let aggregator = queued_aggregator(0, 100);
+        let config = Config {
+            self_metrics_interval_ms: 10,
+            ..test_config("http://127.0.0.1:1".to_string())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(Arc::clone(&aggregator), Arc::new(config));
+        client.start_self_metrics_task();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        client.stop_self_metrics_task();
+        
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.lock().await.get_batch()).unwrap();
+        assert!(!batch.is_empty());
+        assert_eq!(batch[0]["type"], "extension");
+        assert_eq!(batch[0]["record"]["event"], "self_metrics");
+        assert!(batch[0]["record"]["rss_bytes"].as_u64().unwrap() > 0);
+    }
+    
+    #[tokio::test]
+    async fn test_wall_clock_flush_without_invocations() {
+        let server = MockServer::start().await;
+        let aggregator = queued_aggregator(3, 10);
+        let config = Config {
+            wall_clock_flush_ms: 50,
+            ..test_config(server.endpoint())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(Arc::clone(&aggregator), Arc::new(config));
+        assert_eq!(client.current_strategy, FlushingStrategy::EndOfInvocation);
+        client.start_wall_clock_flush_task();
+        
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(server.request_count().await, 1);
+        assert!(aggregator.lock().await.is_empty());
+        
+        // The next tick picks up events buffered since, with no invocation in between
+        aggregator.lock().await.add_batch(vec![TelemetryEvent {
+            time: chrono::Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!("late log"),
+            request_id: None,
+        }]);
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(server.request_count().await, 2);
+        
+        // A tick that lands during another flush is skipped rather than flushing twice
+        aggregator.lock().await.add_batch(vec![TelemetryEvent {
+            time: chrono::Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!("held log"),
+            request_id: None,
+        }]);
+        let held = client.flush_lock.lock().await;
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(server.request_count().await, 2);
+        drop(held);
+        
+        client.stop_wall_clock_flush_task();
+    }
+    
+    #[tokio::test]
+    async fn test_time_to_first_flush() {
+        let server = MockServer::start().await;
+        let config = Arc::new(test_config(server.endpoint()));
+        
+        // Flushing an empty buffer delivers nothing, so it doesn't count
+        let mut idle_client = ExtensionClient::new("test-extension".to_string());
+        idle_client.set_telemetry_components(queued_aggregator(0, 10), Arc::clone(&config));
+        idle_client.flush_end_of_invocation().await.unwrap();
+        assert_eq!(idle_client.time_to_first_flush(), None);
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(queued_aggregator(5, 10), config);
+        assert_eq!(client.time_to_first_flush(), None);
+        
+        client.flush_end_of_invocation().await.unwrap();
+        let first = client.time_to_first_flush().unwrap();
+        assert!(first < Duration::from_secs(5));
+        
+        // Later flushes don't move it
+        client.flush_end_of_invocation().await.unwrap();
+        assert_eq!(client.time_to_first_flush(), Some(first));
+    }
+    
+    #[tokio::test]
+    async fn test_recent_invocations_are_capped() {
+        let server = MockServer::start().await;
+        let config = Config {
+            max_recent_invocations: 100,
+            ..test_config(server.endpoint())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(queued_aggregator(0, 10), Arc::new(config));
+        
+        for _ in 0..10_000 {
+            client.record_invoke(None).await;
+        }
+        assert_eq!(client.recent_invocations.len(), 100);
+        assert_eq!(client.invocation_count, 10_000);
+        // The bounded sample still shows a high invocation rate
+        assert_eq!(client.current_strategy, FlushingStrategy::Continuous);
+        
+        assert!(client.stop_continuous_flush(Duration::from_millis(100)).await);
+    }
+    
+    #[tokio::test]
+    async fn test_pinned_flush_strategy() {
+        let server = MockServer::start().await;
+        let config = Config {
+            flush_strategy: FlushStrategy::Continuous,
+            ..test_config(server.endpoint())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(queued_aggregator(0, 10), Arc::new(config));
+        
+        // A single invocation would normally stay on end-of-invocation flushing
+        client.recent_invocations.push_back(Instant::now());
+        client.update_flushing_strategy().await.unwrap();
+        assert_eq!(client.current_strategy, FlushingStrategy::Continuous);
+        assert!(client.continuous_flush_task.is_some());
+        
+        // Neither idleness nor low frequency moves it off the pinned strategy
+        client.last_invocation_time = Instant::now() - Duration::from_secs(LONG_RUNNING_THRESHOLD_SECS + 1);
+        client.update_flushing_strategy().await.unwrap();
+        assert_eq!(client.current_strategy, FlushingStrategy::Continuous);
+        
+        assert!(client.stop_continuous_flush(Duration::from_millis(100)).await);
+    }
+    
+    #[tokio::test]
+    async fn test_min_flush_interval_spaces_requests() {
+        let server = MockServer::start().await;
+        let aggregator = queued_aggregator(0, 10);
+        let config = Config {
+            min_flush_interval_ms: 100,
+            ..test_config(server.endpoint())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(Arc::clone(&aggregator), Arc::new(config));
+        for i in 0..3 {
+            aggregator.lock().await.add_batch(vec![TelemetryEvent {
+                time: chrono::Utc::now(),
+                event_type: "function".to_string(),
+                record: serde_json::json!(format!("burst {i}")),
+                request_id: None,
+            }]);
+            client.flush_end_of_invocation().await.unwrap();
+        }
+        // An empty flush doesn't take a slot
+        client.flush_end_of_invocation().await.unwrap();
+        
+        let requests = server.requests().await;
+        assert_eq!(requests.len(), 3);
+        for pair in requests.windows(2) {
+            let spacing = pair[1].received_at.duration_since(pair[0].received_at);
+            // Allow for the send itself being quicker the second time
+            assert!(spacing >= Duration::from_millis(90), "requests {}ms apart", spacing.as_millis());
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_flush_on_thaw_after_freeze_gap() {
+        let server = MockServer::start().await;
+        let aggregator = queued_aggregator(3, 10);
+        let config = Config {
+            freeze_gap_secs: 60,
+            ..test_config(server.endpoint())
+        };
+        
+        let mut client = ExtensionClient::new("test-extension".to_string());
+        client.set_telemetry_components(Arc::clone(&aggregator), Arc::new(config));
+        
+        // Back-to-back invokes leave the buffer to the normal strategy
+        client.record_invoke(None).await;
+        assert_eq!(server.request_count().await, 0);
+        
+        // The sandbox was frozen for longer than the gap
+        client.last_invocation_time = Instant::now() - Duration::from_secs(61);
+        client.record_invoke(None).await;
+        assert_eq!(server.request_count().await, 1);
+        assert!(aggregator.lock().await.is_empty());
+    }
+    
+    #[test]
+    fn test_flush_without_components_warns() {
+        let client = ExtensionClient::new("test-extension".to_string());
+        
+        // Debug builds assert on top of the warning; release builds only warn
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            client.warn_missing_components("end-of-invocation flush");
+        }));
+        
+        assert_eq!(result.is_err(), cfg!(debug_assertions));
+        assert!(client.missing_components_warned.load(Ordering::Relaxed));
+    }
+}
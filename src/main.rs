@@ -4,12 +4,20 @@ use std::sync::Arc;
 use std::time::Duration;
 use tokio::time::Instant;
 use tracing::{debug, error, info, warn};
+use std::sync::atomic::{AtomicU64, Ordering};
 use tracing_subscriber::{EnvFilter, fmt::format::Writer, fmt::FormatEvent, fmt::FormatFields};
+use tracing_subscriber::layer::{Context, Filter, SubscriberExt};
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
 
 mod config;
+mod error;
 mod extension;
 mod telemetry;
 mod openobserve;
+mod sigv4;
+#[cfg(test)]
+mod test_support;
 
 use config::Config;
 use extension::{ExtensionClient, NextEventResponse, FlushingStrategy};
@@ -18,18 +26,19 @@ use telemetry::{TelemetrySubscriber};
 const EXTENSION_NAME: &str = "o2-lambda-extension";
 const TELEMETRY_SUBSCRIBER_PORT: u16 = 8080;
 
+// Counters are atomic so the O2_STATS_INTERVAL_SECS task can read them mid-run
 struct ExtensionMetrics {
     start_time: Instant,
-    invocations_processed: u64,
-    logs_processed: u64,
+    invocations_processed: AtomicU64,
+    logs_processed: AtomicU64,
 }
 
 impl ExtensionMetrics {
     fn new() -> Self {
         Self {
             start_time: Instant::now(),
-            invocations_processed: 0,
-            logs_processed: 0,
+            invocations_processed: AtomicU64::new(0),
+            logs_processed: AtomicU64::new(0),
         }
     }
 
@@ -38,12 +47,23 @@ impl ExtensionMetrics {
         info!(
             "Extension stats: uptime={:.2}s, invocations={}, logs={}",
             uptime.as_secs_f64(),
-            self.invocations_processed,
-            self.logs_processed,
+            self.invocations_processed.load(Ordering::Relaxed),
+            self.logs_processed.load(Ordering::Relaxed),
         );
     }
 }
 
+// Log stats every `period` during the run, rather than only at shutdown
+fn start_stats_task(metrics: Arc<ExtensionMetrics>, period: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval_at(Instant::now() + period, period);
+        loop {
+            interval.tick().await;
+            metrics.log_stats();
+        }
+    })
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Parse command line arguments
@@ -53,15 +73,23 @@ async fn main() -> Result<()> {
     if args.len() > 1 {
         match args[1].as_str() {
             "--health-check" | "-h" => {
-                init_logging();
-                
-                let config = Config::from_env().map_err(|e| {
+                let config = Config::from_env();
+                init_logging(config.as_ref().map_or(1, |config| config.self_log_sample));
+                let config = config.map_err(|e| {
                     error!("Configuration error: {}", e);
                     e
                 })?;
                 
                 return health_check(&config).await;
             }
+            "--diagnose" => {
+                return diagnose().await;
+            }
+            "--parse-file" => {
+                let path = args.get(2)
+                    .ok_or_else(|| anyhow!("--parse-file requires a path argument"))?;
+                return parse_file(path);
+            }
             "--version" | "-v" => {
                 println!("{} v{}", EXTENSION_NAME, env!("CARGO_PKG_VERSION"));
                 return Ok(());
@@ -78,11 +106,11 @@ async fn main() -> Result<()> {
         }
     }
 
-    // Normal extension mode
-    init_logging();
-
-    // Load configuration
-    let config = Arc::new(Config::from_env().map_err(|e| {
+    // Normal extension mode. Logging takes O2_SELF_LOG_SAMPLE from the configuration, so a
+    // configuration error is logged once logging is up, with no sampling.
+    let config = Config::from_env();
+    init_logging(config.as_ref().map_or(1, |config| config.self_log_sample));
+    let config = Arc::new(config.map_err(|e| {
         error!("Configuration error: {}", e);
         e
     })?);
@@ -91,10 +119,10 @@ async fn main() -> Result<()> {
     // Log startup sequence
 
     // Initialize extension metrics
-    let mut metrics = ExtensionMetrics::new();
+    let metrics = Arc::new(ExtensionMetrics::new());
 
     // Run the extension
-    match run_extension(config, &mut metrics).await {
+    match run_extension(config, Arc::clone(&metrics)).await {
         Ok(_) => {
             metrics.log_stats();
             Ok(())
@@ -107,14 +135,14 @@ async fn main() -> Result<()> {
     }
 }
 
-async fn run_extension(config: Arc<Config>, metrics: &mut ExtensionMetrics) -> Result<()> {
+async fn run_extension(config: Arc<Config>, metrics: Arc<ExtensionMetrics>) -> Result<()> {
     // Create extension client
     let mut extension_client = ExtensionClient::new(EXTENSION_NAME.to_string());
     
     // We'll set telemetry components after creating them
 
     // Register extension
-    let registration = extension_client.register().await?;
+    let registration = report_init_error(&config, "register", extension_client.register().await).await?;
 
     let extension_id = registration.extension_id.clone();
 
@@ -122,56 +150,162 @@ async fn run_extension(config: Arc<Config>, metrics: &mut ExtensionMetrics) -> R
     
     // Create aggregator
     let aggregator = Arc::new(tokio::sync::Mutex::new(
-        telemetry::TelemetryAggregator::new(
-            config.max_buffer_size_bytes(),
-            100, // max batch entries
-        )
+        telemetry::TelemetryAggregator::from_config(&config)
     ));
 
+    if config.include_arn {
+        match config.function_arn(registration.account_id.as_deref()) {
+            Some(arn) => aggregator.lock().await.set_function_arn(Some(arn)),
+            None => debug!("Function ARN not known yet, events omit function_arn until an invoke provides it"),
+        }
+    }
+
     // Set up telemetry subscriber
     let mut telemetry_subscriber = TelemetrySubscriber::new(TELEMETRY_SUBSCRIBER_PORT, Arc::clone(&aggregator));
+    telemetry_subscriber.set_concurrency_limit(config.telemetry_concurrency);
+    telemetry_subscriber.set_callback_host(&config.telemetry_callback_host);
+    telemetry_subscriber.set_subscribe_retries(&config);
+    if config.immediate_forward {
+        telemetry_subscriber.set_immediate_forward(Arc::clone(&config))?;
+    }
     
-    telemetry_subscriber.start().await?;
+    report_init_error(&config, "telemetry_listener", telemetry_subscriber.start().await).await?;
     
-    telemetry_subscriber.subscribe_to_telemetry_api(&extension_id).await?;
+    let subscribed = telemetry_subscriber.subscribe_to_telemetry_api(&extension_id).await;
+    report_init_error(&config, "subscribe", subscribed).await?;
+
+    // Confirm the extension is live in this instance before the first invoke
+    if config.emit_startup_event {
+        let client = openobserve::client_builder(&config)
+            .timeout(Duration::from_millis(config.request_timeout_ms))
+            .build()?;
+        match openobserve::send_startup_event(&client, &config, EXTENSION_NAME).await {
+            Ok(_) => debug!("📣 Startup event sent"),
+            Err(e) => warn!("⚠️ Failed to send startup event: {}", e),
+        }
+    }
 
     // Note: Using Telemetry API to capture logs, metrics, and traces
     // AWS Lambda allows only one subscription per extension
     
     // Note: No async OpenObserve client needed - using synchronous flush in extension.rs
     
+    if config.flush_only_on_shutdown {
+        warn!(
+            "⚠️ O2_FLUSH_ONLY_ON_SHUTDOWN is set: events are held until SHUTDOWN, up to O2_MAX_BUFFER_SIZE_MB ({} MB), and lost if the instance is stopped without one",
+            config.max_buffer_size_mb
+        );
+        if config.buffer_warn_percent == 0 {
+            warn!("⚠️ O2_BUFFER_WARN_PERCENT is 0, so nothing will warn as the buffer fills up");
+        }
+    }
+    
     // Set telemetry components in extension client for SHUTDOWN handling
     extension_client.set_telemetry_components(
         Arc::clone(&aggregator),
         Arc::clone(&config),
     );
 
+    extension_client.start_self_metrics_task();
+    extension_client.start_wall_clock_flush_task();
+    let stats_task = (config.stats_interval_secs > 0).then(|| {
+        start_stats_task(Arc::clone(&metrics), Duration::from_secs(config.stats_interval_secs))
+    });
+
     // Main extension lifecycle loop - SHUTDOWN flush now happens in extension.rs
     let result = extension_lifecycle_loop(
         &mut extension_client,
-        metrics,
+        &mut telemetry_subscriber,
+        &config,
+        &metrics,
     )
     .await;
+    
+    // Final stats are logged by main once the run ends
+    if let Some(stats_task) = stats_task {
+        stats_task.abort();
+    }
 
     // Simplified shutdown - the flush already happened during SHUTDOWN event
     
     // Stop accepting new telemetry requests
     telemetry_subscriber.shutdown().await;
+    extension_client.stop_self_metrics_task();
+    extension_client.stop_wall_clock_flush_task();
     
-    // Give time for final processing
-    tokio::time::sleep(Duration::from_millis(200)).await;
+    let (excluded, duplicates, oversized, peak_buffered_bytes) = {
+        let guard = aggregator.lock().await;
+        (guard.excluded_count(), guard.duplicate_count(), guard.oversized_count(), guard.peak_buffered_bytes())
+    };
+    info!("Peak telemetry buffer: {} bytes", peak_buffered_bytes);
+    match extension_client.time_to_first_flush() {
+        Some(elapsed) => info!("time_to_first_flush_ms={}", elapsed.as_millis()),
+        None => info!("time_to_first_flush_ms=none (no events were flushed)"),
+    }
+    if let Some(timings) = extension_client.flush_timing_totals() {
+        info!(
+            "Ingest request time: requests={}, headers={}ms, body={}ms, total={}ms",
+            timings.requests,
+            timings.headers.as_millis(),
+            timings.body.as_millis(),
+            timings.total.as_millis(),
+        );
+    }
+    if let Some(batches) = extension_client.batch_size_percentiles() {
+        info!(
+            "Batch sizes: batches={}, p50={}B/{} events, p95={}B/{} events",
+            batches.batches,
+            batches.p50_bytes,
+            batches.p50_events,
+            batches.p95_bytes,
+            batches.p95_events,
+        );
+    }
+    if excluded > 0 {
+        info!("Dropped {} events matching O2_EXCLUDE_PATTERNS", excluded);
+    }
+    if duplicates > 0 {
+        info!("Dropped {} redelivered telemetry events", duplicates);
+    }
+    if oversized > 0 {
+        info!("Dropped {} events larger than a batch", oversized);
+    }
+    
+    // Let an in-flight continuous flush finish rather than sleeping blindly
+    extension_client
+        .stop_continuous_flush(Duration::from_millis(config.shutdown_grace_ms))
+        .await;
 
     result
 }
 
+// Leave a trace in OpenObserve when startup fails, as the extension exits before any flush
+async fn report_init_error<T>(config: &Config, stage: &str, result: Result<T>) -> Result<T> {
+    if let Err(e) = &result {
+        openobserve::send_init_error_event(config, EXTENSION_NAME, stage, &e.to_string()).await;
+    }
+    result
+}
+
 async fn extension_lifecycle_loop(
     extension_client: &mut ExtensionClient,
-    metrics: &mut ExtensionMetrics,
+    telemetry_subscriber: &mut TelemetrySubscriber,
+    config: &Config,
+    metrics: &ExtensionMetrics,
 ) -> Result<()> {
 
+    let mut waiting_for_first_event = true;
+
     loop {
-        // Get the next event from Lambda
-        let event = extension_client.next_event().await?;
+        // Get the next event from Lambda, bounding only the first wait if configured
+        let event = if waiting_for_first_event && config.first_event_timeout_ms > 0 {
+            extension_client
+                .first_event(Duration::from_millis(config.first_event_timeout_ms))
+                .await?
+        } else {
+            extension_client.next_event_with_retries(config.next_event_max_errors).await?
+        };
+        waiting_for_first_event = false;
 
         match event {
             NextEventResponse::Invoke { 
@@ -179,12 +313,20 @@ async fn extension_lifecycle_loop(
                 deadline_ms, 
                 ..
             } => {
-                metrics.invocations_processed += 1;
+                metrics.invocations_processed.fetch_add(1, Ordering::Relaxed);
                 
+                // Recover from a dropped or silently failed telemetry subscription
+                if config.telemetry_resubscribe_window_ms > 0 {
+                    let window = Duration::from_millis(config.telemetry_resubscribe_window_ms);
+                    if let Err(e) = telemetry_subscriber.resubscribe_if_silent(window).await {
+                        warn!("⚠️ Telemetry re-subscribe failed: {}", e);
+                    }
+                }
 
                 // Handle the invoke event  
                 handle_invoke_event(
                     extension_client,
+                    config,
                     metrics,
                     &request_id,
                     deadline_ms,
@@ -206,7 +348,8 @@ async fn extension_lifecycle_loop(
 
 async fn handle_invoke_event(
     extension_client: &mut ExtensionClient,
-    _metrics: &mut ExtensionMetrics,
+    config: &Config,
+    _metrics: &ExtensionMetrics,
     request_id: &str,
     _deadline_ms: u64,
 ) -> Result<()> {
@@ -214,8 +357,17 @@ async fn handle_invoke_event(
     
     debug!("Processing INVOKE event for {}", request_id);
     
-    // Just wait a bit to simulate function execution
-    tokio::time::sleep(Duration::from_millis(100)).await;
+    // Optional delay to simulate function execution when testing locally
+    if config.simulated_invoke_delay_ms > 0 {
+        tokio::time::sleep(Duration::from_millis(config.simulated_invoke_delay_ms)).await;
+    }
+
+    // Drain the buffer early when the function is close to its memory limit or a flush-on event arrived
+    match extension_client.flush_if_requested().await {
+        Ok(events_sent) if events_sent > 0 => debug!("📤 Immediate flush: {} events sent", events_sent),
+        Ok(_) => {}
+        Err(e) => warn!("⚠️ Immediate flush failed: {}", e),
+    }
 
     // Apply smart flushing strategy
     let events_flushed = match extension_client.current_strategy {
@@ -237,6 +389,7 @@ async fn handle_invoke_event(
             // High-frequency: continuous flushing handled by background task
             0 // No action needed, background task handles flushing
         }
+        FlushingStrategy::ShutdownOnly => 0, // Flushed by the SHUTDOWN path only
     };
     
     if events_flushed > 0 {
@@ -254,7 +407,7 @@ async fn handle_invoke_event(
 }
 
 async fn handle_shutdown_event(
-    _metrics: &mut ExtensionMetrics,
+    _metrics: &ExtensionMetrics,
     _deadline_ms: u64,
 ) -> Result<()> {
     let shutdown_start = Instant::now();
@@ -301,7 +454,38 @@ where
     }
 }
 
-fn init_logging() {
+// Keeps only every Nth DEBUG/TRACE line (O2_SELF_LOG_SAMPLE), so per-batch debug logs
+// don't flood the function's logs; INFO and above always pass
+struct DebugLogSampler {
+    every: u64,
+    seen: AtomicU64,
+}
+
+impl DebugLogSampler {
+    fn new(every: u64) -> Self {
+        Self { every: every.max(1), seen: AtomicU64::new(0) }
+    }
+}
+
+impl<S> Filter<S> for DebugLogSampler {
+    fn enabled(&self, _meta: &tracing::Metadata<'_>, _cx: &Context<'_, S>) -> bool {
+        true
+    }
+    
+    // Decided per event rather than cached per callsite
+    fn callsite_enabled(&self, _meta: &'static tracing::Metadata<'static>) -> tracing::subscriber::Interest {
+        tracing::subscriber::Interest::sometimes()
+    }
+    
+    fn event_enabled(&self, event: &tracing::Event<'_>, _cx: &Context<'_, S>) -> bool {
+        if *event.metadata().level() < tracing::Level::DEBUG || self.every == 1 {
+            return true;
+        }
+        self.seen.fetch_add(1, Ordering::Relaxed).is_multiple_of(self.every)
+    }
+}
+
+fn init_logging(sample_every: u64) {
     let log_level = env::var("LOG_LEVEL").unwrap_or_else(|_| "INFO".to_string());
     
     // Create filter that suppresses debug messages from HTTP clients
@@ -319,8 +503,7 @@ fn init_logging() {
                 .add_directive("rustls=warn".parse().unwrap())
         });
 
-    tracing_subscriber::fmt()
-        .with_env_filter(filter)
+    let fmt_layer = tracing_subscriber::fmt::layer()
         .with_target(false)
         .with_thread_ids(false)
         .with_thread_names(false)
@@ -328,6 +511,11 @@ fn init_logging() {
         .with_line_number(false)
         .without_time()
         .event_format(OpenObserveFormatter)
+        .with_filter(DebugLogSampler::new(sample_every));
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt_layer)
         .init();
 
 }
@@ -341,6 +529,8 @@ fn print_help() {
     println!();
     println!("COMMANDS:");
     println!("    --health-check, -h    Run health check (test config and OpenObserve connectivity)");
+    println!("    --diagnose            Run step-by-step connectivity checks (config, DNS, TCP, TLS, test event)");
+    println!("    --parse-file <path>   Parse a Telemetry API JSON payload from a file and print the resulting batches");
     println!("    --version, -v         Show version information");
     println!("    --help               Show this help message");
     println!();
@@ -352,7 +542,10 @@ fn print_help() {
     println!("    Optional:");
     println!("        O2_ENDPOINT              OpenObserve API endpoint (default: https://api.openobserve.ai)");
     println!("        O2_STREAM               Log stream name (default: default)");
+    println!("        O2_AUTH_MODE            header (default) or sigv4 to sign requests with the execution role");
+    println!("        O2_CONTENT_TYPE         Content-Type header for ingest requests (default: application/json)");
     println!("        LOG_LEVEL               Log level (default: INFO)");
+    println!("        O2_SELF_LOG_SAMPLE      Keep only every Nth DEBUG/TRACE log line (default: 1, all)");
     println!();
     println!("EXAMPLES:");
     println!("    # Run health check");
@@ -366,32 +559,105 @@ fn print_help() {
     println!("For more information, visit: https://docs.openobserve.ai");
 }
 
+// Offline check of telemetry parsing and aggregation - no network involved
+fn parse_file(path: &str) -> Result<()> {
+    let contents = std::fs::read(path)
+        .map_err(|e| anyhow!("Failed to read {}: {}", path, e))?;
+    
+    let events = telemetry::parse_telemetry_events(&contents)?;
+    
+    // Shape records with the same settings the extension would use; a bad one is an error
+    let config = Config::from_env_offline()
+        .map_err(|e| anyhow!("Configuration error: {}", e))?;
+    let mut aggregator = telemetry::TelemetryAggregator::from_config(&config);
+    aggregator.add_batch(events);
+    
+    loop {
+        let batch = aggregator.get_batch();
+        if batch.is_empty() {
+            break;
+        }
+        println!("{}", preview_batch(&config, &batch));
+    }
+    
+    Ok(())
+}
+
+// A batch as printed for inspection, indented when O2_PRETTY_JSON is set
+fn preview_batch(config: &Config, batch: &[u8]) -> String {
+    if config.pretty_json && config.output_encoding == config::OutputEncoding::Json {
+        if let Ok(value) = serde_json::from_slice::<serde_json::Value>(batch) {
+            if let Ok(pretty) = serde_json::to_string_pretty(&value) {
+                return pretty;
+            }
+        }
+    }
+    String::from_utf8_lossy(batch).into_owned()
+}
+
 // Health check function for monitoring
 pub async fn health_check(config: &Config) -> Result<()> {
     
     // Test configuration
     config.validate().map_err(|e| anyhow!("Config validation failed: {}", e))?;
     
-    // Test OpenObserve connectivity
-    let client = reqwest::Client::builder()
+    // Test OpenObserve connectivity, without writing to the stream if a probe path is set
+    // or O2_HEALTH_CHECK_NO_WRITE asks for an empty batch
+    match config.health_path.as_deref() {
+        Some(health_path) => probe_health_path(config, health_path).await,
+        None if config.health_check_no_write => post_to_ingest(config, empty_batch(config)).await,
+        None => send_test_event(config).await,
+    }
+}
+
+// A batch with no events in the configured encoding; ingesting it stores nothing
+fn empty_batch(config: &Config) -> Vec<u8> {
+    match config.output_encoding {
+        config::OutputEncoding::Json => b"[]".to_vec(),
+        config::OutputEncoding::Csv => format!("{}\n", config.csv_columns.join(",")).into_bytes(),
+    }
+}
+
+// GET O2_HEALTH_PATH on the endpoint and expect a 2xx
+async fn probe_health_path(config: &Config, health_path: &str) -> Result<()> {
+    let client = openobserve::client_builder(config)
         .timeout(Duration::from_millis(10000))
         .connect_timeout(Duration::from_millis(3000))
-        .danger_accept_invalid_certs(true) // For testing with mock servers
-        .local_address(None) // Let system choose
         .build()?;
     
+    let url = config.endpoint_url(health_path);
+    let (status, _) = openobserve::execute_request(&client, config, client.get(&url)).await?;
+    
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(anyhow!("Health check failed - {} returned status: {}", url, status))
+    }
+}
+
+// Post a single test event to the configured destination
+async fn send_test_event(config: &Config) -> Result<()> {
     let test_event = openobserve::create_test_event();
+    post_to_ingest(config, serde_json::to_vec(&[test_event])?).await
+}
+
+// Post a body to the ingest URL with the configured auth, expecting a 2xx
+async fn post_to_ingest(config: &Config, body: Vec<u8>) -> Result<()> {
+    let client = openobserve::client_builder(config)
+        .timeout(Duration::from_millis(10000))
+        .connect_timeout(Duration::from_millis(3000))
+        .danger_accept_invalid_certs(true) // For testing with mock servers
+        .build()?;
+    
     let url = config.openobserve_url();
     
-    let response = client
+    let request = client
         .post(&url)
-        .header("Authorization", &config.o2_authorization_header)
-        .header("Content-Type", "application/json")
-        .json(&[test_event])
-        .send()
-        .await?;
+        .header("Content-Type", &config.content_type)
+        .body(body.clone());
+    let request = openobserve::apply_auth(request, config, &url, &config.content_type, &body)?;
+    let (status, _) = openobserve::execute_request(&client, config, request).await?;
     
-    let status = response.status();
     if status.is_success() {
         Ok(())
     } else {
@@ -402,20 +668,320 @@ pub async fn health_check(config: &Config) -> Result<()> {
     }
 }
 
+// Print the outcome of one diagnose step, passing the error through on failure
+fn report_step<T>(name: &str, result: Result<T>) -> Result<T> {
+    match &result {
+        Ok(_) => println!("[PASS] {name}"),
+        Err(e) => println!("[FAIL] {name}: {e}"),
+    }
+    result
+}
+
+// End-to-end connectivity checks for support, stopping at the first failure
+async fn diagnose() -> Result<()> {
+    let connect_timeout = Duration::from_millis(3000);
+    
+    let config = report_step(
+        "Configuration",
+        Config::from_env().map_err(|e| anyhow!("{}", e)),
+    )?;
+    
+    if let Some(socket_path) = config.unix_socket_path() {
+        println!("[SKIP] DNS resolution (unix socket endpoint)");
+        report_step(
+            "Unix socket connect",
+            tokio::net::UnixStream::connect(socket_path).await.map_err(|e| anyhow!("{}: {}", socket_path, e)),
+        )?;
+        println!("[SKIP] TLS handshake (unix socket endpoint)");
+    } else {
+        let endpoint = url::Url::parse(&config.o2_endpoint)?;
+        let host = endpoint.host_str().unwrap_or_default().to_string();
+        let port = endpoint.port_or_known_default().unwrap_or(443);
+        
+        let addrs: Vec<_> = report_step(
+            &format!("DNS resolution ({host})"),
+            tokio::net::lookup_host((host.as_str(), port))
+                .await
+                .map(|addrs| addrs.filter(|addr| config.ip_version.allows(addr)).collect::<Vec<_>>())
+                .map_err(|e| anyhow!("{}", e))
+                .and_then(|addrs| {
+                    if addrs.is_empty() { Err(anyhow!("no addresses found")) } else { Ok(addrs) }
+                }),
+        )?;
+        
+        report_step(
+            &format!("TCP connect ({})", addrs[0]),
+            tokio::time::timeout(connect_timeout, tokio::net::TcpStream::connect(addrs[0]))
+                .await
+                .map_err(|_| anyhow!("timed out after {}ms", connect_timeout.as_millis()))
+                .and_then(|connected| connected.map_err(|e| anyhow!("{}", e))),
+        )?;
+        
+        if endpoint.scheme() == "https" {
+            // Any HTTP response means the TLS handshake completed
+            let client = openobserve::client_builder(&config)
+                .connect_timeout(connect_timeout)
+                .timeout(Duration::from_millis(10000))
+                .build()?;
+            report_step(
+                "TLS handshake",
+                client.head(endpoint.as_str()).send().await.map(|_| ()).map_err(|e| anyhow!("{}", e)),
+            )?;
+        } else {
+            println!("[SKIP] TLS handshake (plain HTTP endpoint)");
+        }
+    }
+    
+    report_step("Send test event", send_test_event(&config).await)?;
+    
+    println!("All checks passed");
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use test_support::CapturedLogs;
+    
+    #[test]
+    fn test_debug_log_sampling() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(EnvFilter::new("debug"))
+            .with(
+                tracing_subscriber::fmt::layer()
+                    .without_time()
+                    .with_writer(move || writer.clone())
+                    .with_filter(DebugLogSampler::new(10)),
+            );
+        
+        tracing::subscriber::with_default(subscriber, || {
+            for i in 0..100 {
+                debug!("📦 batch {}", i);
+            }
+            for i in 0..3 {
+                info!("stats {}", i);
+            }
+        });
+        
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(output.lines().filter(|line| line.contains("DEBUG")).count(), 10);
+        assert_eq!(output.lines().filter(|line| line.contains("INFO")).count(), 3);
+    }
     
     #[test]
     fn test_extension_metrics() {
-        let mut metrics = ExtensionMetrics::new();
+        let metrics = ExtensionMetrics::new();
         
-        assert_eq!(metrics.invocations_processed, 0);
-        assert_eq!(metrics.logs_processed, 0);
+        assert_eq!(metrics.invocations_processed.load(Ordering::Relaxed), 0);
+        assert_eq!(metrics.logs_processed.load(Ordering::Relaxed), 0);
         // No flush operations in simplified implementation
         
-        metrics.invocations_processed += 1;
-        assert_eq!(metrics.invocations_processed, 1);
+        metrics.invocations_processed.fetch_add(1, Ordering::Relaxed);
+        assert_eq!(metrics.invocations_processed.load(Ordering::Relaxed), 1);
+    }
+    
+    #[tokio::test]
+    async fn test_periodic_stats_logging() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_subscriber::fmt::layer().without_time().with_writer(move || writer.clone()));
+        // The test runtime is single-threaded, so the spawned task logs through this default
+        let _guard = tracing::subscriber::set_default(subscriber);
+        
+        let metrics = Arc::new(ExtensionMetrics::new());
+        metrics.invocations_processed.fetch_add(3, Ordering::Relaxed);
+        let task = start_stats_task(Arc::clone(&metrics), Duration::from_millis(20));
+        tokio::time::sleep(Duration::from_millis(110)).await;
+        task.abort();
+        
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let stats_lines = output.lines().filter(|line| line.contains("Extension stats")).count();
+        assert!(stats_lines >= 2, "only {} stats lines:\n{}", stats_lines, output);
+        assert!(output.contains("invocations=3"));
+    }
+    
+    #[tokio::test]
+    async fn test_invoke_handling_has_no_artificial_delay() {
+        let config = Arc::new(Config::default());
+        let mut extension_client = ExtensionClient::new("test-extension".to_string());
+        extension_client.set_telemetry_components(
+            Arc::new(tokio::sync::Mutex::new(telemetry::TelemetryAggregator::from_config(&config))),
+            Arc::clone(&config),
+        );
+        let metrics = ExtensionMetrics::new();
+        
+        let start = Instant::now();
+        handle_invoke_event(&mut extension_client, &config, &metrics, "request-id", 0)
+            .await
+            .unwrap();
+        
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+    
+    #[tokio::test]
+    async fn test_flush_only_on_shutdown() {
+        let server = test_support::MockServer::start().await;
+        let config = Arc::new(Config {
+            o2_endpoint: server.endpoint(),
+            o2_organization_id: "test_org".to_string(),
+            o2_authorization_header: "Basic dGVzdA==".to_string(),
+            flush_only_on_shutdown: true,
+            memory_flush_percent: 50,
+            function_memory_size_mb: Some(128),
+            ..Default::default()
+        });
+        let aggregator = Arc::new(tokio::sync::Mutex::new(telemetry::TelemetryAggregator::from_config(&config)));
+        let mut extension_client = ExtensionClient::new("test-extension".to_string());
+        extension_client.set_telemetry_components(Arc::clone(&aggregator), Arc::clone(&config));
+        let metrics = ExtensionMetrics::new();
+        
+        for i in 0..5 {
+            aggregator.lock().await.add_batch(vec![telemetry::TelemetryEvent {
+                time: chrono::Utc::now(),
+                event_type: "platform.report".to_string(),
+                // Memory pressure would normally force an immediate flush
+                record: serde_json::json!({"requestId": format!("req-{i}"), "metrics": {"memorySizeMB": 128, "maxMemoryUsedMB": 120}}),
+                request_id: None,
+            }]);
+            handle_invoke_event(&mut extension_client, &config, &metrics, &format!("req-{i}"), 0)
+                .await
+                .unwrap();
+        }
+        assert_eq!(extension_client.current_strategy, FlushingStrategy::ShutdownOnly);
+        assert_eq!(server.request_count().await, 0);
+        
+        assert!(extension_client.flush_for_shutdown().await.is_ok());
+        assert_eq!(server.request_count().await, 1);
+        assert!(aggregator.lock().await.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_health_check_honours_ip_version() {
+        let server = test_support::MockServer::start().await;
+        let config = Config {
+            o2_endpoint: server.endpoint(),
+            o2_organization_id: "test_org".to_string(),
+            o2_authorization_header: "Basic dGVzdA==".to_string(),
+            ..Default::default()
+        };
+        assert!(health_check(&config).await.is_ok());
+        
+        // The mock listens on 127.0.0.1 only, so an IPv6 pin must not reach it
+        let config = Config { ip_version: config::IpVersion::V6, ..config };
+        assert!(health_check(&config).await.is_err());
+        assert_eq!(server.request_count().await, 1);
+    }
+    
+    #[tokio::test]
+    async fn test_init_error_reported_on_failed_subscription() {
+        let server = test_support::MockServer::start().await;
+        let config = Config {
+            o2_endpoint: server.endpoint(),
+            o2_organization_id: "test_org".to_string(),
+            o2_authorization_header: "Basic dGVzdA==".to_string(),
+            ..Default::default()
+        };
+        
+        let subscribed: Result<()> = Err(anyhow!("Telemetry API subscription failed with status 500: unavailable"));
+        let error = report_init_error(&config, "subscribe", subscribed).await.unwrap_err();
+        assert!(error.to_string().contains("status 500"));
+        
+        let requests = server.requests().await;
+        assert_eq!(requests.len(), 1);
+        let body: Vec<serde_json::Value> = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(body[0]["record"]["event"], "extension_init_error");
+        assert_eq!(body[0]["record"]["stage"], "subscribe");
+        assert_eq!(body[0]["record"]["error"], error.to_string());
+        
+        // Successful steps send nothing
+        report_init_error(&config, "register", Ok(())).await.unwrap();
+        assert_eq!(server.request_count().await, 1);
+    }
+    
+    #[tokio::test]
+    async fn test_health_check_uses_health_path() {
+        let server = test_support::MockServer::start().await;
+        let config = Config {
+            o2_endpoint: server.endpoint(),
+            o2_organization_id: "test_org".to_string(),
+            o2_authorization_header: "Basic dGVzdA==".to_string(),
+            health_path: Some("/healthz".to_string()),
+            ..Default::default()
+        };
+        
+        health_check(&config).await.unwrap();
+        
+        let requests = server.requests().await;
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(requests[0].uri, "/healthz");
+        assert!(requests[0].body.is_empty());
+        
+        server.set_status(http::StatusCode::SERVICE_UNAVAILABLE).await;
+        assert!(health_check(&config).await.is_err());
+        assert!(server.requests().await.iter().all(|request| request.method == "GET"));
+    }
+    
+    #[tokio::test]
+    async fn test_health_check_no_write() {
+        let server = test_support::MockServer::start().await;
+        let config = Config {
+            o2_endpoint: server.endpoint(),
+            o2_organization_id: "test_org".to_string(),
+            o2_authorization_header: "Basic dGVzdA==".to_string(),
+            health_check_no_write: true,
+            ..Default::default()
+        };
+        
+        health_check(&config).await.unwrap();
+        
+        // Authenticated like a real write, but carrying no events
+        let requests = server.requests().await;
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].uri, "/api/test_org/default/_json");
+        assert_eq!(requests[0].headers["authorization"], "Basic dGVzdA==");
+        let events: Vec<serde_json::Value> = serde_json::from_slice(&requests[0].body).unwrap();
+        assert!(events.is_empty());
+        
+        server.set_status(http::StatusCode::UNAUTHORIZED).await;
+        assert!(health_check(&config).await.is_err());
+    }
+    
+    #[tokio::test]
+    async fn test_pretty_json_preview() {
+        let server = test_support::MockServer::start().await;
+        let config = Config {
+            o2_endpoint: server.endpoint(),
+            o2_organization_id: "test_org".to_string(),
+            o2_authorization_header: "Basic dGVzdA==".to_string(),
+            pretty_json: true,
+            ..Default::default()
+        };
+        let mut aggregator = telemetry::TelemetryAggregator::from_config(&config);
+        aggregator.add_batch(vec![telemetry::TelemetryEvent {
+            time: chrono::Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!({"message": "hello"}),
+            request_id: None,
+        }]);
+        let batch = aggregator.get_batch();
+        
+        let preview = preview_batch(&config, &batch);
+        assert!(preview.starts_with("[\n  {\n"), "{preview}");
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&preview).unwrap(),
+            serde_json::from_slice::<serde_json::Value>(&batch).unwrap()
+        );
+        
+        // The request body is still compact
+        let client = reqwest::Client::new();
+        openobserve::send_batch_to_openobserve(&client, &config, &batch).await.unwrap();
+        let requests = server.requests().await;
+        assert_eq!(requests[0].body, batch);
+        assert!(!requests[0].body.contains(&b'\n'));
     }
     
     #[tokio::test]
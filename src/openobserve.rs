@@ -1,26 +1,564 @@
-use anyhow::{anyhow, Result};
 use chrono::Utc;
 use reqwest::Client;
+use sha2::{Digest, Sha256};
 use std::cmp;
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
 use tokio::time::{sleep, Duration};
 use tracing::{debug, error, warn};
 
-use crate::config::Config;
-use crate::telemetry::TelemetryEvent;
+use crate::config::{AuthMode, Config, HttpVersion, IpVersion, OutputEncoding, TlsVersion};
+use crate::error::ExtensionError;
+use crate::sigv4::SigV4Signer;
+use crate::telemetry::{MetricSample, TelemetryAggregator, TelemetryEvent};
 
-// Send JSON batch to OpenObserve with retry logic and exponential backoff
+pub const IDEMPOTENCY_KEY_HEADER: &str = "X-Idempotency-Key";
+pub const CONTENT_SHA256_HEADER: &str = "X-Content-SHA256";
+pub const XRAY_TRACE_HEADER: &str = "X-Amzn-Trace-Id";
+pub const FLUSH_STRATEGY_HEADER: &str = "X-O2-Flush-Strategy";
+const ALERT_WEBHOOK_TIMEOUT_MS: u64 = 2000;
+const INIT_ERROR_TIMEOUT_MS: u64 = 2000;
+const REMOTE_WRITE_CONTENT_TYPE: &str = "application/x-protobuf";
+const REMOTE_WRITE_VERSION: &str = "0.1.0";
+
+// Highest backoff level kept across calls (initial delay * 2^level, capped by max_retry_delay_ms)
+const MAX_BACKOFF_LEVEL: u32 = 16;
+
+// Retry backoff remembered across send calls: each retryable failure raises the
+// starting delay for later flushes, each success lowers it again one step
+pub struct AdaptiveBackoff {
+    level: AtomicU32,
+}
+
+impl AdaptiveBackoff {
+    pub const fn new() -> Self {
+        Self { level: AtomicU32::new(0) }
+    }
+    
+    pub fn starting_delay_ms(&self, config: &Config) -> u64 {
+        let level = self.level.load(Ordering::Relaxed);
+        config.initial_retry_delay_ms
+            .saturating_mul(1u64 << level)
+            .min(config.max_retry_delay_ms)
+    }
+    
+    pub fn record_failure(&self) {
+        let _ = self.level.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |level| {
+            Some((level + 1).min(MAX_BACKOFF_LEVEL))
+        });
+    }
+    
+    pub fn record_success(&self) {
+        let _ = self.level.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |level| {
+            Some(level.saturating_sub(1))
+        });
+    }
+}
+
+impl Default for AdaptiveBackoff {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Delivery state for one destination, kept by whoever sends to it
+#[derive(Default)]
+pub struct DestinationState {
+    pub backoff: AdaptiveBackoff,
+    pub timings: FlushTimings,
+    pub batch_sizes: BatchSizeHistogram,
+}
+
+// State for the primary destination and the mirror, kept apart so a mirror send
+// doesn't lower the primary's backoff
+#[derive(Default)]
+pub struct IngestState {
+    pub primary: DestinationState,
+    pub mirror: DestinationState,
+    // Settings for the mirror, built once from the config the state was created with
+    mirror_config: Option<Config>,
+}
+
+impl IngestState {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            mirror_config: config.mirror_config(),
+            ..Self::default()
+        }
+    }
+}
+
+// What a flush passes along with each batch
+#[derive(Clone, Copy)]
+pub struct SendContext<'a> {
+    pub state: &'a IngestState,
+    // Sent as X-O2-Flush-Strategy
+    pub flush_strategy: Option<&'a str>,
+    // The invocation's X-Ray trace header, from the INVOKE event (sent with O2_PROPAGATE_XRAY)
+    pub trace_id: Option<&'a str>,
+    // The batch's sequence number, keying it apart from other batches with the same content
+    pub batch_seq: Option<u64>,
+}
+
+// Most recent batches kept for size percentiles; older samples are overwritten
+const BATCH_SIZE_SAMPLES: usize = 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchSizePercentiles {
+    pub batches: usize,
+    pub p50_bytes: usize,
+    pub p95_bytes: usize,
+    pub p50_events: u64,
+    pub p95_events: u64,
+}
+
+// Byte size and event count of delivered batches, used to right-size buffers
+pub struct BatchSizeHistogram {
+    samples: Mutex<VecDeque<(usize, u64)>>,
+}
+
+impl BatchSizeHistogram {
+    pub const fn new() -> Self {
+        Self { samples: Mutex::new(VecDeque::new()) }
+    }
+    
+    pub fn record(&self, bytes: usize, events: u64) {
+        let mut samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        if samples.len() >= BATCH_SIZE_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back((bytes, events));
+    }
+    
+    pub fn percentiles(&self) -> Option<BatchSizePercentiles> {
+        let samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        if samples.is_empty() {
+            return None;
+        }
+        
+        let mut bytes: Vec<usize> = samples.iter().map(|(b, _)| *b).collect();
+        let mut events: Vec<u64> = samples.iter().map(|(_, e)| *e).collect();
+        bytes.sort_unstable();
+        events.sort_unstable();
+        
+        Some(BatchSizePercentiles {
+            batches: samples.len(),
+            p50_bytes: nearest_rank(&bytes, 50),
+            p95_bytes: nearest_rank(&bytes, 95),
+            p50_events: nearest_rank(&events, 50),
+            p95_events: nearest_rank(&events, 95),
+        })
+    }
+}
+
+impl Default for BatchSizeHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn nearest_rank<T: Copy>(sorted: &[T], percentile: usize) -> T {
+    let rank = (percentile * sorted.len()).div_ceil(100);
+    sorted[rank.saturating_sub(1)]
+}
+
+// Phases of one ingest request. reqwest doesn't report DNS, connect and TLS separately, so
+// `headers` covers them along with server processing; on a reused connection it is mostly
+// server time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RequestTiming {
+    // Request start until the response headers arrived
+    pub headers: Duration,
+    // Reading the response body
+    pub body: Duration,
+    pub total: Duration,
+}
+
+// Summed phase timings of the requests to one destination that got a response
+pub struct FlushTimings {
+    requests: AtomicU64,
+    headers_us: AtomicU64,
+    body_us: AtomicU64,
+    total_us: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlushTimingTotals {
+    pub requests: u64,
+    pub headers: Duration,
+    pub body: Duration,
+    pub total: Duration,
+}
+
+impl FlushTimings {
+    pub const fn new() -> Self {
+        Self {
+            requests: AtomicU64::new(0),
+            headers_us: AtomicU64::new(0),
+            body_us: AtomicU64::new(0),
+            total_us: AtomicU64::new(0),
+        }
+    }
+    
+    pub fn record(&self, timing: &RequestTiming) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.headers_us.fetch_add(timing.headers.as_micros() as u64, Ordering::Relaxed);
+        self.body_us.fetch_add(timing.body.as_micros() as u64, Ordering::Relaxed);
+        self.total_us.fetch_add(timing.total.as_micros() as u64, Ordering::Relaxed);
+    }
+    
+    pub fn totals(&self) -> Option<FlushTimingTotals> {
+        let requests = self.requests.load(Ordering::Relaxed);
+        (requests > 0).then(|| FlushTimingTotals {
+            requests,
+            headers: Duration::from_micros(self.headers_us.load(Ordering::Relaxed)),
+            body: Duration::from_micros(self.body_us.load(Ordering::Relaxed)),
+            total: Duration::from_micros(self.total_us.load(Ordering::Relaxed)),
+        })
+    }
+}
+
+impl Default for FlushTimings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Key identifying a batch: its content plus the sequence number it was taken with, so
+// retries of the batch share a key while a new batch with the same content gets its own.
+// One-off sends have no sequence number and are keyed by content alone.
+pub fn batch_idempotency_key(json_batch: &[u8], batch_seq: Option<u64>) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(json_batch);
+    if let Some(seq) = batch_seq {
+        hasher.update(seq.to_be_bytes());
+    }
+    hex::encode(hasher.finalize())
+}
+
+// Builder for clients talking to OpenObserve: the egress settings plus O2_HTTP_VERSION and
+// connection pooling (O2_KEEPALIVE_IDLE_MS, O2_POOL_MAX_IDLE_PER_HOST). Callers add timeouts.
+pub fn client_builder(config: &Config) -> reqwest::ClientBuilder {
+    let mut builder = apply_http_version(egress_client_builder(config), config);
+    if config.keepalive_idle_ms > 0 {
+        let idle = Duration::from_millis(config.keepalive_idle_ms);
+        builder = builder.pool_idle_timeout(idle).tcp_keepalive(idle);
+    }
+    if config.pool_max_idle_per_host > 0 {
+        builder = builder.pool_max_idle_per_host(config.pool_max_idle_per_host);
+    }
+    builder
+}
+
+// Builder for any outbound client, e.g. the alert webhook: O2_TLS_MIN_VERSION and O2_IP_VERSION
+pub fn egress_client_builder(config: &Config) -> reqwest::ClientBuilder {
+    apply_ip_version(Client::builder().min_tls_version(tls_min_version(config)), config)
+}
+
+// Pin the HTTP version for clients talking to OpenObserve, per O2_HTTP_VERSION
+fn apply_http_version(builder: reqwest::ClientBuilder, config: &Config) -> reqwest::ClientBuilder {
+    match config.http_version {
+        HttpVersion::Auto => builder,
+        HttpVersion::Http1 => builder.http1_only(),
+        HttpVersion::Http2 => builder.http2_prior_knowledge(),
+    }
+}
+
+// Pin the IP family for egress, per O2_IP_VERSION. Binding one family's unspecified address
+// makes the connector skip resolved addresses of the other family.
+fn apply_ip_version(builder: reqwest::ClientBuilder, config: &Config) -> reqwest::ClientBuilder {
+    match config.ip_version {
+        IpVersion::Auto => builder,
+        IpVersion::V4 => builder.local_address(IpAddr::from(Ipv4Addr::UNSPECIFIED)),
+        IpVersion::V6 => builder.local_address(IpAddr::from(Ipv6Addr::UNSPECIFIED)),
+    }
+}
+
+// Lowest TLS version accepted for egress, from O2_TLS_MIN_VERSION
+pub fn tls_min_version(config: &Config) -> reqwest::tls::Version {
+    match config.tls_min_version {
+        TlsVersion::Tls12 => reqwest::tls::Version::TLS_1_2,
+        TlsVersion::Tls13 => reqwest::tls::Version::TLS_1_3,
+    }
+}
+
+// Attach credentials for the configured auth mode to an ingest request
+pub fn apply_auth(
+    request: reqwest::RequestBuilder,
+    config: &Config,
+    url: &str,
+    content_type: &str,
+    body: &[u8],
+) -> Result<reqwest::RequestBuilder, ExtensionError> {
+    match config.auth_mode {
+        AuthMode::Header => Ok(request.header("Authorization", &config.o2_authorization_header)),
+        AuthMode::SigV4 => {
+            let signer = SigV4Signer::from_config(config)?;
+            let parsed_url = url::Url::parse(url)
+                .map_err(|e| ExtensionError::Config(format!("Invalid ingest URL {url}: {e}")))?;
+            let signed = signer.sign(
+                "POST",
+                &parsed_url,
+                &[("Content-Type", content_type)],
+                body,
+                Utc::now(),
+            );
+            
+            let mut request = request
+                .header("Authorization", signed.authorization)
+                .header("X-Amz-Date", signed.amz_date)
+                .header("X-Amz-Content-Sha256", signed.content_sha256);
+            if let Some(token) = signed.security_token {
+                request = request.header("X-Amz-Security-Token", token);
+            }
+            Ok(request)
+        }
+    }
+}
+
+// Send a request to the configured destination and read the response body
+pub async fn execute_request(
+    client: &Client,
+    config: &Config,
+    request: reqwest::RequestBuilder,
+) -> Result<(reqwest::StatusCode, String), ExtensionError> {
+    let (status, text, _) = execute_request_timed(client, config, request).await?;
+    Ok((status, text))
+}
+
+// execute_request, also reporting how long each phase took
+async fn execute_request_timed(
+    client: &Client,
+    config: &Config,
+    request: reqwest::RequestBuilder,
+) -> Result<(reqwest::StatusCode, String, RequestTiming), ExtensionError> {
+    let started = std::time::Instant::now();
+    if let Some(socket_path) = config.unix_socket_path() {
+        let request_timeout = Duration::from_millis(config.request_timeout_ms);
+        let (status, text) = tokio::time::timeout(request_timeout, execute_over_unix_socket(socket_path, request.build()?))
+            .await
+            .map_err(|_| ExtensionError::Transport(format!("unix socket {socket_path}: request timed out")))??;
+        // The socket path reads the whole response at once, so there is no body phase
+        let total = started.elapsed();
+        return Ok((status, text, RequestTiming { headers: total, body: Duration::ZERO, total }));
+    }
+    
+    let response = client.execute(request.build()?).await?;
+    let headers = started.elapsed();
+    let status = response.status();
+    // Safely consume the response body
+    let text = match response.text().await {
+        Ok(text) => text,
+        Err(_) => format!("Status: {status} (response body unreadable)"),
+    };
+    let total = started.elapsed();
+    Ok((status, text, RequestTiming { headers, body: total - headers, total }))
+}
+
+// Check that the endpoint accepts connections within `limit`, without sending anything
+pub async fn probe_endpoint(config: &Config, limit: Duration) -> Result<(), ExtensionError> {
+    let unreachable = |e: &dyn std::fmt::Display| {
+        ExtensionError::Transport(format!("{} unreachable: {e}", config.o2_endpoint))
+    };
+    
+    let connect = async {
+        if let Some(socket_path) = config.unix_socket_path() {
+            tokio::net::UnixStream::connect(socket_path).await.map(|_| ())
+        } else {
+            let url = url::Url::parse(&config.o2_endpoint)
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+            let host = url.host_str().unwrap_or_default().trim_start_matches('[').trim_end_matches(']');
+            let port = url.port_or_known_default().unwrap_or(443);
+            let addrs: Vec<_> = tokio::net::lookup_host((host, port)).await?
+                .filter(|addr| config.ip_version.allows(addr))
+                .collect();
+            tokio::net::TcpStream::connect(addrs.as_slice()).await.map(|_| ())
+        }
+    };
+    
+    match tokio::time::timeout(limit, connect).await {
+        Ok(result) => result.map_err(|e| unreachable(&e)),
+        Err(_) => Err(unreachable(&format!("no connection within {}ms", limit.as_millis()))),
+    }
+}
+
+// Replay a built request as HTTP/1.1 over a Unix domain socket
+async fn execute_over_unix_socket(
+    socket_path: &str,
+    request: reqwest::Request,
+) -> Result<(reqwest::StatusCode, String), ExtensionError> {
+    let transport_error = |e: &dyn std::fmt::Display| {
+        ExtensionError::Transport(format!("unix socket {socket_path}: {e}"))
+    };
+    
+    let stream = tokio::net::UnixStream::connect(socket_path)
+        .await
+        .map_err(|e| transport_error(&e))?;
+    let (mut sender, connection) = hyper::client::conn::handshake(stream)
+        .await
+        .map_err(|e| transport_error(&e))?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            debug!("Unix socket connection closed with error: {}", e);
+        }
+    });
+    
+    let path = match request.url().query() {
+        Some(query) => format!("{}?{}", request.url().path(), query),
+        None => request.url().path().to_string(),
+    };
+    let body = request.body().and_then(|body| body.as_bytes()).unwrap_or_default().to_vec();
+    let mut builder = http::Request::builder()
+        .method(request.method().clone())
+        .uri(path)
+        .header(http::header::HOST, "localhost");
+    for (name, value) in request.headers() {
+        builder = builder.header(name, value);
+    }
+    let hyper_request = builder
+        .body(hyper::Body::from(body))
+        .map_err(|e| transport_error(&e))?;
+    
+    let response = sender.send_request(hyper_request).await.map_err(|e| transport_error(&e))?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| transport_error(&e))?;
+    Ok((status, String::from_utf8_lossy(&body).into_owned()))
+}
+
+// Send a one-off JSON batch (e.g. the startup event) to OpenObserve, with retry backoff
+// starting afresh. Flushes use send_flush_batch so backoff carries over between them.
 pub async fn send_batch_to_openobserve(
     client: &Client,
     config: &Config,
     json_batch: &[u8],
-) -> Result<u64> {
+) -> Result<u64, ExtensionError> {
+    let state = IngestState::new(config);
+    let context = SendContext { state: &state, flush_strategy: None, trace_id: None, batch_seq: None };
+    send_flush_batch(client, config, json_batch, context).await
+}
+
+// Send JSON batch to OpenObserve with retry logic and exponential backoff, copying it to
+// the mirror destination once the primary accepts it. Backoff is kept in `context.state`.
+pub async fn send_flush_batch(
+    client: &Client,
+    config: &Config,
+    json_batch: &[u8],
+    context: SendContext<'_>,
+) -> Result<u64, ExtensionError> {
+    let events_sent = send_batch_to_destination(client, config, json_batch, context, &context.state.primary).await?;
+    
+    if let Some(mirror_config) = &context.state.mirror_config {
+        if let Err(e) = send_batch_to_destination(client, mirror_config, json_batch, context, &context.state.mirror).await {
+            warn!("⚠️ Mirror delivery to {} failed: {}", mirror_config.o2_endpoint, e);
+        }
+    }
+    
+    Ok(events_sent)
+}
+
+async fn send_batch_to_destination(
+    client: &Client,
+    config: &Config,
+    json_batch: &[u8],
+    context: SendContext<'_>,
+    destination: &DestinationState,
+) -> Result<u64, ExtensionError> {
+    // Stay under a gateway body limit by posting the array in pieces
+    if config.http_max_body_bytes > 0 && json_batch.len() > config.http_max_body_bytes {
+        let mut total_events = 0;
+        for chunk in split_json_array(json_batch, config.http_max_body_bytes)? {
+            total_events += send_body_with_retries(client, config, &chunk, context, destination).await?;
+        }
+        return Ok(total_events);
+    }
+    
+    send_body_with_retries(client, config, json_batch, context, destination).await
+}
+
+// Split a JSON array into smaller arrays of at most `max_bytes` each.
+// A single element larger than the limit is sent on its own.
+fn split_json_array(json_batch: &[u8], max_bytes: usize) -> Result<Vec<Vec<u8>>, ExtensionError> {
+    let elements: Vec<serde_json::Value> = serde_json::from_slice(json_batch)
+        .map_err(|e| ExtensionError::Serialization(format!("Failed to split batch: {e}")))?;
+    
+    let mut chunks = Vec::new();
+    let mut current = b"[".to_vec();
+    for element in elements {
+        let encoded = serde_json::to_vec(&element)
+            .map_err(|e| ExtensionError::Serialization(format!("Failed to split batch: {e}")))?;
+        
+        // +2 for the separating comma and the closing bracket
+        if current.len() > 1 && current.len() + encoded.len() + 2 > max_bytes {
+            current.push(b']');
+            chunks.push(std::mem::replace(&mut current, b"[".to_vec()));
+        }
+        if current.len() > 1 {
+            current.push(b',');
+        }
+        current.extend(encoded);
+    }
+    if current.len() > 1 {
+        current.push(b']');
+        chunks.push(current);
+    }
+    
+    Ok(chunks)
+}
+
+// Per-record outcome of an ingest request, summed over the streams it wrote to
+#[derive(Debug, PartialEq)]
+struct IngestReport {
+    successful: u64,
+    failed: u64,
+    error: Option<String>,
+}
+
+// Read OpenObserve's `{"code":200,"status":[{"name":..,"successful":N,"failed":M,"error":..}]}`
+// response. None for any other body, e.g. a proxy in front of the ingest endpoint.
+fn parse_ingest_report(body: &str) -> Option<IngestReport> {
+    let response: serde_json::Value = serde_json::from_str(body).ok()?;
+    let mut report = IngestReport { successful: 0, failed: 0, error: None };
+    for stream in response.get("status")?.as_array()? {
+        report.successful += stream.get("successful")?.as_u64()?;
+        report.failed += stream.get("failed")?.as_u64()?;
+        if report.error.is_none() {
+            report.error = stream.get("error").and_then(|error| error.as_str()).map(str::to_string);
+        }
+    }
+    Some(report)
+}
+
+// Post one request body, retrying with exponential backoff
+async fn send_body_with_retries(
+    client: &Client,
+    config: &Config,
+    json_batch: &[u8],
+    context: SendContext<'_>,
+    destination: &DestinationState,
+) -> Result<u64, ExtensionError> {
     let url = config.openobserve_url();
     
     debug!("🌐 Making HTTP call to OpenObserve: {} bytes to {}", 
            json_batch.len(), url);
     
     // Parse the batch to count events for metrics
-    let events_count = if let Ok(batch_str) = String::from_utf8(json_batch.to_vec()) {
+    let events_count = if config.output_encoding == OutputEncoding::Csv {
+        // One row per event after the header; quoted cells may contain line breaks
+        let mut in_quotes = false;
+        let mut rows: u64 = 0;
+        for &byte in json_batch {
+            match byte {
+                b'"' => in_quotes = !in_quotes,
+                b'\n' if !in_quotes => rows += 1,
+                _ => {}
+            }
+        }
+        rows.saturating_sub(1)
+    } else if let Ok(batch_str) = String::from_utf8(json_batch.to_vec()) {
         // Count events by counting commas + 1 (assuming valid JSON array)
         if batch_str.trim().starts_with('[') && batch_str.trim().ends_with(']') {
             batch_str.matches(',').count() as u64 + 1
@@ -31,26 +569,42 @@ pub async fn send_batch_to_openobserve(
         1 // Default to 1 if we can't parse
     };
     
-    let mut current_delay = config.initial_retry_delay_ms;
-    let mut last_error = None;
+    let idempotency_key = batch_idempotency_key(json_batch, context.batch_seq);
+    // Integrity checksum over the exact bytes put on the wire
+    let body_checksum = config.checksum.then(|| hex::encode(Sha256::digest(json_batch)));
+    // Puts the ingest call in the invocation's X-Ray trace
+    let trace_id = context.trace_id.filter(|_| config.propagate_xray);
+    let mut current_delay = destination.backoff.starting_delay_ms(config);
     
     // Attempt initial request + retries
     for attempt in 0..=(config.max_retries) {
-        let response_result = client
+        // Signed per attempt so SigV4 timestamps stay fresh across retries
+        let mut request = client
             .post(&url)
-            .header("Authorization", &config.o2_authorization_header)
-            .header("Content-Type", "application/json")
-            .body(json_batch.to_vec())
-            .send()
-            .await;
-        
-        match response_result {
-            Ok(response) => {
-                let status = response.status();
-                
+            .header("Content-Type", &config.content_type)
+            .header(IDEMPOTENCY_KEY_HEADER, &idempotency_key)
+            .body(json_batch.to_vec());
+        if let Some(checksum) = &body_checksum {
+            request = request.header(CONTENT_SHA256_HEADER, checksum);
+        }
+        if let Some(trace_id) = trace_id {
+            request = request.header(XRAY_TRACE_HEADER, trace_id);
+        }
+        if let Some(flush_strategy) = context.flush_strategy {
+            request = request.header(FLUSH_STRATEGY_HEADER, flush_strategy);
+        }
+        let request = apply_auth(request, config, &url, &config.content_type, json_batch)?;
+        
+        let error = match execute_request_timed(client, config, request).await {
+            Ok((status, message, timing)) => {
+                destination.timings.record(&timing);
+                if config.timing_detail {
+                    debug!("⏱️ Ingest request timing: headers={:.1}ms (DNS, connect, TLS and server), body={:.1}ms, total={:.1}ms",
+                           timing.headers.as_secs_f64() * 1000.0,
+                           timing.body.as_secs_f64() * 1000.0,
+                           timing.total.as_secs_f64() * 1000.0);
+                }
                 if status.is_success() {
-                    // Consume response body for successful requests
-                    let _response_text: String = (response.text().await).unwrap_or_default();
                     if attempt > 0 {
                         debug!("✅ Successfully sent batch of {} events to OpenObserve on retry attempt {} - Status: {}", 
                                events_count, attempt, status);
@@ -58,57 +612,263 @@ pub async fn send_batch_to_openobserve(
                         debug!("✅ Successfully sent batch of {} events to OpenObserve - Status: {}", 
                                events_count, status);
                     }
-                    return Ok(events_count);
-                } else {
-                    // Server returned error status - safely consume response body
-                    let error_text = match response.text().await {
-                        Ok(text) => text,
-                        Err(_) => format!("Status: {status} (response body unreadable)"),
-                    };
-                    let error_msg = format!("OpenObserve returned status {status}: {error_text}");
+                    destination.backoff.record_success();
                     
-                    // Check if this is a retryable error (5xx server errors are retryable, 4xx client errors are not)
-                    let is_retryable = status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
-                    
-                    if !is_retryable || attempt >= config.max_retries {
-                        error!("❌ FAILED to send batch to OpenObserve after {} attempts - Status: {}, Error: {}", 
-                               attempt + 1, status, error_text);
-                        return Err(anyhow!(error_msg));
+                    // A 2xx can still carry per-record rejections
+                    if let Some(report) = parse_ingest_report(&message) {
+                        if report.failed > 0 {
+                            let error = report.error.as_deref().unwrap_or("no error given");
+                            warn!("⚠️ OpenObserve rejected {} of {} records: {}",
+                                  report.failed, report.failed + report.successful, error);
+                            if config.requeue_rejected && report.successful == 0 {
+                                return Err(ExtensionError::Rejected { count: report.failed, message: error.to_string() });
+                            }
+                        }
+                        // The server's count beats the estimate from the body
+                        return Ok(report.successful);
                     }
-                    
-                    warn!("⚠️ Retry attempt {}/{} failed with retryable error - Status: {}, will retry in {}ms", 
-                          attempt + 1, config.max_retries, status, current_delay);
-                    last_error = Some(error_msg);
-                }
-            },
-            Err(e) => {
-                // Network/connection error
-                let error_msg = format!("Request failed: {e}");
-                
-                if attempt >= config.max_retries {
-                    error!("❌ FAILED to send batch to OpenObserve after {} attempts - Network error: {}", 
-                           attempt + 1, e);
-                    return Err(anyhow!(error_msg));
+                    return Ok(events_count);
                 }
                 
-                warn!("⚠️ Retry attempt {}/{} failed with network error - {}, will retry in {}ms", 
-                      attempt + 1, config.max_retries, e, current_delay);
-                last_error = Some(error_msg);
-            }
+                // Server returned error status
+                ExtensionError::Server { status, message }
+            },
+            // Network/connection error
+            Err(e) => e,
+        };
+        
+        // 5xx, 429, network errors and allowlisted statuses are retryable, other 4xx client errors are not
+        if !error.is_retryable_with(&config.retryable_statuses) || attempt >= config.max_retries {
+            error!("❌ FAILED to send batch to OpenObserve after {} attempts - {}", 
+                   attempt + 1, error);
+            return Err(error);
         }
         
-        // Wait before next retry (unless this was the last attempt)
-        if attempt < config.max_retries {
-            sleep(Duration::from_millis(current_delay)).await;
-            
-            // Exponential backoff: double the delay, capped at max_retry_delay_ms
-            current_delay = cmp::min(current_delay * 2, config.max_retry_delay_ms);
+        // A reset on the very first attempt is usually a stale connection, so retry straight away
+        if attempt == 0 && error.is_connection_reset() {
+            warn!("⚠️ Connection reset on first attempt - {}, retrying immediately", error);
+            continue;
         }
+        
+        warn!("⚠️ Retry attempt {}/{} failed with retryable error - {}, will retry in {}ms", 
+              attempt + 1, config.max_retries, error, current_delay);
+        
+        destination.backoff.record_failure();
+        sleep(Duration::from_millis(current_delay)).await;
+        
+        // Exponential backoff: double the delay, capped at max_retry_delay_ms
+        current_delay = cmp::min(current_delay * 2, config.max_retry_delay_ms);
+    }
+    
+    unreachable!("retry loop always returns on the final attempt")
+}
+
+// Encode samples as a Prometheus remote-write WriteRequest protobuf (uncompressed)
+pub fn encode_remote_write(samples: &[MetricSample], function_name: &str) -> Vec<u8> {
+    let mut request = Vec::new();
+    
+    for sample in samples {
+        // Labels must be sorted by name
+        let mut labels = vec![("__name__", sample.name)];
+        if !function_name.is_empty() {
+            labels.push(("function_name", function_name));
+        }
+        
+        let mut series = Vec::new();
+        for (name, value) in labels {
+            let mut label = Vec::new();
+            encode_bytes_field(&mut label, 1, name.as_bytes());
+            encode_bytes_field(&mut label, 2, value.as_bytes());
+            encode_bytes_field(&mut series, 1, &label);
+        }
+        
+        let mut encoded_sample = Vec::new();
+        encoded_sample.push(1 << 3 | 1); // value: double
+        encoded_sample.extend(sample.value.to_le_bytes());
+        encoded_sample.push(2 << 3); // timestamp: int64 varint
+        encode_varint(&mut encoded_sample, sample.timestamp_ms as u64);
+        encode_bytes_field(&mut series, 2, &encoded_sample);
+        
+        encode_bytes_field(&mut request, 1, &series);
+    }
+    
+    request
+}
+
+fn encode_bytes_field(buffer: &mut Vec<u8>, field_number: u8, bytes: &[u8]) {
+    buffer.push(field_number << 3 | 2);
+    encode_varint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+fn encode_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+// Post platform metric samples to the configured Prometheus remote-write endpoint
+pub async fn send_metrics_remote_write(
+    client: &Client,
+    config: &Config,
+    samples: &[MetricSample],
+) -> Result<(), ExtensionError> {
+    let url = config.metrics_endpoint.as_deref()
+        .ok_or_else(|| ExtensionError::Config("O2_METRICS_ENDPOINT is not set".to_string()))?;
+    
+    let function_name = config.function_name.as_deref().unwrap_or_default();
+    let body = snap::raw::Encoder::new()
+        .compress_vec(&encode_remote_write(samples, function_name))
+        .map_err(|e| ExtensionError::Serialization(format!("Failed to compress remote-write payload: {e}")))?;
+    
+    debug!("🌐 Sending {} metric samples ({} bytes) to {}", samples.len(), body.len(), url);
+    
+    let request = client
+        .post(url)
+        .header("Content-Type", REMOTE_WRITE_CONTENT_TYPE)
+        .header("Content-Encoding", "snappy")
+        .header("X-Prometheus-Remote-Write-Version", REMOTE_WRITE_VERSION)
+        .body(body.clone());
+    let response = apply_auth(request, config, url, REMOTE_WRITE_CONTENT_TYPE, &body)?
+        .send()
+        .await?;
+    
+    let status = response.status();
+    if !status.is_success() {
+        let message = response.text().await.unwrap_or_default();
+        return Err(ExtensionError::Server { status, message });
+    }
+    
+    Ok(())
+}
+
+// Best-effort notification that a flush cycle failed; errors are only logged
+pub async fn send_flush_failure_alert(config: &Config, failed_batches: u64, last_error: &str) {
+    let Some(webhook) = config.alert_webhook.as_deref() else {
+        return;
+    };
+    
+    let summary = serde_json::json!({
+        "event": "flush_failed",
+        "function_name": config.function_name.as_deref().unwrap_or_default(),
+        "failed_batches": failed_batches,
+        "last_error": last_error,
+        "timestamp": Utc::now().to_rfc3339(),
+    });
+    
+    let result = async {
+        let client = egress_client_builder(config)
+            .timeout(Duration::from_millis(ALERT_WEBHOOK_TIMEOUT_MS))
+            .build()?;
+        client.post(webhook).json(&summary).send().await?.error_for_status()
+    }
+    .await;
+    
+    match result {
+        Ok(_) => debug!("🔔 Sent flush failure alert to webhook"),
+        Err(e) => warn!("⚠️ Failed to send flush failure alert: {}", e),
+    }
+}
+
+// One-time event confirming the extension registered and subscribed in this instance
+pub fn create_startup_event(config: &Config, extension_name: &str) -> TelemetryEvent {
+    let function_name = config.function_name.as_deref().unwrap_or_default();
+    
+    TelemetryEvent {
+        time: Utc::now(),
+        event_type: "extension".to_string(),
+        record: serde_json::json!({
+            "event": "extension_started",
+            "extension": extension_name,
+            "version": env!("CARGO_PKG_VERSION"),
+            "function_name": function_name,
+        }),
+        request_id: None,
+    }
+}
+
+// Send the startup event straight away, shaped the same way as buffered events
+pub async fn send_startup_event(
+    client: &Client,
+    config: &Config,
+    extension_name: &str,
+) -> Result<u64, ExtensionError> {
+    let mut aggregator = TelemetryAggregator::from_config(config);
+    aggregator.add_batch(vec![create_startup_event(config, extension_name)]);
+    
+    send_batch_to_openobserve(client, config, &aggregator.get_batch()).await
+}
+
+// Why the extension failed to start, sent before it exits since nothing else will be flushed
+pub fn create_init_error_event(config: &Config, extension_name: &str, stage: &str, error: &str) -> TelemetryEvent {
+    let function_name = config.function_name.as_deref().unwrap_or_default();
+    
+    TelemetryEvent {
+        time: Utc::now(),
+        event_type: "extension".to_string(),
+        record: serde_json::json!({
+            "event": "extension_init_error",
+            "extension": extension_name,
+            "version": env!("CARGO_PKG_VERSION"),
+            "function_name": function_name,
+            "stage": stage,
+            "error": error,
+        }),
+        request_id: None,
     }
+}
+
+// Best-effort report of a startup failure: one attempt with a short timeout, errors are only logged
+pub async fn send_init_error_event(config: &Config, extension_name: &str, stage: &str, error: &str) {
+    let config = Config {
+        max_retries: 0,
+        ..config.clone()
+    };
     
-    // This should never be reached, but just in case
-    Err(anyhow!("All retry attempts exhausted: {}", 
-                last_error.unwrap_or_else(|| "Unknown error".to_string())))
+    let result = async {
+        let client = client_builder(&config)
+            .timeout(Duration::from_millis(INIT_ERROR_TIMEOUT_MS))
+            .build()?;
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        aggregator.add_batch(vec![create_init_error_event(&config, extension_name, stage, error)]);
+        send_batch_to_openobserve(&client, &config, &aggregator.get_batch()).await
+    }
+    .await;
+    
+    match result {
+        Ok(_) => debug!("📣 Init error event sent"),
+        Err(e) => warn!("⚠️ Failed to send init error event: {}", e),
+    }
+}
+
+// Page size and clock tick rate assumed for /proc values (the Linux defaults on Lambda)
+const PAGE_SIZE_BYTES: u64 = 4096;
+const CLOCK_TICKS_PER_SEC: u64 = 100;
+
+// Sample the extension's own memory and CPU usage from /proc (Linux only)
+pub fn create_self_metrics_event() -> Option<TelemetryEvent> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let resident_pages: u64 = statm.split_whitespace().nth(1)?.parse().ok()?;
+    
+    // Fields after the parenthesised command name start at field 3 (state)
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let user_ticks: u64 = fields.get(11)?.parse().ok()?;
+    let system_ticks: u64 = fields.get(12)?.parse().ok()?;
+    
+    Some(TelemetryEvent {
+        time: Utc::now(),
+        event_type: "extension".to_string(),
+        record: serde_json::json!({
+            "event": "self_metrics",
+            "rss_bytes": resident_pages * PAGE_SIZE_BYTES,
+            "cpu_user_ms": user_ticks * 1000 / CLOCK_TICKS_PER_SEC,
+            "cpu_system_ms": system_ticks * 1000 / CLOCK_TICKS_PER_SEC,
+        }),
+        request_id: None,
+    })
 }
 
 // Utility function to create a test event for health checks
@@ -119,4 +879,632 @@ pub fn create_test_event() -> TelemetryEvent {
         record: serde_json::json!("OpenObserve Lambda Extension health check"),
         request_id: None,
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{CapturedLogs, MockServer};
+
+    fn test_config(endpoint: String) -> Config {
+        Config {
+            o2_endpoint: endpoint,
+            o2_organization_id: "test_org".to_string(),
+            o2_authorization_header: "Basic dGVzdA==".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server_error_variant() {
+        let server = MockServer::start().await;
+        server.set_status(http::StatusCode::SERVICE_UNAVAILABLE).await;
+        let config = Config {
+            max_retries: 0,
+            ..test_config(server.endpoint())
+        };
+
+        let error = send_batch_to_openobserve(&Client::new(), &config, b"[]").await.unwrap_err();
+        assert!(matches!(
+            error,
+            ExtensionError::Server { status, .. } if status == reqwest::StatusCode::SERVICE_UNAVAILABLE
+        ));
+        assert!(error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_client_error_variant_is_not_retried() {
+        let server = MockServer::start().await;
+        server.set_status(http::StatusCode::UNAUTHORIZED).await;
+        let config = Config {
+            max_retries: 3,
+            initial_retry_delay_ms: 1,
+            ..test_config(server.endpoint())
+        };
+
+        let error = send_batch_to_openobserve(&Client::new(), &config, b"[]").await.unwrap_err();
+        assert!(matches!(
+            error,
+            ExtensionError::Server { status, .. } if status == reqwest::StatusCode::UNAUTHORIZED
+        ));
+        assert_eq!(server.request_count().await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_retries_reuse_idempotency_key() {
+        let server = MockServer::start().await;
+        server.set_status(http::StatusCode::SERVICE_UNAVAILABLE).await;
+        let config = Config {
+            max_retries: 2,
+            initial_retry_delay_ms: 1,
+            max_retry_delay_ms: 1,
+            ..test_config(server.endpoint())
+        };
+
+        let batch = b"[{\"a\":1}]";
+        send_batch_to_openobserve(&Client::new(), &config, batch).await.unwrap_err();
+
+        let requests = server.requests().await;
+        assert_eq!(requests.len(), 3);
+        let expected = batch_idempotency_key(batch, None);
+        for request in requests {
+            assert_eq!(request.headers[IDEMPOTENCY_KEY_HEADER], expected.as_str());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_network_error_variant() {
+        // Bind then drop a listener to get a port nobody is listening on
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let config = Config {
+            max_retries: 0,
+            ..test_config(format!("http://127.0.0.1:{port}"))
+        };
+
+        let error = send_batch_to_openobserve(&Client::new(), &config, b"[]").await.unwrap_err();
+        assert!(matches!(error, ExtensionError::Network(_)));
+    }
+
+    #[test]
+    fn test_config_error_variant() {
+        let config = Config {
+            o2_organization_id: String::new(),
+            ..test_config("https://api.openobserve.ai".to_string())
+        };
+
+        assert!(matches!(config.validate(), Err(ExtensionError::Config(_))));
+    }
+
+    #[tokio::test]
+    async fn test_send_startup_event() {
+        let server = MockServer::start().await;
+        let config = test_config(server.endpoint());
+
+        send_startup_event(&Client::new(), &config, "o2-lambda-extension").await.unwrap();
+
+        let requests = server.requests().await;
+        assert_eq!(requests.len(), 1);
+        let body: Vec<serde_json::Value> = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(body.len(), 1);
+        assert_eq!(body[0]["type"], "extension");
+        assert_eq!(body[0]["record"]["event"], "extension_started");
+        assert_eq!(body[0]["record"]["version"], env!("CARGO_PKG_VERSION"));
+        assert!(body[0]["_timestamp"].is_i64());
+    }
+
+    #[tokio::test]
+    async fn test_startup_event_shares_instance_id() {
+        let _guard = crate::test_support::INSTANCE_ID_LOCK.lock().await;
+        let server = MockServer::start().await;
+        let config = Config { include_instance_id: true, ..test_config(server.endpoint()) };
+
+        send_startup_event(&Client::new(), &config, "o2-lambda-extension").await.unwrap();
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        aggregator.add_batch(vec![TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!("log line"),
+            request_id: None,
+        }]);
+
+        let startup: Vec<serde_json::Value> = serde_json::from_slice(&server.requests().await[0].body).unwrap();
+        let regular: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert!(startup[0]["instance_id"].is_string());
+        assert_eq!(startup[0]["instance_id"], regular[0]["instance_id"]);
+    }
+
+    #[test]
+    fn test_extension_events_use_configured_function_name() {
+        let config = Config { function_name: Some("checkout-api".to_string()), ..Config::default() };
+        assert_eq!(create_startup_event(&config, "o2-lambda-extension").record["function_name"], "checkout-api");
+        let init_error = create_init_error_event(&config, "o2-lambda-extension", "register", "refused");
+        assert_eq!(init_error.record["function_name"], "checkout-api");
+    }
+
+    #[tokio::test]
+    async fn test_sigv4_signed_request() {
+        let server = MockServer::start().await;
+        let config = Config {
+            auth_mode: AuthMode::SigV4,
+            o2_authorization_header: String::new(),
+            aws_credentials: Some(crate::sigv4::AwsCredentials {
+                access_key_id: "AKIDEXAMPLE".to_string(),
+                secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+                session_token: Some("session-token".to_string()),
+            }),
+            aws_region: Some("us-east-1".to_string()),
+            ..test_config(server.endpoint())
+        };
+
+        send_batch_to_openobserve(&Client::new(), &config, b"[]").await.unwrap();
+
+        let requests = server.requests().await;
+        let authorization = requests[0].headers["authorization"].to_str().unwrap();
+        assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+        assert!(authorization.contains("/us-east-1/execute-api/aws4_request"));
+        assert!(requests[0].headers.contains_key("x-amz-date"));
+        assert_eq!(requests[0].headers["x-amz-security-token"], "session-token");
+    }
+
+    #[tokio::test]
+    async fn test_checksum_header_matches_body() {
+        let server = MockServer::start().await;
+        let config = Config {
+            checksum: true,
+            ..test_config(server.endpoint())
+        };
+
+        send_batch_to_openobserve(&Client::new(), &config, b"[{\"msg\":\"hello\"}]").await.unwrap();
+
+        let requests = server.requests().await;
+        let expected = hex::encode(Sha256::digest(&requests[0].body));
+        assert_eq!(requests[0].headers[CONTENT_SHA256_HEADER], expected.as_str());
+    }
+
+    #[tokio::test]
+    async fn test_no_checksum_header_by_default() {
+        let server = MockServer::start().await;
+
+        send_batch_to_openobserve(&Client::new(), &test_config(server.endpoint()), b"[]").await.unwrap();
+
+        assert!(!server.requests().await[0].headers.contains_key(CONTENT_SHA256_HEADER));
+    }
+
+    #[tokio::test]
+    async fn test_metrics_remote_write_payload() {
+        let server = MockServer::start().await;
+        let config = Config {
+            metrics_export: crate::config::MetricsExport::Prometheus,
+            metrics_endpoint: Some(format!("{}/api/v1/write", server.endpoint())),
+            ..test_config(server.endpoint())
+        };
+        let samples = vec![
+            MetricSample { name: "aws_lambda_duration_ms", value: 12.5, timestamp_ms: 1_700_000_000_000 },
+            MetricSample { name: "aws_lambda_max_memory_used_mb", value: 70.0, timestamp_ms: 1_700_000_000_000 },
+        ];
+
+        send_metrics_remote_write(&Client::new(), &config, &samples).await.unwrap();
+
+        let request = &server.requests().await[0];
+        assert_eq!(request.uri, "/api/v1/write");
+        assert_eq!(request.headers["Content-Encoding"], "snappy");
+        assert_eq!(request.headers["Content-Type"], REMOTE_WRITE_CONTENT_TYPE);
+        let payload = snap::raw::Decoder::new().decompress_vec(&request.body).unwrap();
+        let contains = |needle: &[u8]| payload.windows(needle.len()).any(|window| window == needle);
+        assert!(contains(b"aws_lambda_duration_ms"));
+        assert!(contains(b"aws_lambda_max_memory_used_mb"));
+        assert!(contains(&12.5f64.to_le_bytes()));
+    }
+
+    // Resets the first connection after reading the request, then answers 200
+    async fn reset_first_connection_server() -> std::net::SocketAddr {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buffer = [0u8; 4096];
+            let _ = stream.read(&mut buffer).await;
+            // A zero linger makes the close send RST instead of FIN; it does not block
+            #[allow(deprecated)]
+            stream.set_linger(Some(Duration::ZERO)).unwrap();
+            drop(stream);
+
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let _ = stream.read(&mut buffer).await;
+                let _ = stream
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                    .await;
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_connection_reset_is_retryable() {
+        let addr = reset_first_connection_server().await;
+
+        let error = Client::new().post(format!("http://{addr}/")).body("[]").send().await.unwrap_err();
+        let error = ExtensionError::Network(error);
+        assert!(error.is_connection_reset());
+        assert!(error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_connection_reset_retried_immediately() {
+        let addr = reset_first_connection_server().await;
+        let config = Config {
+            max_retries: 1,
+            initial_retry_delay_ms: 10_000,
+            ..test_config(format!("http://{addr}"))
+        };
+
+        let started = std::time::Instant::now();
+        send_batch_to_openobserve(&Client::new(), &config, b"[]").await.unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5), "reset retry should skip backoff");
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_destination() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket_path = dir.path().join("collector.sock");
+        let listener = tokio::net::UnixListener::bind(&socket_path).unwrap();
+        let (received_tx, mut received_rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = hyper::service::service_fn(move |request: http::Request<hyper::Body>| {
+                let received_tx = received_tx.clone();
+                async move {
+                    let uri = request.uri().to_string();
+                    let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+                    received_tx.send((uri, body.to_vec())).unwrap();
+                    Ok::<_, std::convert::Infallible>(http::Response::new(hyper::Body::empty()))
+                }
+            });
+            let _ = hyper::server::conn::Http::new().serve_connection(stream, service).await;
+        });
+        let config = test_config(format!("unix://{}", socket_path.display()));
+
+        send_batch_to_openobserve(&Client::new(), &config, b"[{\"msg\":\"hello\"}]").await.unwrap();
+
+        let (uri, body) = received_rx.recv().await.unwrap();
+        assert_eq!(uri, "/api/test_org/default/_json");
+        assert_eq!(body, b"[{\"msg\":\"hello\"}]");
+    }
+
+    #[tokio::test]
+    async fn test_allowlisted_client_error_is_retried() {
+        let server = MockServer::start().await;
+        server.push_status(http::StatusCode::from_u16(425).unwrap()).await;
+        let config = Config {
+            max_retries: 2,
+            initial_retry_delay_ms: 1,
+            retryable_statuses: vec![425],
+            ..test_config(server.endpoint())
+        };
+
+        send_batch_to_openobserve(&Client::new(), &config, b"[]").await.unwrap();
+        assert_eq!(server.request_count().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_batch_split_under_max_body_bytes() {
+        let server = MockServer::start().await;
+        server.set_max_body_bytes(100).await;
+        let batch: Vec<u8> = serde_json::to_vec(
+            &(0..10).map(|i| serde_json::json!({"msg": format!("log line {i}")})).collect::<Vec<_>>(),
+        ).unwrap();
+        assert!(batch.len() > 100);
+
+        // Without a limit the gateway rejects the whole batch
+        let unsplit = Config { max_retries: 0, ..test_config(server.endpoint()) };
+        let error = send_batch_to_openobserve(&Client::new(), &unsplit, &batch).await.unwrap_err();
+        assert!(matches!(error, ExtensionError::Server { status, .. } if status == reqwest::StatusCode::PAYLOAD_TOO_LARGE));
+
+        let config = Config {
+            http_max_body_bytes: 100,
+            ..unsplit
+        };
+        let events_sent = send_batch_to_openobserve(&Client::new(), &config, &batch).await.unwrap();
+        assert_eq!(events_sent, 10);
+
+        let requests = server.requests().await;
+        assert!(requests.len() > 2, "expected the batch to be split");
+        let mut received = 0;
+        for request in &requests[1..] {
+            assert!(request.body.len() <= 100);
+            received += serde_json::from_slice::<Vec<serde_json::Value>>(&request.body).unwrap().len();
+        }
+        assert_eq!(received, 10);
+    }
+
+    #[tokio::test]
+    async fn test_mirror_receives_batch() {
+        let primary = MockServer::start().await;
+        let mirror = MockServer::start().await;
+        let config = Config {
+            mirror_endpoint: Some(mirror.endpoint()),
+            mirror_authorization_header: Some("Basic bWlycm9y".to_string()),
+            ..test_config(primary.endpoint())
+        };
+
+        send_batch_to_openobserve(&Client::new(), &config, b"[{\"msg\":\"hello\"}]").await.unwrap();
+
+        let primary_requests = primary.requests().await;
+        let mirror_requests = mirror.requests().await;
+        assert_eq!(primary_requests.len(), 1);
+        assert_eq!(mirror_requests.len(), 1);
+        assert_eq!(mirror_requests[0].body, primary_requests[0].body);
+        assert_eq!(mirror_requests[0].headers["Authorization"], "Basic bWlycm9y");
+    }
+
+    #[tokio::test]
+    async fn test_mirror_failure_does_not_fail_primary() {
+        let primary = MockServer::start().await;
+        let mirror = MockServer::start().await;
+        mirror.set_status(http::StatusCode::INTERNAL_SERVER_ERROR).await;
+        let config = Config {
+            mirror_endpoint: Some(mirror.endpoint()),
+            ..test_config(primary.endpoint())
+        };
+
+        assert!(send_batch_to_openobserve(&Client::new(), &config, b"[]").await.is_ok());
+        assert_eq!(mirror.request_count().await, 1);
+    }
+
+    #[test]
+    fn test_adaptive_backoff_grows_and_recovers() {
+        let backoff = AdaptiveBackoff::new();
+        let config = Config {
+            initial_retry_delay_ms: 100,
+            max_retry_delay_ms: 1000,
+            ..Config::default()
+        };
+        assert_eq!(backoff.starting_delay_ms(&config), 100);
+
+        // A flapping endpoint gets progressively gentler treatment, up to the cap
+        backoff.record_failure();
+        assert_eq!(backoff.starting_delay_ms(&config), 200);
+        backoff.record_failure();
+        backoff.record_failure();
+        assert_eq!(backoff.starting_delay_ms(&config), 800);
+        backoff.record_failure();
+        assert_eq!(backoff.starting_delay_ms(&config), 1000);
+
+        // One success only steps back down; sustained success fully recovers
+        backoff.record_success();
+        assert_eq!(backoff.starting_delay_ms(&config), 800);
+        for _ in 0..10 {
+            backoff.record_success();
+        }
+        assert_eq!(backoff.starting_delay_ms(&config), 100);
+    }
+
+    #[tokio::test]
+    async fn test_backoff_is_kept_per_destination() {
+        let server = MockServer::start().await;
+        let mirror = MockServer::start().await;
+        let config = Config {
+            max_retries: 1,
+            initial_retry_delay_ms: 50,
+            max_retry_delay_ms: 1000,
+            mirror_endpoint: Some(mirror.endpoint()),
+            ..test_config(server.endpoint())
+        };
+        let client = Client::new();
+        let state = IngestState::new(&config);
+        let context = SendContext { state: &state, flush_strategy: None, trace_id: None, batch_seq: None };
+        
+        // A failed send leaves the primary starting its retries one step higher
+        server.push_status(http::StatusCode::SERVICE_UNAVAILABLE).await;
+        server.push_status(http::StatusCode::SERVICE_UNAVAILABLE).await;
+        send_flush_batch(&client, &config, b"[]", context).await.unwrap_err();
+        assert_eq!(state.primary.backoff.starting_delay_ms(&config), 100);
+        
+        // Retried, then delivered and mirrored; the mirror's success leaves the primary alone
+        server.push_status(http::StatusCode::SERVICE_UNAVAILABLE).await;
+        send_flush_batch(&client, &config, b"[]", context).await.unwrap();
+        assert_eq!(mirror.request_count().await, 1);
+        assert_eq!(state.primary.backoff.starting_delay_ms(&config), 100);
+        
+        server.push_status(http::StatusCode::SERVICE_UNAVAILABLE).await;
+        send_flush_batch(&client, &config, b"[]", context).await.unwrap();
+        let requests = server.requests().await;
+        let gap = requests[5].received_at - requests[4].received_at;
+        assert!(gap >= Duration::from_millis(100), "retried after {gap:?}");
+        
+        // A one-off send starts from the configured delay
+        server.push_status(http::StatusCode::SERVICE_UNAVAILABLE).await;
+        send_batch_to_openobserve(&client, &config, b"[]").await.unwrap();
+        let requests = server.requests().await;
+        let gap = requests[7].received_at - requests[6].received_at;
+        assert!(gap < Duration::from_millis(100), "retried after {gap:?}");
+    }
+
+    #[tokio::test]
+    async fn test_tls_min_version() {
+        let mut config = Config::default();
+        assert_eq!(tls_min_version(&config), reqwest::tls::Version::TLS_1_2);
+
+        config.tls_min_version = TlsVersion::Tls13;
+        assert_eq!(tls_min_version(&config), reqwest::tls::Version::TLS_1_3);
+
+        // Plain HTTP is unaffected by the TLS floor
+        let server = MockServer::start().await;
+        let client = egress_client_builder(&config).build().unwrap();
+        let config = Config { tls_min_version: TlsVersion::Tls13, ..test_config(server.endpoint()) };
+        assert!(send_batch_to_openobserve(&client, &config, b"[]").await.is_ok());
+    }
+
+    #[test]
+    fn test_parse_ingest_report() {
+        assert_eq!(
+            parse_ingest_report(r#"{"code":200,"status":[{"name":"default","successful":8,"failed":2,"error":"too old data"}]}"#),
+            Some(IngestReport { successful: 8, failed: 2, error: Some("too old data".to_string()) })
+        );
+        assert_eq!(parse_ingest_report("{}"), None);
+        assert_eq!(parse_ingest_report("ok"), None);
+    }
+
+    #[tokio::test]
+    async fn test_partial_success_response() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt().without_time().with_writer(move || writer.clone()).finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let server = MockServer::start().await;
+        let partial = r#"{"code":200,"status":[{"name":"default","successful":2,"failed":1,"error":"too old data"}]}"#;
+        server.push_response(http::StatusCode::OK, partial).await;
+        let batch = br#"[{"a":1,"b":1},{"a":2,"b":2},{"a":3,"b":3}]"#;
+
+        let config = Config { requeue_rejected: true, ..test_config(server.endpoint()) };
+        assert_eq!(send_batch_to_openobserve(&Client::new(), &config, batch).await.unwrap(), 2);
+        assert!(logs.output().contains("OpenObserve rejected 1 of 3 records: too old data"));
+
+        // Only a fully rejected batch is handed back for re-queuing
+        let rejected = r#"{"code":200,"status":[{"name":"default","successful":0,"failed":3,"error":"too old data"}]}"#;
+        server.push_response(http::StatusCode::OK, rejected).await;
+        let error = send_batch_to_openobserve(&Client::new(), &config, batch).await.unwrap_err();
+        assert!(matches!(error, ExtensionError::Rejected { count: 3, .. }));
+        assert_eq!(server.request_count().await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_xray_trace_header_propagation() {
+        let trace_id = "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1";
+        let server = MockServer::start().await;
+        let state = IngestState::default();
+        let context = SendContext { state: &state, flush_strategy: None, trace_id: Some(trace_id), batch_seq: None };
+
+        let config = test_config(server.endpoint());
+        send_flush_batch(&Client::new(), &config, b"[]", context).await.unwrap();
+
+        let config = Config { propagate_xray: true, ..test_config(server.endpoint()) };
+        send_flush_batch(&Client::new(), &config, b"[]", context).await.unwrap();
+        send_batch_to_openobserve(&Client::new(), &config, b"[]").await.unwrap();
+
+        let requests = server.requests().await;
+        assert!(requests[0].headers.get(XRAY_TRACE_HEADER).is_none());
+        assert_eq!(requests[1].headers[XRAY_TRACE_HEADER], trace_id);
+        assert!(requests[2].headers.get(XRAY_TRACE_HEADER).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_flush_request_timing() {
+        let server = MockServer::start().await;
+        server.set_delay(Duration::from_millis(30)).await;
+        let config = Config {
+            timing_detail: true,
+            ..test_config(server.endpoint())
+        };
+        let client = Client::new();
+        
+        let request = client.post(config.openobserve_url()).body("[]");
+        let (status, _, timing) = execute_request_timed(&client, &config, request).await.unwrap();
+        assert!(status.is_success());
+        assert!(timing.headers >= Duration::from_millis(30), "{timing:?}");
+        assert_eq!(timing.total, timing.headers + timing.body);
+        
+        // Totals are kept per destination, retries included
+        let mirror = MockServer::start().await;
+        let config = Config {
+            mirror_endpoint: Some(mirror.endpoint()),
+            initial_retry_delay_ms: 10,
+            ..config
+        };
+        let state = IngestState::new(&config);
+        assert_eq!(state.primary.timings.totals(), None);
+        server.push_status(http::StatusCode::SERVICE_UNAVAILABLE).await;
+        let context = SendContext { state: &state, flush_strategy: None, trace_id: None, batch_seq: None };
+        send_flush_batch(&client, &config, br#"[{"message":"hello"}]"#, context).await.unwrap();
+        
+        let primary = state.primary.timings.totals().unwrap();
+        assert_eq!(primary.requests, 2);
+        assert!(primary.headers >= Duration::from_millis(60), "{primary:?}");
+        assert!(primary.total >= primary.headers + primary.body, "{primary:?}");
+        assert_eq!(state.mirror.timings.totals().unwrap().requests, 1);
+    }
+
+    #[test]
+    fn test_batch_size_percentiles() {
+        let histogram = BatchSizeHistogram::new();
+        assert_eq!(histogram.percentiles(), None);
+
+        // 1..=100 KB batches carrying 1..=100 events, recorded out of order
+        for i in (1..=100u64).rev() {
+            histogram.record(i as usize * 1024, i);
+        }
+
+        let stats = histogram.percentiles().unwrap();
+        assert_eq!(stats.batches, 100);
+        assert_eq!(stats.p50_bytes, 50 * 1024);
+        assert_eq!(stats.p95_bytes, 95 * 1024);
+        assert_eq!(stats.p50_events, 50);
+        assert_eq!(stats.p95_events, 95);
+    }
+
+    #[test]
+    fn test_batch_size_histogram_keeps_recent_samples() {
+        let histogram = BatchSizeHistogram::new();
+        histogram.record(10, 1);
+        for _ in 0..BATCH_SIZE_SAMPLES {
+            histogram.record(500, 5);
+        }
+
+        let stats = histogram.percentiles().unwrap();
+        assert_eq!(stats.batches, BATCH_SIZE_SAMPLES);
+        assert_eq!(stats.p50_bytes, 500);
+        assert_eq!(stats.p95_events, 5);
+    }
+
+    #[tokio::test]
+    async fn test_http1_version() {
+        let server = MockServer::start().await;
+        let config = Config {
+            http_version: HttpVersion::Http1,
+            ..test_config(server.endpoint())
+        };
+        let client = client_builder(&config).build().unwrap();
+
+        send_batch_to_openobserve(&client, &config, b"[]").await.unwrap();
+
+        assert_eq!(server.requests().await[0].version, http::Version::HTTP_11);
+    }
+
+    #[tokio::test]
+    async fn test_ip_version_preference() {
+        let server = MockServer::start().await;
+        let send = |ip_version: IpVersion| {
+            let config = Config { ip_version, max_retries: 0, ..test_config(server.endpoint()) };
+            async move {
+                let client = client_builder(&config).build().unwrap();
+                send_batch_to_openobserve(&client, &config, b"[]").await
+            }
+        };
+
+        // The mock listens on 127.0.0.1 only
+        assert!(send(IpVersion::Auto).await.is_ok());
+        assert!(send(IpVersion::V4).await.is_ok());
+        assert!(send(IpVersion::V6).await.is_err());
+        assert_eq!(server.request_count().await, 2);
+
+        let config = Config { ip_version: IpVersion::V6, ..test_config(server.endpoint()) };
+        assert!(probe_endpoint(&config, Duration::from_secs(1)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_content_type_override() {
+        let server = MockServer::start().await;
+        let config = Config {
+            content_type: "application/x-ndjson".to_string(),
+            ..test_config(server.endpoint())
+        };
+
+        send_batch_to_openobserve(&Client::new(), &config, b"[{\"a\":1}]").await.unwrap();
+
+        let requests = server.requests().await;
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].headers["content-type"], "application/x-ndjson");
+    }
+}
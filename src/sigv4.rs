@@ -0,0 +1,267 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::config::Config;
+use crate::error::ExtensionError;
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+
+// Credentials from the Lambda execution role, as exposed in the function environment
+#[derive(Clone)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+impl AwsCredentials {
+    pub fn from_env() -> Result<Self, ExtensionError> {
+        let access_key_id = std::env::var("AWS_ACCESS_KEY_ID")
+            .map_err(|_| ExtensionError::Config("AWS_ACCESS_KEY_ID is required for SigV4 signing".to_string()))?;
+        let secret_access_key = std::env::var("AWS_SECRET_ACCESS_KEY")
+            .map_err(|_| ExtensionError::Config("AWS_SECRET_ACCESS_KEY is required for SigV4 signing".to_string()))?;
+        let session_token = std::env::var("AWS_SESSION_TOKEN").ok();
+
+        Ok(Self { access_key_id, secret_access_key, session_token })
+    }
+}
+
+// Keep the secret out of logged configs
+impl std::fmt::Debug for AwsCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AwsCredentials")
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &"<redacted>")
+            .field("session_token", &self.session_token.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+// Headers to attach to a signed request
+pub struct SignedHeaders {
+    pub authorization: String,
+    pub amz_date: String,
+    pub content_sha256: String,
+    pub security_token: Option<String>,
+}
+
+// Signs requests with AWS Signature Version 4
+pub struct SigV4Signer {
+    pub credentials: AwsCredentials,
+    pub region: String,
+    pub service: String,
+}
+
+impl SigV4Signer {
+    // Signer for the credentials and region loaded into the config
+    pub fn from_config(config: &Config) -> Result<Self, ExtensionError> {
+        let credentials = config.aws_credentials.clone()
+            .ok_or_else(|| ExtensionError::Config("AWS_ACCESS_KEY_ID is required for SigV4 signing".to_string()))?;
+        let region = config.aws_region.clone()
+            .ok_or_else(|| ExtensionError::Config("AWS_REGION is required for SigV4 signing".to_string()))?;
+
+        Ok(Self {
+            credentials,
+            region,
+            service: config.sigv4_service.clone(),
+        })
+    }
+
+    /// Sign a request. `extra_headers` are other headers sent with the request that the signature should cover.
+    pub fn sign(
+        &self,
+        method: &str,
+        url: &Url,
+        extra_headers: &[(&str, &str)],
+        body: &[u8],
+        now: DateTime<Utc>,
+    ) -> SignedHeaders {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let content_sha256 = hex::encode(Sha256::digest(body));
+
+        let mut headers: Vec<(String, String)> = vec![
+            ("host".to_string(), host_header(url)),
+            ("x-amz-date".to_string(), amz_date.clone()),
+            ("x-amz-content-sha256".to_string(), content_sha256.clone()),
+        ];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token".to_string(), token.clone()));
+        }
+        for (name, value) in extra_headers {
+            headers.push((name.to_ascii_lowercase(), value.trim().to_string()));
+        }
+
+        let authorization = self.authorization_header(method, url, &mut headers, &content_sha256, now);
+
+        SignedHeaders {
+            authorization,
+            amz_date,
+            content_sha256,
+            security_token: self.credentials.session_token.clone(),
+        }
+    }
+
+    // Build the Authorization header value for the given (lowercase) headers and payload hash
+    fn authorization_header(
+        &self,
+        method: &str,
+        url: &Url,
+        headers: &mut [(String, String)],
+        payload_hash: &str,
+        now: DateTime<Utc>,
+    ) -> String {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let scope = format!("{date}/{}/{}/aws4_request", self.region, self.service);
+
+        headers.sort_by(|a, b| a.0.cmp(&b.0));
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{name}:{value}\n"))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+
+        let canonical_request = format!(
+            "{method}\n{}\n{}\n{canonical_headers}\n{signed_headers}\n{payload_hash}",
+            canonical_path(url, &self.service),
+            canonical_query(url),
+        );
+
+        let string_to_sign = format!(
+            "{ALGORITHM}\n{amz_date}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let secret = format!("AWS4{}", self.credentials.secret_access_key);
+        let k_date = hmac_sha256(secret.as_bytes(), date.as_bytes());
+        let k_region = hmac_sha256(&k_date, self.region.as_bytes());
+        let k_service = hmac_sha256(&k_region, self.service.as_bytes());
+        let k_signing = hmac_sha256(&k_service, b"aws4_request");
+        let signature = hex::encode(hmac_sha256(&k_signing, string_to_sign.as_bytes()));
+
+        format!(
+            "{ALGORITHM} Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            self.credentials.access_key_id
+        )
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn host_header(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => host.to_string(),
+    }
+}
+
+// Url keeps the path percent-encoded already. Services other than S3 sign each segment
+// encoded once more (e.g. "app%2Flogs" -> "app%252Flogs"); S3 signs the path as sent.
+fn canonical_path(url: &Url, service: &str) -> String {
+    let path = url.path();
+    if path.is_empty() {
+        return "/".to_string();
+    }
+    if service == "s3" {
+        return path.to_string();
+    }
+    path.split('/').map(uri_encode).collect::<Vec<_>>().join("/")
+}
+
+fn canonical_query(url: &Url) -> String {
+    let mut pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(k, v)| (uri_encode(&k), uri_encode(&v)))
+        .collect();
+    pairs.sort();
+    pairs
+        .into_iter()
+        .map(|(k, v)| format!("{k}={v}"))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+// RFC 3986 encoding as required by SigV4 (unreserved characters are left as-is). Also keeps a
+// value to a single URL path segment (e.g. "app/logs" -> "app%2Flogs").
+pub(crate) fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example_signer() -> SigV4Signer {
+        SigV4Signer {
+            credentials: AwsCredentials {
+                access_key_id: "AKIDEXAMPLE".to_string(),
+                secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+                session_token: None,
+            },
+            region: "us-east-1".to_string(),
+            service: "service".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_aws_get_vanilla_vector() {
+        // "get-vanilla" from the AWS SigV4 test suite
+        let url = Url::parse("https://example.amazonaws.com/").unwrap();
+        let now = DateTime::parse_from_rfc3339("2015-08-30T12:36:00Z").unwrap().with_timezone(&Utc);
+        let mut headers = vec![
+            ("host".to_string(), "example.amazonaws.com".to_string()),
+            ("x-amz-date".to_string(), "20150830T123600Z".to_string()),
+        ];
+        let empty_hash = hex::encode(Sha256::digest(b""));
+
+        let authorization = example_signer().authorization_header("GET", &url, &mut headers, &empty_hash, now);
+
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+    }
+
+    #[test]
+    fn test_sign_includes_session_token() {
+        let mut signer = example_signer();
+        signer.credentials.session_token = Some("token".to_string());
+        let url = Url::parse("https://example.amazonaws.com/api/org/stream/_json").unwrap();
+
+        let signed = signer.sign("POST", &url, &[("Content-Type", "application/json")], b"[]", Utc::now());
+
+        assert!(signed.authorization.contains("SignedHeaders=content-type;host;x-amz-content-sha256;x-amz-date;x-amz-security-token"));
+        assert_eq!(signed.security_token.as_deref(), Some("token"));
+    }
+
+    #[test]
+    fn test_canonical_path_encodes_segments_again() {
+        let url = Url::parse("https://example.amazonaws.com/api/org/app%2Flogs/_json").unwrap();
+        assert_eq!(canonical_path(&url, "es"), "/api/org/app%252Flogs/_json");
+        assert_eq!(canonical_path(&url, "s3"), "/api/org/app%2Flogs/_json");
+    }
+
+    #[test]
+    fn test_uri_encode() {
+        assert_eq!(uri_encode("a b/c~d"), "a%20b%2Fc~d");
+    }
+}
@@ -2,13 +2,55 @@ use anyhow::{anyhow, Result};
 use chrono::{DateTime, Utc};
 use http::{Request, Response, StatusCode};
 use hyper::{body, Body, Server};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
 use std::convert::Infallible;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use tracing::error;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, Semaphore};
+use tracing::{debug, error, info, warn};
+
+use crate::config::{Config, MetricsExport, OutputEncoding, TimestampFormat, TimestampUnit};
+use crate::error::ExtensionError;
+
+// Batch entry cap used when building the aggregator from config
+const MAX_BATCH_ENTRIES: usize = 100;
+// Capacity kept by the batch buffer and message queue after an idle shrink
+const BUFFER_BASELINE_CAPACITY: usize = 64 * 1024;
+const QUEUE_BASELINE_CAPACITY: usize = MAX_BATCH_ENTRIES;
+// Minimum time between "buffer nearly full" warnings
+const BUFFER_WARN_INTERVAL: Duration = Duration::from_secs(60);
+// Recently seen event identities remembered for O2_TELEMETRY_DEDUP
+const DEDUP_WINDOW: usize = 4096;
+// Times a rejected event is put back (O2_REQUEUE_REJECTED) before it is dropped
+pub const MAX_REQUEUE_ATTEMPTS: u32 = 3;
+
+// Number of consecutive ports to try when the configured one is taken
+const PORT_FALLBACK_ATTEMPTS: u16 = 10;
+
+// Longest a single Telemetry API subscribe request may take, so a re-subscribe can't stall an invoke
+const SUBSCRIBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+// Identifies this extension process for the lifetime of the sandbox (or SnapStart restore).
+// Process-wide so the startup event, forwarded deliveries and the main stream all agree.
+static INSTANCE_ID: OnceLock<RwLock<Arc<str>>> = OnceLock::new();
+
+fn instance_id_slot() -> &'static RwLock<Arc<str>> {
+    INSTANCE_ID.get_or_init(|| RwLock::new(uuid::Uuid::new_v4().to_string().into()))
+}
+
+// The ID tagged on events as `instance_id`
+pub fn instance_id() -> Arc<str> {
+    Arc::clone(&instance_id_slot().read().unwrap())
+}
+
+// Sandboxes restored from one snapshot would otherwise share an ID
+fn regenerate_instance_id() {
+    *instance_id_slot().write().unwrap() = uuid::Uuid::new_v4().to_string().into();
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TelemetryEvent {
@@ -20,266 +62,2833 @@ pub struct TelemetryEvent {
     pub request_id: Option<String>,
 }
 
+// Hostname the Lambda sandbox resolves to the extension, for Telemetry API deliveries
+pub const DEFAULT_CALLBACK_HOST: &str = "sandbox.localdomain";
+
+// Numeric platform.report metrics and the names they are exported under
+const PLATFORM_REPORT_METRICS: &[(&str, &str)] = &[
+    ("durationMs", "aws_lambda_duration_ms"),
+    ("billedDurationMs", "aws_lambda_billed_duration_ms"),
+    ("initDurationMs", "aws_lambda_init_duration_ms"),
+    ("memorySizeMB", "aws_lambda_memory_size_mb"),
+    ("maxMemoryUsedMB", "aws_lambda_max_memory_used_mb"),
+];
+
+// A single platform metric value extracted from a report event
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricSample {
+    pub name: &'static str,
+    pub value: f64,
+    pub timestamp_ms: i64,
+}
+
+// An event in OpenObserve format, serialized straight from borrowed parts. Fields are in
+// alphabetical order so the output matches what a serde_json::Value map would produce.
+#[derive(Serialize)]
+struct OutputEvent<'a> {
+    #[serde(rename = "_raw", skip_serializing_if = "Option::is_none")]
+    raw: Option<serde_json::Value>,
+    #[serde(rename = "_seq", skip_serializing_if = "Option::is_none")]
+    seq: Option<u64>,
+    #[serde(rename = "_timestamp")]
+    timestamp: serde_json::Value,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    depth_truncated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    extension_version: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    function_arn: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    init_type: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    instance_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pid: Option<u32>,
+    #[serde(flatten)]
+    record: RecordField<'a>,
+    #[serde(rename = "requestId", skip_serializing_if = "Option::is_none")]
+    request_id: Option<&'a str>,
+    #[serde(rename = "type")]
+    event_type: &'a str,
+}
+
+// The record, under "record" or the O2_RECORD_AS_MESSAGE_FIELD name
+struct RecordField<'a> {
+    key: &'a str,
+    value: &'a serde_json::Value,
+}
+
+impl Serialize for RecordField<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(1))?;
+        map.serialize_entry(self.key, self.value)?;
+        map.end()
+    }
+}
+
+// A serialized event waiting to be batched
+#[derive(Clone)]
+struct QueuedMessage {
+    // Only tracked when batching by request id
+    request_id: Option<String>,
+    json: String,
+    // Arrival time, only tracked with O2_TRACK_LAG
+    received_at: Option<Instant>,
+    // How many times the event was put back after OpenObserve rejected it
+    requeues: u32,
+    // Dedup identity, only tracked when events are marked seen once their batch is sent
+    identity: Option<u64>,
+    // Sequence number of the batch the event was put back from, so the batch keeps its key
+    batch_seq: Option<u64>,
+}
+
+// A batch taken for sending, keeping its events so a rejected batch can be put back as it was
+#[derive(Clone)]
+pub struct PendingBatch {
+    pub body: Vec<u8>,
+    // Numbers the batch for its idempotency key; kept when the batch is put back and retaken
+    pub seq: u64,
+    messages: Vec<QueuedMessage>,
+}
+
+impl PendingBatch {
+    pub fn idempotency_key(&self) -> String {
+        crate::openobserve::batch_idempotency_key(&self.body, Some(self.seq))
+    }
+    
+    // Events in the batch
+    pub fn entries(&self) -> usize {
+        self.messages.len()
+    }
+}
+
+// One request id's queued events, each with its arrival number
+type RequestGroup = VecDeque<(i64, QueuedMessage)>;
+
+// Queued events kept per request id for O2_BATCH_BY_REQUEST_ID, so a batch can take one
+// invocation's events without walking everything else that is queued
+#[derive(Default)]
+struct RequestGroups {
+    groups: HashMap<Option<String>, RequestGroup>,
+    // Each group under the arrival number of its oldest event, so batches go oldest first
+    oldest: BTreeMap<i64, Option<String>>,
+    // Arrival numbers for new events count up; requeued events count down from -1
+    next_back: i64,
+    next_front: i64,
+    len: usize,
+}
+
+impl RequestGroups {
+    fn push_back(&mut self, message: QueuedMessage) {
+        let seq = self.next_back;
+        self.next_back += 1;
+        self.insert(seq, message, VecDeque::push_back);
+    }
+    
+    // Ahead of everything queued, as for a requeued batch
+    fn push_front(&mut self, message: QueuedMessage) {
+        self.next_front -= 1;
+        let seq = self.next_front;
+        self.insert(seq, message, VecDeque::push_front);
+    }
+    
+    fn insert(
+        &mut self,
+        seq: i64,
+        message: QueuedMessage,
+        push: fn(&mut RequestGroup, (i64, QueuedMessage)),
+    ) {
+        self.len += 1;
+        let Some(group) = self.groups.get_mut(&message.request_id) else {
+            self.oldest.insert(seq, message.request_id.clone());
+            self.groups.insert(message.request_id.clone(), VecDeque::from([(seq, message)]));
+            return;
+        };
+        let old_front = group.front().map(|(front, _)| *front);
+        push(group, (seq, message));
+        if let Some((front, message)) = group.front().filter(|(front, _)| Some(*front) != old_front) {
+            if let Some(old_front) = old_front {
+                self.oldest.remove(&old_front);
+            }
+            self.oldest.insert(*front, message.request_id.clone());
+        }
+    }
+    
+    // The group holding the oldest queued event, taken out until put_group_back
+    fn take_oldest_group(&mut self) -> Option<(Option<String>, RequestGroup)> {
+        let (_, key) = self.oldest.pop_first()?;
+        let group = self.groups.remove(&key).unwrap_or_default();
+        self.len -= group.len();
+        Some((key, group))
+    }
+    
+    fn put_group_back(&mut self, key: Option<String>, group: RequestGroup) {
+        if let Some((front, _)) = group.front() {
+            self.oldest.insert(*front, key.clone());
+            self.len += group.len();
+            self.groups.insert(key, group);
+        }
+    }
+    
+    fn pop_oldest(&mut self) -> Option<QueuedMessage> {
+        let (key, mut group) = self.take_oldest_group()?;
+        let message = group.pop_front().map(|(_, message)| message);
+        self.put_group_back(key, group);
+        message
+    }
+}
+
+// Room left in the batch for the `,"lag_ms":N` suffix added to each event by O2_TRACK_LAG
+const LAG_FIELD_RESERVE: usize = 32;
+
 // aggregator - exactly like their implementation
 pub struct TelemetryAggregator {
-    messages: VecDeque<String>,
+    messages: VecDeque<QueuedMessage>,
+    // Used instead of `messages` with O2_BATCH_BY_REQUEST_ID
+    request_groups: RequestGroups,
+    buffered_bytes: usize,
+    // High-water mark of buffered_bytes over the aggregator's lifetime
+    peak_buffered_bytes: usize,
+    oldest_message_at: Option<Instant>,
+    last_activity: Instant,
+    shrink_idle: Option<Duration>,
     buffer: Vec<u8>,
+    // Reused for serializing each event, so small frequent deliveries don't reallocate it
+    scratch: Vec<u8>,
     max_content_size_bytes: usize,
     max_batch_entries_size: usize,
+    // Batch size get_batch packs up to (O2_TARGET_BATCH_BYTES), 0 = fill to the limits
+    target_batch_bytes: usize,
+    max_queue_len: usize,
+    buffer_warn_percent: u64,
+    last_buffer_warning: Option<Instant>,
+    buffer_warnings: u64,
+    batch_by_request_id: bool,
+    drop_platform_events: bool,
+    exclude_patterns: Vec<Regex>,
+    excluded_count: u64,
+    multiline_pattern: Option<Regex>,
+    // Last function log line and when it arrived, waiting for continuation lines
+    pending_multiline: Option<(TelemetryEvent, Instant)>,
+    dedup: bool,
+    seen_events: HashSet<u64>,
+    seen_order: VecDeque<u64>,
+    duplicate_count: u64,
+    // Set by the immediate forwarder: events count as seen only once their batch is sent
+    mark_seen_on_send: bool,
+    oversized_count: u64,
+    // Sequence number for the next batch taken, starting at a random point per aggregator
+    next_batch_seq: u64,
+    timestamp_field: Option<String>,
+    timestamp_unit: TimestampUnit,
+    timestamp_format: TimestampFormat,
+    field_renames: Vec<(String, String)>,
+    // Record keys to keep (O2_INCLUDE_FIELDS); empty keeps them all
+    include_fields: Vec<String>,
+    // Emitted type names (O2_TYPE_MAP), by full type or by category
+    type_map: Vec<(String, String)>,
+    record_field: String,
+    // Parsed O2_EVENT_TEMPLATE, rendered in place of the default envelope
+    event_template: Option<serde_json::Value>,
+    function_name: Option<String>,
+    // Set with O2_OUTPUT_ENCODING=csv; queued messages are then CSV rows
+    csv_columns: Option<Vec<String>>,
+    max_record_depth: usize,
+    include_instance_id: bool,
+    tag_version: bool,
+    include_raw: bool,
+    emit_request_id: bool,
+    preserve_order: bool,
+    next_seq: u64,
+    track_lag: bool,
+    init_type: Option<String>,
+    // Set once known, with O2_INCLUDE_ARN
+    function_arn: Option<String>,
+    memory_flush_threshold_mb: Option<f64>,
+    flush_requested: bool,
+    flush_on_types: Vec<String>,
+    // Types only counted per minute (O2_COUNT_ONLY_TYPES), and the counts by (minute, type)
+    count_only_types: Vec<String>,
+    type_counts: BTreeMap<(i64, String), u64>,
+    collect_platform_metrics: bool,
+    metric_samples: VecDeque<MetricSample>,
 }
 
 impl TelemetryAggregator {
     pub fn new(max_content_size_bytes: usize, max_batch_entries_size: usize) -> Self {
         Self {
             messages: VecDeque::new(),
+            request_groups: RequestGroups::default(),
+            buffered_bytes: 0,
+            peak_buffered_bytes: 0,
+            oldest_message_at: None,
+            last_activity: Instant::now(),
+            shrink_idle: None,
             buffer: Vec::with_capacity(max_content_size_bytes),
+            scratch: Vec::new(),
             max_content_size_bytes,
             max_batch_entries_size,
+            target_batch_bytes: 0,
+            max_queue_len: usize::MAX,
+            buffer_warn_percent: 0,
+            last_buffer_warning: None,
+            buffer_warnings: 0,
+            batch_by_request_id: false,
+            drop_platform_events: false,
+            exclude_patterns: Vec::new(),
+            multiline_pattern: None,
+            pending_multiline: None,
+            excluded_count: 0,
+            dedup: false,
+            seen_events: HashSet::new(),
+            seen_order: VecDeque::new(),
+            duplicate_count: 0,
+            mark_seen_on_send: false,
+            oversized_count: 0,
+            next_batch_seq: uuid::Uuid::new_v4().as_u64_pair().0,
+            timestamp_field: None,
+            timestamp_unit: TimestampUnit::Micros,
+            timestamp_format: TimestampFormat::Epoch,
+            field_renames: Vec::new(),
+            include_fields: Vec::new(),
+            type_map: Vec::new(),
+            event_template: None,
+            function_name: None,
+            record_field: "record".to_string(),
+            csv_columns: None,
+            max_record_depth: 0,
+            include_instance_id: false,
+            tag_version: false,
+            include_raw: false,
+            emit_request_id: true,
+            preserve_order: false,
+            track_lag: false,
+            next_seq: 0,
+            init_type: None,
+            function_arn: None,
+            memory_flush_threshold_mb: None,
+            flush_requested: false,
+            flush_on_types: Vec::new(),
+            count_only_types: Vec::new(),
+            type_counts: BTreeMap::new(),
+            collect_platform_metrics: false,
+            metric_samples: VecDeque::new(),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        let mut aggregator = Self::new(config.max_buffer_size_bytes(), MAX_BATCH_ENTRIES);
+        aggregator.max_queue_len = config.max_queue_len;
+        if config.target_batch_bytes > 0 {
+            // Batches are sized by bytes instead of entries
+            aggregator.target_batch_bytes = config.target_batch_bytes;
+            aggregator.max_batch_entries_size = usize::MAX;
+        }
+        aggregator.buffer_warn_percent = config.buffer_warn_percent;
+        aggregator.batch_by_request_id = config.batch_by_request_id;
+        aggregator.shrink_idle = (config.buffer_shrink_idle_ms > 0)
+            .then(|| Duration::from_millis(config.buffer_shrink_idle_ms));
+        aggregator.drop_platform_events = config.drop_platform_events;
+        // Patterns are checked by Config::validate, so anything invalid here is skipped
+        aggregator.exclude_patterns = config.exclude_patterns
+            .iter()
+            .filter_map(|pattern| Regex::new(pattern).ok())
+            .collect();
+        aggregator.multiline_pattern = config.merge_multiline
+            .then(|| Regex::new(&config.multiline_pattern).ok())
+            .flatten();
+        aggregator.timestamp_field = config.timestamp_field.clone();
+        aggregator.timestamp_unit = config.timestamp_unit;
+        aggregator.timestamp_format = config.timestamp_format;
+        aggregator.field_renames = config.field_renames.clone();
+        aggregator.include_fields = config.include_fields.clone();
+        aggregator.type_map = config.type_map.clone();
+        if let Some(field) = &config.record_field {
+            aggregator.record_field = field.clone();
         }
+        // Templates are checked by Config::validate, so an invalid one is ignored here
+        aggregator.event_template = config.event_template.as_deref()
+            .and_then(|template| crate::config::parse_event_template(template).ok());
+        aggregator.function_name = config.function_name.clone();
+        if config.output_encoding == OutputEncoding::Csv {
+            aggregator.csv_columns = Some(config.csv_columns.clone());
+        }
+        aggregator.max_record_depth = config.max_record_depth;
+        aggregator.include_instance_id = config.include_instance_id;
+        aggregator.tag_version = config.tag_version;
+        aggregator.include_raw = config.include_raw;
+        aggregator.emit_request_id = config.emit_request_id;
+        aggregator.preserve_order = config.preserve_order;
+        aggregator.track_lag = config.track_lag;
+        aggregator.dedup = config.telemetry_dedup;
+        aggregator.init_type = config.init_type.clone();
+        aggregator.memory_flush_threshold_mb = config.memory_flush_threshold_mb();
+        aggregator.flush_on_types = config.flush_on_types.clone();
+        aggregator.count_only_types = config.count_only_types.clone();
+        aggregator.collect_platform_metrics = config.metrics_export != MetricsExport::None;
+        aggregator
     }
 
     // add a batch of events immediately
     pub fn add_batch(&mut self, events: Vec<TelemetryEvent>) {
-        for event in events {
-            // Convert to OpenObserve format: add _timestamp and remove time
-            let mut event_json = serde_json::json!({
-                "_timestamp": event.time.timestamp_micros(),
-                "record": event.record,
-                "type": event.event_type
-            });
+        self.last_activity = Instant::now();
+        // Batches are added as soon as the Telemetry API delivers them
+        let received_at = self.track_lag.then_some(self.last_activity);
+        let events = self.merge_multiline(events);
+        self.enqueue_events(events, received_at);
+    }
+    
+    fn enqueue_events(&mut self, events: Vec<TelemetryEvent>, received_at: Option<Instant>) {
+        for mut event in events {
+            let identity = (self.dedup && self.mark_seen_on_send).then(|| event_identity(&event));
+            if identity.is_some_and(|identity| self.seen_events.contains(&identity)) {
+                self.duplicate_count += 1;
+                continue;
+            }
             
-            // Add requestId if present
-            if let Some(request_id) = event.request_id {
-                event_json["requestId"] = serde_json::Value::String(request_id);
+            // Checked before filtering so dropping platform events doesn't hide memory pressure
+            if !self.flush_requested && self.triggers_flush(&event) {
+                self.flush_requested = true;
+            }
+            if event.event_type == "platform.restoreStart" && self.init_type.as_deref() == Some("snap-start") {
+                regenerate_instance_id();
+            }
+            if event.event_type == "platform.report" {
+                self.check_memory_pressure(&event.record);
+                if self.collect_platform_metrics {
+                    self.collect_metric_samples(&event);
+                }
             }
             
-            // Serialize to JSON string
-            if let Ok(json_str) = serde_json::to_string(&event_json) {
-                self.messages.push_back(json_str);
+            // Platform events are still subscribed to, just not forwarded
+            if self.drop_platform_events && is_platform_event(&event.event_type) {
+                continue;
             }
-        }
-    }
-
-    // returns JSON array bytes
-    pub fn get_batch(&mut self) -> Vec<u8> {
-        self.buffer.extend(b"[");
-
-        // Fill the batch with events from the messages
-        for _ in 0..self.max_batch_entries_size {
-            if let Some(event_json) = self.messages.pop_front() {
-                // Check if the buffer will be full after adding the event
-                if self.buffer.len() + event_json.len() > self.max_content_size_bytes {
-                    // Put the event back in the queue
-                    self.messages.push_front(event_json);
-                    break;
+            
+            if self.is_excluded(&event.record) {
+                self.excluded_count += 1;
+                continue;
+            }
+            
+            if self.count_only(&event) {
+                continue;
+            }
+            
+            // Drop record keys outside the allow-list before anything (even the raw copy) sees them
+            if !self.include_fields.is_empty() {
+                if let Some(record) = event.record.as_object_mut() {
+                    record.retain(|key, _| self.include_fields.contains(key));
                 }
-
-                self.buffer.extend(event_json.as_bytes());
-                self.buffer.extend(b",");
-            } else {
-                break;
             }
+            
+            self.push_event(event, received_at, identity);
         }
-
-        // Make sure we added at least one element
-        if self.buffer.len() > 1 {
-            // Remove the last comma and close bracket
-            self.buffer.pop();
-            self.buffer.extend(b"]");
-        } else {
-            // No elements, remove opening bracket
-            self.buffer.pop();
+        
+        // Bound the queue by count too, dropping the oldest like a full buffer does
+        let overflow = self.queued_len().saturating_sub(self.max_queue_len);
+        if overflow > 0 {
+            let dropped: usize = if self.batch_by_request_id {
+                (0..overflow).filter_map(|_| self.request_groups.pop_oldest()).map(|message| message.json.len()).sum()
+            } else {
+                self.messages.drain(..overflow).map(|message| message.json.len()).sum()
+            };
+            self.buffered_bytes -= dropped;
+            warn!("⚠️ Telemetry queue full ({} messages), dropped {} oldest", self.max_queue_len, overflow);
+        }
+        
+        self.peak_buffered_bytes = self.peak_buffered_bytes.max(self.buffered_bytes);
+        self.warn_if_near_capacity();
+        
+        let overflow = self.metric_samples.len().saturating_sub(self.max_queue_len);
+        if overflow > 0 {
+            self.metric_samples.drain(..overflow);
+            warn!("⚠️ Metric queue full ({} samples), dropped {} oldest", self.max_queue_len, overflow);
         }
-
-        std::mem::take(&mut self.buffer)
     }
-
-}
-
-// Note: TelemetryProcessor removed - events now added directly to aggregator
-// Note: TelemetryFlusher removed - using synchronous flush in extension.rs
-
-pub struct TelemetrySubscriber {
-    port: u16,
-    aggregator: Arc<Mutex<TelemetryAggregator>>,
-    server_handle: Option<tokio::task::JoinHandle<()>>,
-}
-
-impl TelemetrySubscriber {
-    pub fn new(port: u16, aggregator: Arc<Mutex<TelemetryAggregator>>) -> Self {
-        Self {
-            port,
-            aggregator,
-            server_handle: None,
+    
+    // With O2_COUNT_ONLY_TYPES, count the event in its minute instead of queueing it
+    fn count_only(&mut self, event: &TelemetryEvent) -> bool {
+        if !self.count_only_types.contains(&event.event_type) {
+            return false;
         }
+        let minute = event.time.timestamp().div_euclid(60);
+        *self.type_counts.entry((minute, event.event_type.clone())).or_default() += 1;
+        true
     }
     
-    pub async fn start(&mut self) -> Result<()> {
-        let addr = SocketAddr::from(([0, 0, 0, 0], self.port));
-        let aggregator = Arc::clone(&self.aggregator);
-        
-        let make_svc = hyper::service::make_service_fn(move |_conn| {
-            let aggregator = Arc::clone(&aggregator);
-            async move {
-                Ok::<_, Infallible>(hyper::service::service_fn(move |req| {
-                    handle_telemetry_request(req, Arc::clone(&aggregator))
-                }))
+    // Queue one count event per type for each finished minute, or for every minute counted
+    // so far when `all` is set (on SHUTDOWN, when the current minute won't finish)
+    pub fn release_counts(&mut self, all: bool) {
+        let current_minute = Utc::now().timestamp().div_euclid(60);
+        let counts = std::mem::take(&mut self.type_counts);
+        for ((minute, event_type), count) in counts {
+            if !all && minute >= current_minute {
+                self.type_counts.insert((minute, event_type), count);
+                continue;
             }
-        });
-        
-        let server = Server::bind(&addr).serve(make_svc);
+            let time = DateTime::from_timestamp(minute * 60, 0).unwrap_or_else(Utc::now);
+            self.push_event(TelemetryEvent {
+                time,
+                event_type,
+                record: serde_json::json!({"count": count, "window_secs": 60}),
+                request_id: None,
+            }, None, None);
+        }
+        if self.queued_len() > 0 && self.oldest_message_at.is_none() {
+            self.oldest_message_at = Some(Instant::now());
+        }
+    }
+    
+    // Serialize an event into the OpenObserve envelope (or O2_EVENT_TEMPLATE) and queue it
+    fn push_event(&mut self, mut event: TelemetryEvent, received_at: Option<Instant>, identity: Option<u64>) {
+        // Snapshot before any reshaping so the envelope can be compared with the output
+        let raw = if self.include_raw {
+            serde_json::to_value(&event).ok()
+        } else {
+            None
+        };
         
+        // Prefer the log's own timestamp when configured, falling back to delivery time
+        let timestamp_micros = self.timestamp_field.as_deref()
+            .and_then(|field| extract_timestamp_micros(&event.record, field))
+            .unwrap_or_else(|| event.time.timestamp_micros());
         
-        let server_handle = tokio::spawn(async move {
-            if let Err(e) = server.await {
-                error!("❌ Telemetry subscriber server error: {}", e);
+        // Move record keys that would collide with reserved or envelope fields
+        if let Some(record) = event.record.as_object_mut() {
+            for (from, to) in &self.field_renames {
+                if let Some(value) = record.remove(from) {
+                    record.insert(to.clone(), value);
+                }
             }
-        });
+        }
         
-        self.server_handle = Some(server_handle);
+        // Platform events carry the invocation's id inside the record
+        let group_request_id = if self.batch_by_request_id {
+            event.request_id.clone().or_else(|| {
+                event.record.get("requestId").and_then(|id| id.as_str()).map(str::to_string)
+            })
+        } else {
+            None
+        };
         
-        Ok(())
-    }
-    
-    pub async fn subscribe_to_telemetry_api(&self, extension_id: &str) -> Result<()> {
-        let runtime_api_endpoint = std::env::var("AWS_LAMBDA_RUNTIME_API")
-            .unwrap_or_else(|_| "localhost:9001".to_string());
+        let depth_truncated = self.max_record_depth > 0
+            && truncate_depth(&mut event.record, self.max_record_depth);
         
-        let url = format!("http://{runtime_api_endpoint}/2022-07-01/telemetry");
+        let timestamp = match self.timestamp_format {
+            TimestampFormat::Epoch => serde_json::Value::from(self.timestamp_unit.convert_micros(timestamp_micros)),
+            TimestampFormat::Rfc3339 => DateTime::from_timestamp_micros(timestamp_micros)
+                .unwrap_or(event.time)
+                .to_rfc3339()
+                .into(),
+        };
         
-        let subscription = serde_json::json!({
-            "schemaVersion": "2022-12-13",
-            "destination": {
-                "protocol": "HTTP",
-                "URI": format!("http://sandbox.localdomain:{}", self.port)
-            },
-            "types": ["platform", "function", "extension"],
-            "buffering": {
-                "maxBytes": 262144, // maxBytes should be between 262144 and 10485760
-                "maxItems": 1000, // maxItems should be between 1000 and 10000
-                "timeoutMs": 25 // mimimum is 25ms
-            }
+        // Arrival order, so it can be reconstructed whatever the _timestamp says
+        let seq = self.preserve_order.then(|| {
+            self.next_seq += 1;
+            self.next_seq
         });
         
-        let client = reqwest::Client::new();
-        
+        let event_type = self.mapped_type(&event.event_type);
+        let instance_id = instance_id();
         
-        let response = client
-            .put(&url)
-            .header("Lambda-Extension-Identifier", extension_id)
-            .json(&subscription)
-            .send()
-            .await
-            .map_err(|e| anyhow!("Failed to subscribe to Telemetry API: {}", e))?;
+        // Convert to OpenObserve format: add _timestamp and remove time
+        let output = OutputEvent {
+            raw,
+            seq,
+            timestamp,
+            depth_truncated,
+            extension_version: self.tag_version.then_some(env!("CARGO_PKG_VERSION")),
+            function_arn: self.function_arn.as_deref(),
+            init_type: self.init_type.as_deref(),
+            instance_id: self.include_instance_id.then_some(&*instance_id),
+            pid: self.include_instance_id.then(std::process::id),
+            record: RecordField {
+                key: &self.record_field,
+                value: &event.record,
+            },
+            // Omitted when the destination schema can't take it
+            request_id: event.request_id.as_deref().filter(|_| self.emit_request_id),
+            event_type: &event_type,
+        };
         
-        if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            return Err(anyhow!(
-                "Telemetry API subscription failed with status {}: {}", 
-                status, text
-            ));
+        let placeholder = || {
+            let mut placeholder = serde_json::json!({
+                "_timestamp": output.timestamp,
+                "requestId": output.request_id,
+                "serialization_error": true,
+                "type": output.event_type,
+            });
+            placeholder[output.record.key] = format!("{:?}", output.record.value).into();
+            placeholder
+        };
+        let json_str = match (&self.csv_columns, &self.event_template) {
+            // Built from the event directly rather than from its serialized JSON
+            (Some(columns), _) => csv_row(columns, &output),
+            (None, Some(template)) => {
+                let resolve = |name: &str| template_field(name, &output, event.time, self.function_name.as_deref(), &instance_id);
+                if !write_event_or_placeholder(&mut self.scratch, &render_template(template, &resolve), placeholder) {
+                    return;
+                }
+                // serde_json only writes valid UTF-8
+                String::from_utf8_lossy(&self.scratch).into_owned()
+            }
+            (None, None) => {
+                if !write_event_or_placeholder(&mut self.scratch, &output, placeholder) {
+                    return;
+                }
+                String::from_utf8_lossy(&self.scratch).into_owned()
+            }
+        };
+        if self.queued_len() == 0 {
+            self.oldest_message_at = Some(Instant::now());
         }
-        
-        Ok(())
-    }
-    
-    pub async fn shutdown(&mut self) {
-        if let Some(handle) = self.server_handle.take() {
-            handle.abort();
+        self.buffered_bytes += json_str.len();
+        let message = QueuedMessage {
+            request_id: group_request_id,
+            json: json_str,
+            received_at,
+            requeues: 0,
+            identity,
+            batch_seq: None,
+        };
+        if self.batch_by_request_id {
+            self.request_groups.push_back(message);
+        } else {
+            self.messages.push_back(message);
         }
     }
-}
-
-async fn handle_telemetry_request(
-    req: Request<Body>,
-    aggregator: Arc<Mutex<TelemetryAggregator>>,
-) -> Result<Response<Body>, Infallible> {
-    // debug!("🔥 TELEMETRY REQUEST RECEIVED! Method: {}, URI: {}", req.method(), req.uri());
     
-    match req.method() {
-        &hyper::Method::POST => {
-            match process_telemetry_batch(req, aggregator).await {
-                Ok(_) => {
-                    let response = Response::builder()
-                        .status(StatusCode::OK)
-                        .body(Body::from("OK"))
-                        .unwrap();
-                    Ok(response)
-                }
-                Err(e) => {
-                    error!("❌ Error processing telemetry batch: {}", e);
-                    let response = Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Body::from("Internal Server Error"))
-                        .unwrap();
-                    Ok(response)
+    // With O2_MERGE_MULTILINE, fold function log lines matching the continuation pattern into
+    // the line before them. The last line is held back, since its continuation may arrive in
+    // the next delivery; it is released by the next non-continuation line or by get_batch.
+    fn merge_multiline(&mut self, events: Vec<TelemetryEvent>) -> Vec<TelemetryEvent> {
+        let Some(pattern) = &self.multiline_pattern else {
+            return events;
+        };
+        
+        let mut merged = Vec::with_capacity(events.len() + 1);
+        let mut pending = self.pending_multiline.take();
+        for event in events {
+            let line = function_log_line(&event);
+            if let (Some(line), Some((held, _))) = (line, pending.as_mut()) {
+                if pattern.is_match(line) {
+                    if let serde_json::Value::String(text) = &mut held.record {
+                        let kept = text.trim_end_matches('\n').len();
+                        text.truncate(kept);
+                        text.push('\n');
+                        text.push_str(line);
+                    }
+                    continue;
                 }
             }
+            
+            merged.extend(pending.take().map(|(held, _)| held));
+            if line.is_some() {
+                pending = Some((event, Instant::now()));
+            } else {
+                merged.push(event);
+            }
         }
-        _ => {
-            let response = Response::builder()
-                .status(StatusCode::METHOD_NOT_ALLOWED)
-                .body(Body::from("Method Not Allowed"))
-                .unwrap();
-            Ok(response)
-        }
+        self.pending_multiline = pending;
+        merged
     }
-}
-
-async fn process_telemetry_batch(
-    req: Request<Body>,
-    aggregator: Arc<Mutex<TelemetryAggregator>>,
-) -> Result<()> {
-    let body_bytes = body::to_bytes(req.into_body())
-        .await
-        .map_err(|e| anyhow!("Failed to read request body: {}", e))?;
     
-    let body_str = String::from_utf8(body_bytes.to_vec())
-        .map_err(|e| anyhow!("Invalid UTF-8 in request body: {}", e))?;
+    // Queue the line held back by merge_multiline, as no more continuations are waited for
+    fn release_pending_multiline(&mut self) {
+        if let Some((event, held_at)) = self.pending_multiline.take() {
+            self.enqueue_events(vec![event], self.track_lag.then_some(held_at));
+        }
+    }
     
+    // Emitted name for an event type under O2_TYPE_MAP. A full type ("platform.report") is
+    // matched first, then its category ("platform"), whose suffix is kept ("infra.report").
+    fn mapped_type<'a>(&self, event_type: &'a str) -> std::borrow::Cow<'a, str> {
+        let lookup = |name: &str| self.type_map.iter().find(|(from, _)| from == name).map(|(_, to)| to);
+        if let Some(to) = lookup(event_type) {
+            return std::borrow::Cow::Owned(to.clone());
+        }
+        match event_type.split_once('.') {
+            Some((category, suffix)) => match lookup(category) {
+                Some(to) => std::borrow::Cow::Owned(format!("{}.{}", to, suffix)),
+                None => std::borrow::Cow::Borrowed(event_type),
+            },
+            None => std::borrow::Cow::Borrowed(event_type),
+        }
+    }
     
-    // Parse telemetry events
-    let telemetry_events: Vec<TelemetryEvent> = serde_json::from_str(&body_str)
-        .map_err(|e| {
-            error!("Failed to parse telemetry events: {}", e);
-            anyhow!("Failed to parse telemetry events: {}", e)
-        })?;
+    fn collect_metric_samples(&mut self, event: &TelemetryEvent) {
+        let Some(metrics) = event.record.get("metrics") else {
+            return;
+        };
+        
+        for (field, name) in PLATFORM_REPORT_METRICS {
+            if let Some(value) = metrics.get(*field).and_then(|value| value.as_f64()) {
+                self.metric_samples.push_back(MetricSample {
+                    name,
+                    value,
+                    timestamp_ms: event.time.timestamp_millis(),
+                });
+            }
+        }
+    }
     
-    // Add events directly to aggregator
-    {
-        let mut aggregator_guard = aggregator.lock().await;
-        aggregator_guard.add_batch(telemetry_events);
+    // Drain collected platform metric samples for export
+    pub fn take_metric_samples(&mut self) -> Vec<MetricSample> {
+        self.metric_samples.drain(..).collect()
     }
     
-    Ok(())
-}
+    // Put samples back after a failed export so the next flush retries them
+    pub fn restore_metric_samples(&mut self, samples: Vec<MetricSample>) {
+        for sample in samples.into_iter().rev() {
+            self.metric_samples.push_front(sample);
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    // Whether the record text matches any O2_EXCLUDE_PATTERNS entry
+    fn is_excluded(&self, record: &serde_json::Value) -> bool {
+        if self.exclude_patterns.is_empty() {
+            return false;
+        }
+        let text = match record {
+            serde_json::Value::String(text) => std::borrow::Cow::Borrowed(text.as_str()),
+            other => std::borrow::Cow::Owned(other.to_string()),
+        };
+        self.exclude_patterns.iter().any(|pattern| pattern.is_match(&text))
+    }
+    
+    // Drop events already delivered by the Telemetry API within the last DEDUP_WINDOW events,
+    // as happens when AWS retries a delivery. Returns how many were dropped.
+    pub fn remove_duplicates(&mut self, events: &mut Vec<TelemetryEvent>) -> usize {
+        if !self.dedup {
+            return 0;
+        }
+        
+        let before = events.len();
+        events.retain(|event| {
+            let identity = event_identity(event);
+            if self.seen_events.contains(&identity) {
+                return false;
+            }
+            self.mark_seen(identity);
+            true
+        });
+        
+        let dropped = before - events.len();
+        self.duplicate_count += dropped as u64;
+        dropped
+    }
+    
+    // Record the events of a sent batch as seen, when they weren't on arrival (immediate forward)
+    pub fn mark_batch_seen(&mut self, batch: &PendingBatch) {
+        for identity in batch.messages.iter().filter_map(|message| message.identity) {
+            self.mark_seen(identity);
+        }
+    }
+    
+    fn mark_seen(&mut self, identity: u64) {
+        if !self.seen_events.insert(identity) {
+            return;
+        }
+        self.seen_order.push_back(identity);
+        if self.seen_order.len() > DEDUP_WINDOW {
+            if let Some(oldest) = self.seen_order.pop_front() {
+                self.seen_events.remove(&oldest);
+            }
+        }
+    }
+    
+    // Number of redelivered events dropped by O2_TELEMETRY_DEDUP so far
+    pub fn duplicate_count(&self) -> u64 {
+        self.duplicate_count
+    }
+    
+    // Tag events added from now on with the function's ARN (O2_INCLUDE_ARN)
+    pub fn set_function_arn(&mut self, function_arn: Option<String>) {
+        self.function_arn = function_arn;
+    }
+    
+    // Largest number of serialized bytes held at once so far
+    pub fn peak_buffered_bytes(&self) -> usize {
+        self.peak_buffered_bytes
+    }
+    
+    // Number of events dropped by O2_EXCLUDE_PATTERNS so far
+    pub fn excluded_count(&self) -> u64 {
+        self.excluded_count
+    }
+    
+    // Number of events dropped for being too large to fit in any batch so far
+    pub fn oversized_count(&self) -> u64 {
+        self.oversized_count
+    }
+    
+    // Drop an event that can't fit even in an empty batch, as it would hold up the queue for good
+    fn drop_oversized(&mut self, message: QueuedMessage) {
+        self.buffered_bytes -= message.json.len();
+        self.oversized_count += 1;
+        warn!("⚠️ Dropped a {} byte event larger than the {} byte batch limit", message.json.len(), self.max_content_size_bytes);
+    }
+    
+    // Whether the event matches an O2_FLUSH_ON_TYPES entry: "type" or "type:subtype",
+    // where the subtype is compared to the record's status (or type) field
+    fn triggers_flush(&self, event: &TelemetryEvent) -> bool {
+        self.flush_on_types.iter().any(|entry| {
+            let (event_type, subtype) = match entry.split_once(':') {
+                Some((event_type, subtype)) => (event_type, Some(subtype)),
+                None => (entry.as_str(), None),
+            };
+            if event_type != event.event_type {
+                return false;
+            }
+            let Some(subtype) = subtype else {
+                return true;
+            };
+            let record_subtype = event.record.get("status")
+                .or_else(|| event.record.get("type"))
+                .and_then(|value| value.as_str());
+            record_subtype == Some(subtype)
+        })
+    }
+    
+    // Early warning before the buffer cap is reached, throttled to one per BUFFER_WARN_INTERVAL
+    fn warn_if_near_capacity(&mut self) {
+        if self.buffer_warn_percent == 0 {
+            return;
+        }
+        let threshold = self.max_content_size_bytes as u128 * self.buffer_warn_percent as u128 / 100;
+        if (self.buffered_bytes as u128) < threshold {
+            return;
+        }
+        if self.last_buffer_warning.is_some_and(|last| last.elapsed() < BUFFER_WARN_INTERVAL) {
+            return;
+        }
+        
+        warn!(
+            "⚠️ Telemetry buffer at {}% of capacity ({} of {} bytes), consider tuning O2_MAX_BUFFER_SIZE_MB or flushing more often",
+            self.buffered_bytes as u128 * 100 / self.max_content_size_bytes.max(1) as u128,
+            self.buffered_bytes,
+            self.max_content_size_bytes,
+        );
+        self.last_buffer_warning = Some(Instant::now());
+        self.buffer_warnings += 1;
+    }
+    
+    // Flag a flush when the function's reported memory use crosses the threshold
+    fn check_memory_pressure(&mut self, record: &serde_json::Value) {
+        let Some(threshold_mb) = self.memory_flush_threshold_mb else {
+            return;
+        };
+        let used_mb = record
+            .pointer("/metrics/maxMemoryUsedMB")
+            .and_then(|value| value.as_f64());
+        
+        if let Some(used_mb) = used_mb {
+            if used_mb >= threshold_mb {
+                warn!("⚠️ Memory usage {}MB reached flush threshold {:.0}MB", used_mb, threshold_mb);
+                self.flush_requested = true;
+            }
+        }
+    }
+    
+    // Release capacity left over from a burst once no events have arrived for O2_BUFFER_SHRINK_IDLE_MS.
+    // Returns whether anything was shrunk.
+    pub fn shrink_if_idle(&mut self) -> bool {
+        let Some(idle) = self.shrink_idle else {
+            return false;
+        };
+        if self.last_activity.elapsed() < idle {
+            return false;
+        }
+        if self.buffer.capacity() <= BUFFER_BASELINE_CAPACITY
+            && self.messages.capacity() <= QUEUE_BASELINE_CAPACITY.max(self.messages.len())
+        {
+            return false;
+        }
+        
+        self.buffer.shrink_to(BUFFER_BASELINE_CAPACITY);
+        self.scratch.shrink_to(0);
+        self.messages.shrink_to(QUEUE_BASELINE_CAPACITY);
+        self.metric_samples.shrink_to(QUEUE_BASELINE_CAPACITY);
+        debug!("🧹 Shrunk telemetry buffers after {}ms idle", idle.as_millis());
+        true
+    }
+    
+    // Returns whether an immediate flush is pending (memory pressure or O2_FLUSH_ON_TYPES), clearing the request
+    pub fn take_flush_request(&mut self) -> bool {
+        std::mem::take(&mut self.flush_requested)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        let current_minute = Utc::now().timestamp().div_euclid(60);
+        self.queued_len() == 0
+            && self.pending_multiline.is_none()
+            && self.type_counts.keys().all(|(minute, _)| *minute >= current_minute)
+    }
+
+    // A partial batch is held back until it fills up or its oldest event reaches min_age
+    pub fn is_batch_ready(&self, min_age: Duration) -> bool {
+        if self.queued_len() >= self.max_batch_entries_size {
+            return true;
+        }
+        if self.target_batch_bytes > 0 && self.buffered_bytes >= self.target_batch_bytes {
+            return true;
+        }
+        if self.pending_multiline.as_ref().is_some_and(|(_, held_at)| held_at.elapsed() >= min_age) {
+            return true;
+        }
+        self.oldest_message_at
+            .is_some_and(|oldest| oldest.elapsed() >= min_age)
+    }
+
+    // returns JSON array bytes
+    pub fn get_batch(&mut self) -> Vec<u8> {
+        self.take_batch().body
+    }
+    
+    // Like get_batch, keeping the batch's events for requeue_batch
+    pub fn take_batch(&mut self) -> PendingBatch {
+        self.release_pending_multiline();
+        self.release_counts(false);
+        match &self.csv_columns {
+            Some(columns) => {
+                let header: Vec<String> = columns.iter().map(|column| csv_escape(column)).collect();
+                self.buffer.extend(header.join(",").as_bytes());
+                self.buffer.extend(b"\n");
+            }
+            None => self.buffer.extend(b"["),
+        }
+        let prefix_len = self.buffer.len();
+
+        let mut taken = Vec::new();
+        if self.batch_by_request_id {
+            self.fill_request_id_batch(&mut taken);
+        } else {
+            // Fill the batch with events from the messages
+            for _ in 0..self.max_batch_entries_size {
+                if let Some(message) = self.messages.pop_front() {
+                    // Check if the buffer will be full after adding the event
+                    if self.buffer.len() + self.batched_len(&message) > self.max_content_size_bytes {
+                        if taken.is_empty() {
+                            self.drop_oversized(message);
+                            continue;
+                        }
+                        // Put the event back in the queue
+                        self.messages.push_front(message);
+                        break;
+                    }
+                    self.buffered_bytes -= message.json.len();
+
+                    self.append_message(&message);
+                    taken.push(message);
+                    if self.reached_target_size() {
+                        break;
+                    }
+                } else {
+                    break;
+                }
+            }
+        }
+
+        // Remaining messages are newer, so keeping the old mark only errs towards flushing sooner
+        if self.queued_len() == 0 {
+            self.oldest_message_at = None;
+        }
+
+        // Make sure we added at least one element
+        if self.buffer.len() == prefix_len {
+            // No elements, remove opening bracket or CSV header
+            self.buffer.clear();
+        } else if self.csv_columns.is_none() {
+            // Remove the last comma and close bracket
+            self.buffer.pop();
+            self.buffer.extend(b"]");
+        }
+
+        PendingBatch {
+            body: std::mem::take(&mut self.buffer),
+            seq: self.batch_seq(&taken),
+            messages: taken,
+        }
+    }
+    
+    // A batch put back and taken again as it was keeps its number; anything else gets a new one
+    fn batch_seq(&mut self, taken: &[QueuedMessage]) -> u64 {
+        if let Some(seq) = taken.first().and_then(|message| message.batch_seq) {
+            if taken.iter().all(|message| message.batch_seq == Some(seq)) {
+                return seq;
+            }
+        }
+        let seq = self.next_batch_seq;
+        self.next_batch_seq = self.next_batch_seq.wrapping_add(1);
+        seq
+    }
+
+    // Put a sent batch's events back at the front of the queue, for records OpenObserve
+    // rejected with O2_REQUEUE_REJECTED. They go out again with the next flush; events already
+    // put back MAX_REQUEUE_ATTEMPTS times are dropped instead. Returns how many were dropped.
+    pub fn requeue_batch(&mut self, batch: PendingBatch) -> usize {
+        let mut dropped = 0;
+        for mut message in batch.messages.into_iter().rev() {
+            if message.requeues >= MAX_REQUEUE_ATTEMPTS {
+                dropped += 1;
+                continue;
+            }
+            message.requeues += 1;
+            message.batch_seq = Some(batch.seq);
+            self.buffered_bytes += message.json.len();
+            if self.batch_by_request_id {
+                self.request_groups.push_front(message);
+            } else {
+                self.messages.push_front(message);
+            }
+        }
+        if self.queued_len() > 0 && self.oldest_message_at.is_none() {
+            self.oldest_message_at = Some(Instant::now());
+        }
+        dropped
+    }
+    
+    // Fill the batch only with events sharing the oldest event's request id, keeping the
+    // rest queued in their original order
+    fn fill_request_id_batch(&mut self, taken: &mut Vec<QueuedMessage>) {
+        let Some((key, mut group)) = self.request_groups.take_oldest_group() else {
+            return;
+        };
+        
+        let mut entries = 0;
+        while let Some((_, message)) = group.front() {
+            let fits = self.buffer.len() + self.batched_len(message) <= self.max_content_size_bytes;
+            if entries >= self.max_batch_entries_size || (!fits && entries > 0) {
+                break;
+            }
+            let Some((_, message)) = group.pop_front() else {
+                break;
+            };
+            if !fits {
+                self.drop_oversized(message);
+                continue;
+            }
+            
+            self.buffered_bytes -= message.json.len();
+            self.append_message(&message);
+            taken.push(message);
+            entries += 1;
+            if self.reached_target_size() {
+                break;
+            }
+        }
+        self.request_groups.put_group_back(key, group);
+    }
+    
+    // Events waiting to be batched
+    fn queued_len(&self) -> usize {
+        self.messages.len() + self.request_groups.len
+    }
+    
+    // Whether the batch being built has reached O2_TARGET_BATCH_BYTES
+    fn reached_target_size(&self) -> bool {
+        self.target_batch_bytes > 0 && self.buffer.len() >= self.target_batch_bytes
+    }
+    
+    // Upper bound on the bytes append_message adds to the batch
+    fn batched_len(&self, message: &QueuedMessage) -> usize {
+        match message.received_at {
+            Some(_) => message.json.len() + LAG_FIELD_RESERVE,
+            None => message.json.len(),
+        }
+    }
+    
+    // Append a queued event and its trailing comma to the batch. With O2_TRACK_LAG the
+    // event's closing brace is replaced by a lag_ms field, measured as the batch goes out.
+    fn append_message(&mut self, message: &QueuedMessage) {
+        match message.received_at {
+            Some(received_at) => {
+                let json = message.json.as_bytes();
+                self.buffer.extend(&json[..json.len() - 1]);
+                self.buffer.extend(format!(",\"lag_ms\":{}}}", received_at.elapsed().as_millis()).as_bytes());
+            }
+            None => self.buffer.extend(message.json.as_bytes()),
+        }
+        // CSV rows keep their newline, which also ends the document
+        self.buffer.extend(if self.csv_columns.is_some() { b"\n" } else { b"," });
+    }
+
+}
+
+// Serialize an event into `scratch`, falling back to `placeholder` if the event can't be
+// serialized so that the event's existence isn't lost. Returns whether anything was written.
+fn write_event_or_placeholder<T: Serialize>(
+    scratch: &mut Vec<u8>,
+    output: &T,
+    placeholder: impl FnOnce() -> serde_json::Value,
+) -> bool {
+    scratch.clear();
+    match serde_json::to_writer(&mut *scratch, output) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("⚠️ Failed to serialize event, forwarding a placeholder instead: {}", e);
+            scratch.clear();
+            serde_json::to_writer(&mut *scratch, &placeholder()).is_ok()
+        }
+    }
+}
+
+// Value of an O2_EVENT_TEMPLATE placeholder: a built-in name, else a top-level record field
+fn template_field(
+    name: &str,
+    output: &OutputEvent,
+    time: DateTime<Utc>,
+    function_name: Option<&str>,
+    instance_id: &str,
+) -> serde_json::Value {
+    match name {
+        "time" => time.to_rfc3339().into(),
+        "timestamp" => output.timestamp.clone(),
+        "type" => output.event_type.into(),
+        "record" => output.record.value.clone(),
+        "request_id" => output.request_id.into(),
+        "function_name" => function_name.into(),
+        "function_arn" => output.function_arn.into(),
+        "instance_id" => instance_id.into(),
+        "extension_version" => env!("CARGO_PKG_VERSION").into(),
+        field => output.record.value.get(field).cloned().unwrap_or_default(),
+    }
+}
+
+// Fill in a template's `$name` placeholders. A string that is a single placeholder takes the
+// value as-is (so `$record` stays an object); otherwise placeholders are spliced in as text.
+fn render_template(template: &serde_json::Value, resolve: &dyn Fn(&str) -> serde_json::Value) -> serde_json::Value {
+    use serde_json::Value;
+    match template {
+        Value::String(text) => {
+            let placeholders = template_placeholders(text);
+            match placeholders.as_slice() {
+                [] => template.clone(),
+                [range] if range.start == 0 && range.end == text.len() => resolve(&text[1..]),
+                _ => {
+                    let mut rendered = String::with_capacity(text.len());
+                    let mut last = 0;
+                    for range in placeholders {
+                        rendered.push_str(&text[last..range.start]);
+                        match resolve(&text[range.start + 1..range.end]) {
+                            Value::String(value) => rendered.push_str(&value),
+                            value => rendered.push_str(&value.to_string()),
+                        }
+                        last = range.end;
+                    }
+                    rendered.push_str(&text[last..]);
+                    rendered.into()
+                }
+            }
+        }
+        Value::Array(items) => items.iter().map(|item| render_template(item, resolve)).collect(),
+        Value::Object(fields) => Value::Object(
+            fields.iter().map(|(key, value)| (key.clone(), render_template(value, resolve))).collect()
+        ),
+        other => other.clone(),
+    }
+}
+
+// Byte ranges of `$name` placeholders in a template string, `$` included
+fn template_placeholders(text: &str) -> Vec<std::ops::Range<usize>> {
+    let bytes = text.as_bytes();
+    let mut placeholders = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'$' && bytes.get(i + 1).is_some_and(|&b| crate::config::is_placeholder_start(b.into())) {
+            let end = (i + 1..bytes.len())
+                .find(|&j| !crate::config::is_placeholder_char(bytes[j].into()))
+                .unwrap_or(bytes.len());
+            placeholders.push(i..end);
+            i = end;
+        } else {
+            i += 1;
+        }
+    }
+    placeholders
+}
+
+// Identity of a Telemetry API event for duplicate detection: its type, time and record
+fn event_identity(event: &TelemetryEvent) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    event.event_type.hash(&mut hasher);
+    event.time.hash(&mut hasher);
+    event.record.to_string().hash(&mut hasher);
+    hasher.finish()
+}
+
+// A CSV row of `columns` for an event, looking each one up in the record first and then in
+// the event envelope. Missing fields and nulls become empty cells.
+fn csv_row(columns: &[String], output: &OutputEvent) -> String {
+    let cells: Vec<String> = columns
+        .iter()
+        .map(|column| {
+            let value = match output.record.value.get(column) {
+                Some(value) => Some(std::borrow::Cow::Borrowed(value)),
+                None => envelope_field(output, column).map(std::borrow::Cow::Owned),
+            };
+            match value.as_deref() {
+                None | Some(serde_json::Value::Null) => String::new(),
+                Some(serde_json::Value::String(text)) => csv_escape(text),
+                Some(other) => csv_escape(&other.to_string()),
+            }
+        })
+        .collect();
+    cells.join(",")
+}
+
+// An envelope field of an event by its serialized name, None when it would be omitted
+fn envelope_field(output: &OutputEvent, name: &str) -> Option<serde_json::Value> {
+    match name {
+        "_raw" => output.raw.clone(),
+        "_seq" => output.seq.map(Into::into),
+        "_timestamp" => Some(output.timestamp.clone()),
+        "depth_truncated" => output.depth_truncated.then_some(true.into()),
+        "extension_version" => output.extension_version.map(Into::into),
+        "function_arn" => output.function_arn.map(Into::into),
+        "init_type" => output.init_type.map(Into::into),
+        "instance_id" => output.instance_id.map(Into::into),
+        "pid" => output.pid.map(Into::into),
+        "requestId" => output.request_id.map(Into::into),
+        "type" => Some(output.event_type.into()),
+        name if name == output.record.key => Some(output.record.value.clone()),
+        _ => None,
+    }
+}
+
+// Quote a CSV cell if it contains a delimiter, quote or line break (RFC 4180)
+fn csv_escape(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+// The text of a plain-text function log line, the only records multiline merging applies to
+fn function_log_line(event: &TelemetryEvent) -> Option<&str> {
+    match (event.event_type.as_str(), &event.record) {
+        ("function", serde_json::Value::String(line)) => Some(line),
+        _ => None,
+    }
+}
+
+// Telemetry API platform events are typed "platform.start", "platform.report", etc.
+fn is_platform_event(event_type: &str) -> bool {
+    event_type == "platform" || event_type.starts_with("platform.")
+}
+
+// Stringify objects and arrays nested deeper than `max_depth` (the record itself is depth 1).
+// Returns whether anything was truncated.
+fn truncate_depth(value: &mut serde_json::Value, max_depth: usize) -> bool {
+    let has_children = match value {
+        serde_json::Value::Object(map) => !map.is_empty(),
+        serde_json::Value::Array(items) => !items.is_empty(),
+        _ => return false,
+    };
+    
+    if max_depth <= 1 {
+        if has_children {
+            *value = serde_json::Value::String(value.to_string());
+        }
+        return has_children;
+    }
+    
+    let mut truncated = false;
+    match value {
+        serde_json::Value::Object(map) => {
+            for child in map.values_mut() {
+                truncated |= truncate_depth(child, max_depth - 1);
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for child in items.iter_mut() {
+                truncated |= truncate_depth(child, max_depth - 1);
+            }
+        }
+        _ => {}
+    }
+    truncated
+}
+
+// Read a timestamp from a record field as epoch microseconds.
+// Accepts RFC3339/ISO8601 strings or epoch numbers in seconds, millis, micros or nanos.
+fn extract_timestamp_micros(record: &serde_json::Value, field: &str) -> Option<i64> {
+    let value = record.as_object()?.get(field)?;
+    
+    let epoch = match value {
+        serde_json::Value::String(text) => {
+            if let Ok(parsed) = DateTime::parse_from_rfc3339(text) {
+                return Some(parsed.timestamp_micros());
+            }
+            text.trim().parse::<f64>().ok()?
+        }
+        serde_json::Value::Number(number) => number.as_f64()?,
+        _ => return None,
+    };
+    
+    // Infer the unit from the magnitude
+    let abs = epoch.abs();
+    let micros = if abs < 1e11 {
+        epoch * 1_000_000.0
+    } else if abs < 1e14 {
+        epoch * 1_000.0
+    } else if abs < 1e17 {
+        epoch
+    } else {
+        epoch / 1_000.0
+    };
+    
+    Some(micros as i64)
+}
+
+// Note: TelemetryProcessor removed - events now added directly to aggregator
+// Note: TelemetryFlusher removed - using synchronous flush in extension.rs
+
+pub struct TelemetrySubscriber {
+    port: u16,
+    aggregator: Arc<Mutex<TelemetryAggregator>>,
+    server_handle: Option<tokio::task::JoinHandle<()>>,
+    runtime_api_endpoint: String,
+    // Host the Telemetry API delivers to (O2_TELEMETRY_CALLBACK_HOST)
+    callback_host: String,
+    extension_id: Option<String>,
+    last_telemetry_received: Arc<Mutex<Option<Instant>>>,
+    silence_watch_started: Option<Instant>,
+    // Bounds how many deliveries are processed at once (O2_TELEMETRY_CONCURRENCY)
+    concurrency_limit: Option<Arc<Semaphore>>,
+    forwarder: Option<Arc<ImmediateForwarder>>,
+    // Subscribe retries and backoff bounds (O2_MAX_RETRIES, O2_*_RETRY_DELAY_MS)
+    subscribe_max_retries: u32,
+    subscribe_retry_delay: Duration,
+    subscribe_max_retry_delay: Duration,
+    // Shared by subscribe and re-subscribe requests, with a short timeout
+    client: reqwest::Client,
+}
+
+// Sends each delivery straight to OpenObserve (O2_IMMEDIATE_FORWARD). Events are shaped by
+// a private aggregator so the output matches batched mode.
+pub struct ImmediateForwarder {
+    aggregator: Mutex<TelemetryAggregator>,
+    config: Arc<Config>,
+    client: reqwest::Client,
+    ingest_state: crate::openobserve::IngestState,
+}
+
+impl ImmediateForwarder {
+    pub fn new(config: Arc<Config>) -> Result<Self> {
+        let client = crate::openobserve::client_builder(&config)
+            .timeout(Duration::from_millis(config.request_timeout_ms))
+            .build()
+            .map_err(|e| anyhow!("Failed to create HTTP client: {}", e))?;
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        aggregator.mark_seen_on_send = true;
+        Ok(Self {
+            aggregator: Mutex::new(aggregator),
+            ingest_state: crate::openobserve::IngestState::new(&config),
+            config,
+            client,
+        })
+    }
+    
+    async fn forward(&self, events: Vec<TelemetryEvent>) -> Result<u64> {
+        let batches = {
+            let mut aggregator = self.aggregator.lock().await;
+            aggregator.add_batch(events);
+            // Not is_empty(): counts for the current minute stay queued without making a batch
+            let mut batches = Vec::new();
+            loop {
+                let batch = aggregator.take_batch();
+                if batch.body.is_empty() {
+                    break;
+                }
+                batches.push(batch);
+            }
+            batches
+        };
+        
+        let mut events_sent = 0;
+        for batch in batches {
+            let context = crate::openobserve::SendContext {
+                state: &self.ingest_state,
+                flush_strategy: None,
+                trace_id: None,
+                batch_seq: Some(batch.seq),
+            };
+            events_sent += crate::openobserve::send_flush_batch(&self.client, &self.config, &batch.body, context).await?;
+            self.ingest_state.primary.batch_sizes.record(batch.body.len(), batch.entries() as u64);
+            // Delivered, so a redelivery after a later batch fails doesn't post these again
+            self.aggregator.lock().await.mark_batch_seen(&batch);
+        }
+        Ok(events_sent)
+    }
+}
+
+impl TelemetrySubscriber {
+    pub fn new(port: u16, aggregator: Arc<Mutex<TelemetryAggregator>>) -> Self {
+        let runtime_api_endpoint = crate::extension::runtime_api_base_url_from_env();
+        
+        Self {
+            port,
+            aggregator,
+            server_handle: None,
+            runtime_api_endpoint,
+            callback_host: DEFAULT_CALLBACK_HOST.to_string(),
+            extension_id: None,
+            last_telemetry_received: Arc::new(Mutex::new(None)),
+            silence_watch_started: None,
+            concurrency_limit: None,
+            forwarder: None,
+            subscribe_max_retries: 0,
+            subscribe_retry_delay: Duration::ZERO,
+            subscribe_max_retry_delay: Duration::ZERO,
+            client: Self::subscribe_client(SUBSCRIBE_TIMEOUT),
+        }
+    }
+    
+    // Retry a failed subscribe with exponential backoff, using the ingest retry settings.
+    // Each attempt is also bounded by O2_REQUEST_TIMEOUT_MS when that is shorter.
+    pub fn set_subscribe_retries(&mut self, config: &Config) {
+        self.subscribe_max_retries = config.max_retries;
+        self.subscribe_retry_delay = Duration::from_millis(config.initial_retry_delay_ms);
+        self.subscribe_max_retry_delay = Duration::from_millis(config.max_retry_delay_ms);
+        self.client = Self::subscribe_client(Duration::from_millis(config.request_timeout_ms).min(SUBSCRIBE_TIMEOUT));
+    }
+    
+    fn subscribe_client(timeout: Duration) -> reqwest::Client {
+        reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .unwrap_or_default()
+    }
+    
+    // Have the Telemetry API deliver to this host instead of sandbox.localdomain, for runtimes
+    // where that name doesn't resolve. Takes effect on the next subscribe.
+    pub fn set_callback_host(&mut self, host: &str) {
+        self.callback_host = host.to_string();
+    }
+    
+    // Forward deliveries to OpenObserve as they arrive instead of buffering them.
+    // Takes effect on the next start().
+    pub fn set_immediate_forward(&mut self, config: Arc<Config>) -> Result<()> {
+        self.forwarder = Some(Arc::new(ImmediateForwarder::new(config)?));
+        Ok(())
+    }
+    
+    // Process at most `limit` telemetry deliveries at once, queueing the rest (0 = unlimited).
+    // Takes effect on the next start().
+    pub fn set_concurrency_limit(&mut self, limit: usize) {
+        self.concurrency_limit = (limit > 0).then(|| Arc::new(Semaphore::new(limit)));
+    }
+    
+    pub async fn start(&mut self) -> Result<()> {
+        let aggregator = Arc::clone(&self.aggregator);
+        let last_telemetry_received = Arc::clone(&self.last_telemetry_received);
+        let concurrency_limit = self.concurrency_limit.clone();
+        let forwarder = self.forwarder.clone();
+        
+        let make_svc = hyper::service::make_service_fn(move |_conn| {
+            let aggregator = Arc::clone(&aggregator);
+            let last_telemetry_received = Arc::clone(&last_telemetry_received);
+            let concurrency_limit = concurrency_limit.clone();
+            let forwarder = forwarder.clone();
+            async move {
+                Ok::<_, Infallible>(hyper::service::service_fn(move |req| {
+                    let aggregator = Arc::clone(&aggregator);
+                    let last_telemetry_received = Arc::clone(&last_telemetry_received);
+                    let concurrency_limit = concurrency_limit.clone();
+                    let forwarder = forwarder.clone();
+                    async move {
+                        // The semaphore is never closed, so acquire cannot fail
+                        let _permit = match &concurrency_limit {
+                            Some(limit) => limit.acquire().await.ok(),
+                            None => None,
+                        };
+                        handle_telemetry_request(req, aggregator, forwarder, last_telemetry_received).await
+                    }
+                }))
+            }
+        });
+        
+        let builder = Self::bind_with_fallback(self.port)?;
+        let server = builder.serve(make_svc);
+        
+        let bound_port = server.local_addr().port();
+        if bound_port != self.port {
+            warn!("⚠️ Telemetry port {} unavailable, listening on port {} instead", self.port, bound_port);
+        }
+        info!("Telemetry subscriber listening on port {}", bound_port);
+        // The subscription URI must point at the port we actually bound
+        self.port = bound_port;
+        
+        let server_handle = tokio::spawn(async move {
+            if let Err(e) = server.await {
+                error!("❌ Telemetry subscriber server error: {}", e);
+            }
+        });
+        
+        self.server_handle = Some(server_handle);
+        
+        Ok(())
+    }
+    
+    // Try the requested port first, then the next few ports if it is already in use
+    fn bind_with_fallback(port: u16) -> Result<hyper::server::Builder<hyper::server::conn::AddrIncoming>> {
+        let mut last_error = None;
+        
+        for offset in 0..PORT_FALLBACK_ATTEMPTS {
+            // Port 0 lets the OS pick, so there is nothing to fall back to
+            let Some(candidate) = port.checked_add(offset) else { break };
+            let addr = SocketAddr::from(([0, 0, 0, 0], candidate));
+            
+            match Server::try_bind(&addr) {
+                Ok(builder) => return Ok(builder),
+                Err(e) => {
+                    warn!("⚠️ Failed to bind telemetry subscriber to port {}: {}", candidate, e);
+                    last_error = Some(e);
+                    if port == 0 {
+                        break;
+                    }
+                }
+            }
+        }
+        
+        Err(anyhow!(
+            "Failed to bind telemetry subscriber to ports {}-{}: {}",
+            port,
+            port.saturating_add(PORT_FALLBACK_ATTEMPTS - 1),
+            last_error.map(|e| e.to_string()).unwrap_or_default()
+        ))
+    }
+    
+    pub async fn subscribe_to_telemetry_api(&mut self, extension_id: &str) -> Result<()> {
+        // Remember the ID so a dropped subscription can be re-established later
+        self.extension_id = Some(extension_id.to_string());
+        
+        let mut current_delay = self.subscribe_retry_delay;
+        for attempt in 0..=self.subscribe_max_retries {
+            let error = match self.try_subscribe(extension_id).await {
+                Ok(()) => return Ok(()),
+                Err(e) => e,
+            };
+            
+            if attempt >= self.subscribe_max_retries {
+                return Err(error);
+            }
+            
+            warn!("⚠️ Telemetry API subscribe attempt {}/{} failed: {}. Retrying in {:?}",
+                  attempt + 1, self.subscribe_max_retries + 1, error, current_delay);
+            tokio::time::sleep(current_delay).await;
+            current_delay = (current_delay * 2).min(self.subscribe_max_retry_delay);
+        }
+        
+        unreachable!("retry loop always returns on the final attempt")
+    }
+    
+    // Single subscribe PUT to the Telemetry API
+    async fn try_subscribe(&self, extension_id: &str) -> Result<()> {
+        let url = format!("{}/2022-07-01/telemetry", self.runtime_api_endpoint);
+        
+        let subscription = serde_json::json!({
+            "schemaVersion": "2022-12-13",
+            "destination": {
+                "protocol": "HTTP",
+                "URI": format!("http://{}:{}", self.callback_host, self.port)
+            },
+            "types": ["platform", "function", "extension"],
+            "buffering": {
+                "maxBytes": 262144, // maxBytes should be between 262144 and 10485760
+                "maxItems": 1000, // maxItems should be between 1000 and 10000
+                "timeoutMs": 25 // mimimum is 25ms
+            }
+        });
+        
+        let response = self.client
+            .put(&url)
+            .header("Lambda-Extension-Identifier", extension_id)
+            .json(&subscription)
+            .send()
+            .await
+            .map_err(|e| anyhow!("Failed to subscribe to Telemetry API: {}", e))?;
+        
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(anyhow!(
+                "Telemetry API subscription failed with status {}: {}", 
+                status, text
+            ));
+        }
+        
+        Ok(())
+    }
+    
+    /// Re-subscribe to the Telemetry API if nothing has arrived within `window` of an invoke.
+    /// Called on every INVOKE; returns true if a re-subscribe was attempted.
+    pub async fn resubscribe_if_silent(&mut self, window: Duration) -> Result<bool> {
+        let now = Instant::now();
+        let watch_started = *self.silence_watch_started.get_or_insert(now);
+        let last_received = *self.last_telemetry_received.lock().await;
+        
+        // Telemetry arrived since the watch started - subscription is alive, restart the watch
+        if last_received.is_some_and(|received| received >= watch_started) {
+            self.silence_watch_started = Some(now);
+            return Ok(false);
+        }
+        
+        if now.duration_since(watch_started) < window {
+            return Ok(false);
+        }
+        
+        let extension_id = self.extension_id.clone()
+            .ok_or_else(|| anyhow!("Cannot re-subscribe: extension ID not known"))?;
+        
+        warn!(
+            "⚠️ No telemetry received for {:.1}s after invoke, re-subscribing to Telemetry API",
+            now.duration_since(watch_started).as_secs_f64()
+        );
+        
+        // Give the new subscription a fresh window regardless of the outcome. A single attempt,
+        // since this runs on the invoke path and the next invoke tries again anyway.
+        self.silence_watch_started = Some(now);
+        self.try_subscribe(&extension_id).await?;
+        info!("✅ Re-subscribed to Telemetry API");
+        
+        Ok(true)
+    }
+    
+    pub async fn shutdown(&mut self) {
+        if let Some(handle) = self.server_handle.take() {
+            handle.abort();
+        }
+    }
+}
+
+async fn handle_telemetry_request(
+    req: Request<Body>,
+    aggregator: Arc<Mutex<TelemetryAggregator>>,
+    forwarder: Option<Arc<ImmediateForwarder>>,
+    last_telemetry_received: Arc<Mutex<Option<Instant>>>,
+) -> Result<Response<Body>, Infallible> {
+    // debug!("🔥 TELEMETRY REQUEST RECEIVED! Method: {}, URI: {}", req.method(), req.uri());
+    
+    match req.method() {
+        &hyper::Method::POST => {
+            *last_telemetry_received.lock().await = Some(Instant::now());
+            
+            match process_telemetry_batch(req, aggregator, forwarder).await {
+                Ok(_) => {
+                    let response = Response::builder()
+                        .status(StatusCode::OK)
+                        .body(Body::from("OK"))
+                        .unwrap();
+                    Ok(response)
+                }
+                Err(e) => {
+                    error!("❌ Error processing telemetry batch: {}", e);
+                    let response = Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Body::from("Internal Server Error"))
+                        .unwrap();
+                    Ok(response)
+                }
+            }
+        }
+        _ => {
+            let response = Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .body(Body::from("Method Not Allowed"))
+                .unwrap();
+            Ok(response)
+        }
+    }
+}
+
+// Decode a Telemetry API payload (a JSON array of events). Invalid UTF-8, e.g. a stray binary
+// byte in one log line, becomes U+FFFD instead of failing the whole batch.
+pub fn parse_telemetry_events(body: &[u8]) -> Result<Vec<TelemetryEvent>, ExtensionError> {
+    let body_str = String::from_utf8_lossy(body);
+    if let std::borrow::Cow::Owned(_) = body_str {
+        warn!("⚠️ Telemetry batch contained invalid UTF-8, replaced with U+FFFD");
+    }
+    
+    serde_json::from_str(&body_str)
+        .map_err(|e| ExtensionError::Serialization(format!("Failed to parse telemetry events: {e}")))
+}
+
+async fn process_telemetry_batch(
+    req: Request<Body>,
+    aggregator: Arc<Mutex<TelemetryAggregator>>,
+    forwarder: Option<Arc<ImmediateForwarder>>,
+) -> Result<()> {
+    let body_bytes = body::to_bytes(req.into_body())
+        .await
+        .map_err(|e| anyhow!("Failed to read request body: {}", e))?;
+    
+    // Parse telemetry events
+    let mut telemetry_events = parse_telemetry_events(&body_bytes).map_err(|e| {
+        error!("{}", e);
+        e
+    })?;
+    
+    // A failed forward answers 500 so the Telemetry API delivers the events again
+    if let Some(forwarder) = forwarder {
+        let events_sent = forwarder.forward(telemetry_events).await?;
+        debug!("📤 Forwarded {} events", events_sent);
+        return Ok(());
+    }
+    
+    // Add events directly to aggregator
+    {
+        let mut aggregator_guard = aggregator.lock().await;
+        let duplicates = aggregator_guard.remove_duplicates(&mut telemetry_events);
+        if duplicates > 0 {
+            debug!("⏭️ Skipped {} redelivered telemetry events", duplicates);
+        }
+        aggregator_guard.add_batch(telemetry_events);
+    }
+    
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::MockServer;
+    
+    #[tokio::test]
+    async fn test_invalid_utf8_keeps_rest_of_batch() {
+        let aggregator = Arc::new(Mutex::new(TelemetryAggregator::new(1024 * 1024, 10)));
+        let mut body = br#"[{"time":"2024-01-01T00:00:00Z","type":"function","record":"ok before"},"#.to_vec();
+        body.extend_from_slice(br#"{"time":"2024-01-01T00:00:01Z","type":"function","record":"bad "#);
+        body.push(0xff);
+        body.extend_from_slice(br#" byte"},{"time":"2024-01-01T00:00:02Z","type":"function","record":"ok after"}]"#);
+        
+        let request = Request::post("/").body(Body::from(body)).unwrap();
+        let response = handle_telemetry_request(request, Arc::clone(&aggregator), None, Arc::new(Mutex::new(None)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.lock().await.get_batch()).unwrap();
+        let records: Vec<&str> = batch.iter().map(|e| e["record"].as_str().unwrap()).collect();
+        assert_eq!(records, vec!["ok before", "bad \u{FFFD} byte", "ok after"]);
+    }
+    
+    #[tokio::test]
+    async fn test_telemetry_dedup_skips_redelivered_batch() {
+        let config = Config {
+            telemetry_dedup: true,
+            ..Config::default()
+        };
+        let aggregator = Arc::new(Mutex::new(TelemetryAggregator::from_config(&config)));
+        let body = r#"[
+            {"time":"2024-01-01T00:00:00Z","type":"function","record":"first"},
+            {"time":"2024-01-01T00:00:01Z","type":"function","record":"second"}
+        ]"#;
+        
+        for _ in 0..2 {
+            let request = Request::post("/").body(Body::from(body)).unwrap();
+            let response = handle_telemetry_request(request, Arc::clone(&aggregator), None, Arc::new(Mutex::new(None)))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        
+        let mut guard = aggregator.lock().await;
+        assert_eq!(guard.duplicate_count(), 2);
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&guard.get_batch()).unwrap();
+        assert_eq!(batch.len(), 2);
+        
+        // Same record at a different time is a new event
+        let mut events = parse_telemetry_events(br#"[{"time":"2024-01-01T00:00:02Z","type":"function","record":"first"}]"#).unwrap();
+        assert_eq!(guard.remove_duplicates(&mut events), 0);
+        assert_eq!(events.len(), 1);
+    }
     
     #[test]
     fn test_telemetry_aggregator() {
         let mut aggregator = TelemetryAggregator::new(1024, 10);
         
-        let events = vec![
-            TelemetryEvent {
+        let events = vec![
+            TelemetryEvent {
+                time: Utc::now(),
+                event_type: "function".to_string(),
+                record: serde_json::json!("test log"),
+                request_id: None,
+            }
+        ];
+        
+        aggregator.add_batch(events);
+        let batch = aggregator.get_batch();
+        assert!(!batch.is_empty());
+        
+        // Should be JSON array
+        let batch_str = String::from_utf8(batch).unwrap();
+        assert!(batch_str.starts_with('['));
+        assert!(batch_str.ends_with(']'));
+    }
+    
+    #[test]
+    fn test_drop_platform_events() {
+        let mut aggregator = TelemetryAggregator::new(1024, 10);
+        aggregator.drop_platform_events = true;
+        
+        let events = vec![
+            TelemetryEvent {
+                time: Utc::now(),
+                event_type: "platform".to_string(),
+                record: serde_json::json!({"requestId": "abc"}),
+                request_id: None,
+            },
+            TelemetryEvent {
+                time: Utc::now(),
+                event_type: "platform.report".to_string(),
+                record: serde_json::json!({"requestId": "abc", "metrics": {}}),
+                request_id: None,
+            },
+            TelemetryEvent {
+                time: Utc::now(),
+                event_type: "function".to_string(),
+                record: serde_json::json!("function log"),
+                request_id: None,
+            },
+        ];
+        
+        aggregator.add_batch(events);
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0]["type"], "function");
+    }
+    
+    fn timestamp_of_first(aggregator: &mut TelemetryAggregator, record: serde_json::Value, time: DateTime<Utc>) -> i64 {
+        aggregator.add_batch(vec![TelemetryEvent {
+            time,
+            event_type: "function".to_string(),
+            record,
+            request_id: None,
+        }]);
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        batch[0]["_timestamp"].as_i64().unwrap()
+    }
+    
+    #[test]
+    fn test_timestamp_field_iso8601() {
+        let mut aggregator = TelemetryAggregator::new(1024, 10);
+        aggregator.timestamp_field = Some("ts".to_string());
+        
+        let record = serde_json::json!({"ts": "2024-01-02T03:04:05.678Z", "msg": "hello"});
+        let expected = DateTime::parse_from_rfc3339("2024-01-02T03:04:05.678Z").unwrap().timestamp_micros();
+        assert_eq!(timestamp_of_first(&mut aggregator, record, Utc::now()), expected);
+    }
+    
+    #[test]
+    fn test_timestamp_field_epoch_millis() {
+        let mut aggregator = TelemetryAggregator::new(1024, 10);
+        aggregator.timestamp_field = Some("ts".to_string());
+        
+        let record = serde_json::json!({"ts": 1_704_164_645_678_i64, "msg": "hello"});
+        assert_eq!(timestamp_of_first(&mut aggregator, record, Utc::now()), 1_704_164_645_678_000);
+    }
+    
+    #[test]
+    fn test_timestamp_field_unparseable_falls_back() {
+        let mut aggregator = TelemetryAggregator::new(1024, 10);
+        aggregator.timestamp_field = Some("ts".to_string());
+        
+        let time = Utc::now();
+        let record = serde_json::json!({"ts": "not a timestamp", "msg": "hello"});
+        assert_eq!(timestamp_of_first(&mut aggregator, record, time), time.timestamp_micros());
+    }
+    
+    #[test]
+    fn test_queue_length_is_capped() {
+        let mut aggregator = TelemetryAggregator::new(1024 * 1024, 10);
+        aggregator.max_queue_len = 5;
+        
+        let events = (0..50)
+            .map(|i| TelemetryEvent {
+                time: Utc::now(),
+                event_type: "function".to_string(),
+                record: serde_json::json!(i),
+                request_id: None,
+            })
+            .collect();
+        aggregator.add_batch(events);
+        assert_eq!(aggregator.messages.len(), 5);
+        
+        // The newest events are the ones kept
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        let records: Vec<i64> = batch.iter().map(|e| e["record"].as_i64().unwrap()).collect();
+        assert_eq!(records, vec![45, 46, 47, 48, 49]);
+    }
+    
+    #[test]
+    fn test_target_batch_bytes() {
+        let config = Config {
+            target_batch_bytes: 16 * 1024,
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        let events = (0..1000)
+            .map(|i| TelemetryEvent {
+                time: Utc::now(),
+                event_type: "function".to_string(),
+                record: serde_json::json!(format!("log line {i}")),
+                request_id: None,
+            })
+            .collect();
+        aggregator.add_batch(events);
+        let event_len = aggregator.messages.iter().map(|message| message.json.len()).max().unwrap();
+        assert!(aggregator.is_batch_ready(Duration::from_secs(60)));
+        
+        let mut batches = Vec::new();
+        loop {
+            let batch = aggregator.get_batch();
+            if batch.is_empty() {
+                break;
+            }
+            batches.push(batch);
+        }
+        
+        let (last, full) = batches.split_last().unwrap();
+        assert!(full.len() >= 4);
+        for batch in full {
+            // Packed just past the target, and past the usual entry cap
+            assert!(batch.len() >= config.target_batch_bytes);
+            assert!(batch.len() <= config.target_batch_bytes + event_len + 1);
+            let events: Vec<serde_json::Value> = serde_json::from_slice(batch).unwrap();
+            assert!(events.len() > MAX_BATCH_ENTRIES);
+        }
+        assert!(last.len() < config.target_batch_bytes);
+    }
+    
+    #[test]
+    fn test_small_batch_waits_for_min_age() {
+        let mut aggregator = TelemetryAggregator::new(1024, 10);
+        let min_age = Duration::from_millis(50);
+        assert!(!aggregator.is_batch_ready(min_age));
+        
+        aggregator.add_batch(vec![TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!("tiny"),
+            request_id: None,
+        }]);
+        assert!(!aggregator.is_batch_ready(min_age));
+        
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(aggregator.is_batch_ready(min_age));
+        
+        aggregator.get_batch();
+        assert!(!aggregator.is_batch_ready(min_age));
+    }
+    
+    #[test]
+    fn test_full_batch_ready_before_min_age() {
+        let mut aggregator = TelemetryAggregator::new(1024, 2);
+        let events = (0..2)
+            .map(|_| TelemetryEvent {
+                time: Utc::now(),
+                event_type: "function".to_string(),
+                record: serde_json::json!("log"),
+                request_id: None,
+            })
+            .collect();
+        aggregator.add_batch(events);
+        
+        assert!(aggregator.is_batch_ready(Duration::from_secs(60)));
+    }
+    
+    #[test]
+    fn test_memory_pressure_requests_flush() {
+        let config = Config {
+            memory_flush_percent: 80,
+            function_memory_size_mb: Some(128),
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        let report = |used_mb: u64| TelemetryEvent {
+            time: Utc::now(),
+            event_type: "platform.report".to_string(),
+            record: serde_json::json!({"metrics": {"maxMemoryUsedMB": used_mb, "memorySizeMB": 128}}),
+            request_id: None,
+        };
+        
+        aggregator.add_batch(vec![report(64)]);
+        assert!(!aggregator.take_flush_request());
+        
+        aggregator.add_batch(vec![report(110)]);
+        assert!(aggregator.take_flush_request());
+        assert!(!aggregator.take_flush_request(), "request is cleared once taken");
+    }
+
+    #[test]
+    fn test_platform_report_metric_samples() {
+        let config = Config {
+            metrics_export: MetricsExport::Prometheus,
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        aggregator.add_batch(vec![TelemetryEvent {
+            time: Utc::now(),
+            event_type: "platform.report".to_string(),
+            record: serde_json::json!({"metrics": {"durationMs": 12.5, "maxMemoryUsedMB": 70}}),
+            request_id: Some("req-1".to_string()),
+        }]);
+        
+        let names: Vec<_> = aggregator.take_metric_samples().iter().map(|sample| sample.name).collect();
+        assert_eq!(names, vec!["aws_lambda_duration_ms", "aws_lambda_max_memory_used_mb"]);
+        assert!(aggregator.take_metric_samples().is_empty());
+    }
+
+    #[test]
+    fn test_exclude_patterns_drop_events() {
+        let config = Config {
+            exclude_patterns: vec!["health ping".to_string()],
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        let event = |message: &str| TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!(message),
+            request_id: None,
+        };
+        aggregator.add_batch(vec![event("GET /status health ping ok"), event("order created"), event("health ping")]);
+        
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert_eq!(batch.len(), 1);
+        assert_eq!(batch[0]["record"], "order created");
+        assert_eq!(aggregator.excluded_count(), 2);
+    }
+
+    #[test]
+    fn test_max_record_depth_stringifies_deep_subtree() {
+        let config = Config {
+            max_record_depth: 5,
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        let mut record = serde_json::json!({"leaf": 1});
+        for _ in 0..20 {
+            record = serde_json::json!({"nested": record});
+        }
+        aggregator.add_batch(vec![TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record,
+            request_id: None,
+        }]);
+        
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        let level_five = &batch[0]["record"]["nested"]["nested"]["nested"]["nested"];
+        assert!(level_five.is_string());
+        assert!(level_five.as_str().unwrap().contains("\"leaf\":1"));
+        assert_eq!(batch[0]["depth_truncated"], true);
+    }
+
+    #[test]
+    fn test_field_renames() {
+        let config = Config {
+            field_renames: vec![("type".to_string(), "log_type".to_string())],
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        aggregator.add_batch(vec![TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!({"type": "audit", "message": "hello"}),
+            request_id: None,
+        }]);
+        
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert_eq!(batch[0]["record"], serde_json::json!({"log_type": "audit", "message": "hello"}));
+        assert_eq!(batch[0]["type"], "function");
+    }
+
+    #[test]
+    fn test_include_fields() {
+        let config = Config {
+            include_fields: vec!["level".to_string(), "message".to_string()],
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        aggregator.add_batch(vec![TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!({
+                "level": "INFO",
+                "message": "hello",
+                "user_email": "a@example.com",
+                "client_ip": "10.0.0.1",
+                "session": "abc123",
+            }),
+            request_id: None,
+        }]);
+        
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert_eq!(batch[0]["record"], serde_json::json!({"level": "INFO", "message": "hello"}));
+        assert_eq!(batch[0]["type"], "function");
+        assert!(batch[0]["_timestamp"].is_number());
+    }
+
+    #[test]
+    fn test_batch_by_request_id() {
+        let config = Config {
+            batch_by_request_id: true,
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        let event = |request_id: Option<&str>, i: i64| TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!(i),
+            request_id: request_id.map(str::to_string),
+        };
+        aggregator.add_batch(vec![
+            event(Some("req-1"), 1),
+            event(Some("req-2"), 2),
+            event(None, 3),
+            event(Some("req-1"), 4),
+            event(Some("req-3"), 5),
+            event(Some("req-2"), 6),
+            event(None, 7),
+        ]);
+        
+        let mut batches = Vec::new();
+        while !aggregator.is_empty() {
+            let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+            let ids: Vec<serde_json::Value> = batch.iter().map(|e| e["requestId"].clone()).collect();
+            let records: Vec<i64> = batch.iter().map(|e| e["record"].as_i64().unwrap()).collect();
+            batches.push((ids[0].clone(), records));
+            assert!(ids.iter().all(|id| *id == ids[0]), "mixed request ids: {:?}", ids);
+        }
+        
+        assert_eq!(batches, vec![
+            (serde_json::json!("req-1"), vec![1, 4]),
+            (serde_json::json!("req-2"), vec![2, 6]),
+            (serde_json::Value::Null, vec![3, 7]),
+            (serde_json::json!("req-3"), vec![5]),
+        ]);
+        assert_eq!(aggregator.buffered_bytes, 0);
+    }
+
+    #[test]
+    fn test_batch_by_request_id_takes_oldest_event_first() {
+        let mut aggregator = TelemetryAggregator::new(1024 * 1024, 1);
+        aggregator.batch_by_request_id = true;
+        let event = |request_id: &str, i: i64| TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!(i),
+            request_id: Some(request_id.to_string()),
+        };
+        aggregator.add_batch(vec![event("req-1", 1), event("req-2", 2), event("req-1", 3)]);
+        
+        // A group split by the entry limit waits behind older events of other groups
+        let mut records = Vec::new();
+        while !aggregator.is_empty() {
+            let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+            records.push(batch[0]["record"].as_i64().unwrap());
+        }
+        assert_eq!(records, vec![1, 2, 3]);
+        assert_eq!(aggregator.buffered_bytes, 0);
+    }
+
+    #[test]
+    fn test_requeued_batch_keeps_request_id_group() {
+        let config = Config {
+            batch_by_request_id: true,
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        let event = |request_id: &str, i: i64| TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!(i),
+            request_id: Some(request_id.to_string()),
+        };
+        aggregator.add_batch(vec![event("req-1", 1), event("req-2", 2), event("req-1", 3)]);
+        
+        let batch = aggregator.take_batch();
+        let body = batch.body.clone();
+        assert_eq!(aggregator.requeue_batch(batch), 0);
+        aggregator.add_batch(vec![event("req-1", 4)]);
+        
+        // The requeued events still group with the rest of their request
+        let records = |body: &[u8]| -> Vec<i64> {
+            serde_json::from_slice::<Vec<serde_json::Value>>(body).unwrap()
+                .iter().map(|e| e["record"].as_i64().unwrap()).collect()
+        };
+        assert_eq!(records(&body), vec![1, 3]);
+        assert_eq!(records(&aggregator.get_batch()), vec![1, 3, 4]);
+        assert_eq!(records(&aggregator.get_batch()), vec![2]);
+        assert!(aggregator.is_empty());
+    }
+
+    #[test]
+    fn test_shrink_if_idle() {
+        let config = Config {
+            buffer_shrink_idle_ms: 50,
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        assert!(aggregator.buffer.capacity() > BUFFER_BASELINE_CAPACITY);
+        
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(aggregator.shrink_if_idle());
+        assert!(aggregator.buffer.capacity() <= BUFFER_BASELINE_CAPACITY);
+        
+        // A burst grows the queue; it is kept until the idle threshold passes
+        let events: Vec<TelemetryEvent> = (0..1000)
+            .map(|i| TelemetryEvent {
                 time: Utc::now(),
                 event_type: "function".to_string(),
-                record: serde_json::json!("test log"),
+                record: serde_json::json!(i),
                 request_id: None,
+            })
+            .collect();
+        aggregator.add_batch(events);
+        while !aggregator.is_empty() {
+            aggregator.get_batch();
+        }
+        assert!(aggregator.messages.capacity() > QUEUE_BASELINE_CAPACITY);
+        assert!(!aggregator.shrink_if_idle());
+        
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(aggregator.shrink_if_idle());
+        assert!(aggregator.messages.capacity() <= QUEUE_BASELINE_CAPACITY);
+        assert!(!aggregator.shrink_if_idle());
+    }
+
+    #[test]
+    fn test_buffer_warning_is_throttled() {
+        let mut aggregator = TelemetryAggregator::new(10_000, MAX_BATCH_ENTRIES);
+        aggregator.buffer_warn_percent = 80;
+        let event = || TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!("log line"),
+            request_id: None,
+        };
+        
+        while aggregator.buffered_bytes * 100 < 10_000 * 85 {
+            aggregator.add_batch(vec![event()]);
+            if aggregator.buffered_bytes * 100 < 10_000 * 80 {
+                assert_eq!(aggregator.buffer_warnings, 0);
             }
-        ];
+        }
+        assert!(aggregator.buffered_bytes < 10_000);
+        assert_eq!(aggregator.buffer_warnings, 1);
+        
+        // Still above the threshold, but within the throttle window
+        aggregator.add_batch(vec![event()]);
+        assert_eq!(aggregator.buffer_warnings, 1);
+        
+        // Draining releases the tracked bytes
+        while !aggregator.is_empty() {
+            aggregator.get_batch();
+        }
+        assert_eq!(aggregator.buffered_bytes, 0);
+    }
+
+    #[test]
+    fn test_peak_buffered_bytes() {
+        let mut aggregator = TelemetryAggregator::new(1024 * 1024, MAX_BATCH_ENTRIES);
+        let events = |count: usize| -> Vec<TelemetryEvent> {
+            (0..count)
+                .map(|i| TelemetryEvent {
+                    time: Utc::now(),
+                    event_type: "function".to_string(),
+                    record: serde_json::json!(format!("log line {}", i)),
+                    request_id: None,
+                })
+                .collect()
+        };
+        
+        let mut observed_peak = 0;
+        for load in [5, 40, 2, 15] {
+            aggregator.add_batch(events(load));
+            observed_peak = observed_peak.max(aggregator.buffered_bytes);
+            assert_eq!(aggregator.peak_buffered_bytes(), observed_peak);
+            while !aggregator.is_empty() {
+                aggregator.get_batch();
+            }
+        }
+        
+        // The 40-event load was the largest; draining doesn't lower the mark
+        assert!(observed_peak > 0);
+        assert_eq!(aggregator.buffered_bytes, 0);
+        assert_eq!(aggregator.peak_buffered_bytes(), observed_peak);
+    }
+
+    #[test]
+    fn test_function_arn_tagging() {
+        let mut aggregator = TelemetryAggregator::from_config(&Config::default());
+        let event = || TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!("log line"),
+            request_id: None,
+        };
+        
+        aggregator.add_batch(vec![event()]);
+        aggregator.set_function_arn(Some("arn:aws:lambda:eu-west-1:123456789012:function:checkout-api".to_string()));
+        aggregator.add_batch(vec![event()]);
+        
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert!(batch[0].get("function_arn").is_none());
+        assert_eq!(batch[1]["function_arn"], "arn:aws:lambda:eu-west-1:123456789012:function:checkout-api");
+    }
+
+    #[test]
+    fn test_init_type_tagging() {
+        let _guard = crate::test_support::INSTANCE_ID_LOCK.blocking_lock();
+        let config = Config {
+            init_type: Some("snap-start".to_string()),
+            include_instance_id: true,
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        let event = |event_type: &str| TelemetryEvent {
+            time: Utc::now(),
+            event_type: event_type.to_string(),
+            record: serde_json::json!("log line"),
+            request_id: None,
+        };
+        
+        aggregator.add_batch(vec![event("function")]);
+        aggregator.add_batch(vec![event("platform.restoreStart"), event("function")]);
+        
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert!(batch.iter().all(|e| e["init_type"] == "snap-start"));
+        // The restored sandbox gets a fresh instance id
+        assert_ne!(batch[0]["instance_id"], batch[2]["instance_id"]);
+        assert_eq!(batch[1]["instance_id"], batch[2]["instance_id"]);
+        
+        let mut untagged = TelemetryAggregator::from_config(&Config::default());
+        untagged.add_batch(vec![event("function")]);
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&untagged.get_batch()).unwrap();
+        assert!(batch[0].get("init_type").is_none());
+    }
+
+    #[test]
+    fn test_add_batch_allocations() {
+        use crate::test_support::count_allocations;
+        
+        let events = || -> Vec<TelemetryEvent> {
+            (0..1000)
+                .map(|i| TelemetryEvent {
+                    time: Utc::now(),
+                    event_type: "function".to_string(),
+                    record: serde_json::json!(format!("log line {i}")),
+                    request_id: Some("req-1".to_string()),
+                })
+                .collect()
+        };
+        
+        // Per-event cost of building an intermediate Value and stringifying it
+        let value_events = events();
+        let (_, value_allocations) = count_allocations(|| {
+            value_events
+                .into_iter()
+                .map(|event| {
+                    let mut event_json = serde_json::json!({
+                        "_timestamp": event.time.timestamp_micros(),
+                        "record": event.record,
+                        "type": event.event_type
+                    });
+                    event_json["requestId"] = serde_json::Value::String(event.request_id.unwrap());
+                    serde_json::to_string(&event_json).unwrap()
+                })
+                .collect::<Vec<_>>()
+        });
+        
+        let mut aggregator = TelemetryAggregator::new(10 * 1024 * 1024, MAX_BATCH_ENTRIES);
+        aggregator.add_batch(events()); // warm up the scratch buffer and queue
+        while !aggregator.is_empty() {
+            aggregator.get_batch();
+        }
+        let batch = events();
+        let (_, add_batch_allocations) = count_allocations(|| aggregator.add_batch(batch));
+        
+        assert!(
+            add_batch_allocations * 2 < value_allocations,
+            "add_batch made {add_batch_allocations} allocations, Value path made {value_allocations}"
+        );
+        
+        let output: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert_eq!(output[0]["record"], "log line 0");
+        assert_eq!(output[0]["requestId"], "req-1");
+    }
+
+    #[test]
+    fn test_preserve_order_sequence() {
+        let config = Config {
+            preserve_order: true,
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        let event = |event_type: &str, seconds: i64| TelemetryEvent {
+            // Delivery times deliberately out of order
+            time: DateTime::from_timestamp(1_700_000_000 - seconds, 0).unwrap(),
+            event_type: event_type.to_string(),
+            record: serde_json::json!({"n": seconds}),
+            request_id: None,
+        };
+        aggregator.add_batch(vec![event("platform.start", 1), event("function", 5), event("extension", 3)]);
+        aggregator.add_batch(vec![event("function", 2), event("platform.report", 4)]);
+        
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        let seqs: Vec<u64> = batch.iter().map(|e| e["_seq"].as_u64().unwrap()).collect();
+        assert_eq!(seqs.len(), 5);
+        assert!(seqs.windows(2).all(|pair| pair[0] < pair[1]), "not increasing: {seqs:?}");
+        
+        let mut unordered = TelemetryAggregator::from_config(&Config::default());
+        unordered.add_batch(vec![event("function", 1)]);
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&unordered.get_batch()).unwrap();
+        assert!(batch[0].get("_seq").is_none());
+    }
+
+    #[test]
+    fn test_serialization_failure_emits_placeholder() {
+        // serde_json::Value always serializes, so stand in an event that can't
+        struct Unserializable;
+        impl Serialize for Unserializable {
+            fn serialize<S: serde::Serializer>(&self, _: S) -> std::result::Result<S::Ok, S::Error> {
+                Err(serde::ser::Error::custom("NaN is not valid JSON"))
+            }
+        }
+        
+        let mut scratch = b"stale".to_vec();
+        let written = write_event_or_placeholder(&mut scratch, &Unserializable, || {
+            serde_json::json!({"record": "Number(NaN)", "serialization_error": true, "type": "function"})
+        });
+        assert!(written);
+        let event: serde_json::Value = serde_json::from_slice(&scratch).unwrap();
+        assert_eq!(event["serialization_error"], true);
+        assert_eq!(event["record"], "Number(NaN)");
+        assert_eq!(event["type"], "function");
+        
+        // Events that serialize are written as-is
+        let written = write_event_or_placeholder(&mut scratch, &serde_json::json!({"ok": 1}), || unreachable!());
+        assert!(written);
+        assert_eq!(scratch, br#"{"ok":1}"#);
+    }
+
+    #[test]
+    fn test_merge_multiline_stack_trace() {
+        let config = Config {
+            merge_multiline: true,
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        let line = |text: &str| TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!(text),
+            request_id: None,
+        };
+        
+        aggregator.add_batch(vec![
+            line("starting\n"),
+            line("Error: boom\n"),
+            line("    at handler (/var/task/index.js:3:9)\n"),
+        ]);
+        // The trace continues in the next delivery
+        aggregator.add_batch(vec![
+            line("    at process (node:internal/process:1:1)\n"),
+            line("done\n"),
+        ]);
+        
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        let records: Vec<&str> = batch.iter().map(|event| event["record"].as_str().unwrap()).collect();
+        assert_eq!(records, vec![
+            "starting\n",
+            "Error: boom\n    at handler (/var/task/index.js:3:9)\n    at process (node:internal/process:1:1)\n",
+            "done\n",
+        ]);
+        assert!(aggregator.is_empty());
+        
+        // Disabled, every line stays its own event
+        let mut unmerged = TelemetryAggregator::from_config(&Config::default());
+        unmerged.add_batch(vec![line("Error: boom\n"), line("    at handler\n")]);
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&unmerged.get_batch()).unwrap();
+        assert_eq!(batch.len(), 2);
+    }
+
+    #[test]
+    fn test_record_as_message_field() {
+        let config = Config {
+            record_field: Some("message".to_string()),
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        aggregator.add_batch(vec![TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!({"level": "INFO", "msg": "hello"}),
+            request_id: Some("req-1".to_string()),
+        }]);
+        
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert_eq!(batch[0]["message"], serde_json::json!({"level": "INFO", "msg": "hello"}));
+        assert!(batch[0].get("record").is_none());
+        assert!(batch[0]["_timestamp"].is_number());
+        assert_eq!(batch[0]["type"], "function");
+        assert_eq!(batch[0]["requestId"], "req-1");
+    }
+
+    #[test]
+    fn test_type_map() {
+        let config = Config {
+            type_map: vec![
+                ("platform".to_string(), "infra".to_string()),
+                ("function".to_string(), "app".to_string()),
+                ("platform.report".to_string(), "infra_report".to_string()),
+            ],
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        let event = |event_type: &str| TelemetryEvent {
+            time: Utc::now(),
+            event_type: event_type.to_string(),
+            record: serde_json::json!({"requestId": "req-1"}),
+            request_id: None,
+        };
+        aggregator.add_batch(vec![
+            event("function"),
+            event("platform.start"),
+            event("platform.report"),
+            event("extension"),
+        ]);
+        
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        let types: Vec<&str> = batch.iter().map(|event| event["type"].as_str().unwrap()).collect();
+        assert_eq!(types, ["app", "infra.start", "infra_report", "extension"]);
+    }
+    
+    #[test]
+    fn test_event_template() {
+        let config = Config {
+            event_template: Some(r#"{"ts": "$time", "lvl": "$level", "msg": $record, "svc": "$function_name", "tag": "$type/$level", "x": $missing}"#.to_string()),
+            function_name: Some("checkout-api".to_string()),
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        aggregator.add_batch(vec![TelemetryEvent {
+            time: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            event_type: "function".to_string(),
+            record: serde_json::json!({"level": "INFO", "msg": "hello"}),
+            request_id: Some("req-1".to_string()),
+        }]);
         
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert_eq!(batch, [serde_json::json!({
+            "ts": "2023-11-14T22:13:20+00:00",
+            "lvl": "INFO",
+            "msg": {"level": "INFO", "msg": "hello"},
+            "svc": "checkout-api",
+            "tag": "function/INFO",
+            "x": null,
+        })]);
+    }
+    
+    #[test]
+    fn test_count_only_types() {
+        let config = Config {
+            count_only_types: vec!["platform.start".to_string()],
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        let minute_ago = Utc::now() - chrono::Duration::minutes(2);
+        let mut events: Vec<TelemetryEvent> = (0..500)
+            .map(|i| TelemetryEvent {
+                time: minute_ago,
+                event_type: "platform.start".to_string(),
+                record: serde_json::json!({"requestId": format!("req-{i}")}),
+                request_id: None,
+            })
+            .collect();
+        events.push(TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!("hello"),
+            request_id: None,
+        });
         aggregator.add_batch(events);
-        let batch = aggregator.get_batch();
-        assert!(!batch.is_empty());
         
-        // Should be JSON array
-        let batch_str = String::from_utf8(batch).unwrap();
-        assert!(batch_str.starts_with('['));
-        assert!(batch_str.ends_with(']'));
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert_eq!(batch.len(), 2);
+        assert_eq!(batch[0]["type"], "function");
+        assert_eq!(batch[1]["type"], "platform.start");
+        assert_eq!(batch[1]["record"], serde_json::json!({"count": 500, "window_secs": 60}));
+        assert!(aggregator.is_empty());
+        
+        // The current minute is held back until it ends, or until shutdown asks for everything
+        aggregator.add_batch(vec![TelemetryEvent {
+            time: Utc::now(),
+            event_type: "platform.start".to_string(),
+            record: serde_json::json!({}),
+            request_id: None,
+        }]);
+        aggregator.release_counts(true);
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert_eq!(batch[0]["record"]["count"], 1);
+    }
+    
+    #[test]
+    fn test_csv_output_encoding() {
+        let config = Config {
+            output_encoding: OutputEncoding::Csv,
+            csv_columns: vec!["_timestamp".to_string(), "level".to_string(), "msg".to_string(), "user_id".to_string()],
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        let event = |record: serde_json::Value| TelemetryEvent {
+            time: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            event_type: "function".to_string(),
+            record,
+            request_id: None,
+        };
+        aggregator.add_batch(vec![
+            event(serde_json::json!({"level": "INFO", "msg": "signed in", "user_id": 42})),
+            event(serde_json::json!({"level": "WARN", "msg": "slow, retrying \"db\""})),
+            event(serde_json::json!("plain text line")),
+        ]);
+        
+        let batch = String::from_utf8(aggregator.get_batch()).unwrap();
+        let lines: Vec<&str> = batch.lines().collect();
+        assert_eq!(lines, vec![
+            "_timestamp,level,msg,user_id",
+            "1700000000000000,INFO,signed in,42",
+            r#"1700000000000000,WARN,"slow, retrying ""db""","#,
+            "1700000000000000,,,",
+        ]);
+        assert!(batch.ends_with('\n'));
+        
+        // Nothing buffered, nothing to send
+        assert!(aggregator.get_batch().is_empty());
+    }
+
+    #[test]
+    fn test_csv_envelope_columns() {
+        let config = Config {
+            output_encoding: OutputEncoding::Csv,
+            csv_columns: vec!["type".to_string(), "requestId".to_string(), "record".to_string(), "_seq".to_string()],
+            preserve_order: true,
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        aggregator.add_batch(vec![TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!("plain, text"),
+            request_id: Some("req-1".to_string()),
+        }]);
+        
+        let batch = String::from_utf8(aggregator.get_batch()).unwrap();
+        assert_eq!(batch.lines().nth(1), Some(r#"function,req-1,"plain, text",1"#));
+    }
+
+    #[test]
+    fn test_track_lag_field() {
+        let config = Config {
+            track_lag: true,
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        aggregator.add_batch(vec![TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!({"message": "held"}),
+            request_id: None,
+        }]);
+        std::thread::sleep(Duration::from_millis(20));
+        
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert!(batch[0]["lag_ms"].as_u64().unwrap() >= 20);
+        assert_eq!(batch[0]["record"]["message"], "held");
+        
+        let mut untracked = TelemetryAggregator::from_config(&Config::default());
+        untracked.add_batch(vec![TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!("log line"),
+            request_id: None,
+        }]);
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&untracked.get_batch()).unwrap();
+        assert!(batch[0].get("lag_ms").is_none());
+    }
+
+    #[test]
+    fn test_emit_request_id_toggle() {
+        let event = || TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!("log line"),
+            request_id: Some("req-1".to_string()),
+        };
+        
+        let mut aggregator = TelemetryAggregator::from_config(&Config::default());
+        aggregator.add_batch(vec![event()]);
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert_eq!(batch[0]["requestId"], "req-1");
+        
+        let config = Config {
+            emit_request_id: false,
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        aggregator.add_batch(vec![event()]);
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert!(batch[0].get("requestId").is_none());
+    }
+
+    #[test]
+    fn test_include_raw_envelope() {
+        let config = Config {
+            include_raw: true,
+            field_renames: vec![("type".to_string(), "log_type".to_string())],
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        let time = Utc::now();
+        aggregator.add_batch(vec![TelemetryEvent {
+            time,
+            event_type: "function".to_string(),
+            record: serde_json::json!({"type": "audit"}),
+            request_id: None,
+        }]);
+        
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        let raw = &batch[0]["_raw"];
+        assert_eq!(raw["time"], serde_json::to_value(time).unwrap());
+        assert_eq!(raw["type"], "function");
+        assert_eq!(raw["record"], serde_json::json!({"type": "audit"}));
+        assert_eq!(batch[0]["record"], serde_json::json!({"log_type": "audit"}));
+    }
+
+    #[test]
+    fn test_tag_version() {
+        let config = Config {
+            tag_version: true,
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        aggregator.add_batch(vec![TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!("hello"),
+            request_id: None,
+        }]);
+        
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert_eq!(batch[0]["extension_version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn test_instance_id_shared_across_events() {
+        let _guard = crate::test_support::INSTANCE_ID_LOCK.blocking_lock();
+        let config = Config {
+            include_instance_id: true,
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        let event = |message: &str| TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function".to_string(),
+            record: serde_json::json!(message),
+            request_id: None,
+        };
+        aggregator.add_batch(vec![event("one"), event("two")]);
+        aggregator.add_batch(vec![event("three")]);
+        
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert_eq!(batch.len(), 3);
+        let first_id = batch[0]["instance_id"].as_str().unwrap();
+        assert!(uuid::Uuid::parse_str(first_id).is_ok());
+        for record in &batch {
+            assert_eq!(record["instance_id"], first_id);
+            assert_eq!(record["pid"], std::process::id());
+        }
+    }
+
+    #[test]
+    fn test_flush_on_types() {
+        let config = Config {
+            flush_on_types: vec!["platform.runtimeDone:error".to_string(), "function.alert".to_string()],
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        let runtime_done = |status: &str| TelemetryEvent {
+            time: Utc::now(),
+            event_type: "platform.runtimeDone".to_string(),
+            record: serde_json::json!({"requestId": "req-1", "status": status}),
+            request_id: None,
+        };
+        
+        aggregator.add_batch(vec![runtime_done("success")]);
+        assert!(!aggregator.take_flush_request());
+        
+        aggregator.add_batch(vec![runtime_done("error")]);
+        assert!(aggregator.take_flush_request());
+        
+        aggregator.add_batch(vec![TelemetryEvent {
+            time: Utc::now(),
+            event_type: "function.alert".to_string(),
+            record: serde_json::json!("disk almost full"),
+            request_id: None,
+        }]);
+        assert!(aggregator.take_flush_request());
+    }
+
+    #[test]
+    fn test_timestamp_units() {
+        let time = DateTime::parse_from_rfc3339("2024-01-02T03:04:05.678901Z").unwrap().with_timezone(&Utc);
+        let cases = [
+            (TimestampUnit::Seconds, 1_704_164_645_i64),
+            (TimestampUnit::Millis, 1_704_164_645_678),
+            (TimestampUnit::Micros, 1_704_164_645_678_901),
+            (TimestampUnit::Nanos, 1_704_164_645_678_901_000),
+        ];
+        
+        for (unit, expected) in cases {
+            let mut aggregator = TelemetryAggregator::new(1024, 10);
+            aggregator.timestamp_unit = unit;
+            assert_eq!(
+                timestamp_of_first(&mut aggregator, serde_json::json!("log"), time),
+                expected,
+                "unexpected _timestamp for {unit:?}"
+            );
+        }
+    }
+    
+    #[test]
+    fn test_timestamp_format() {
+        let time = DateTime::parse_from_rfc3339("2024-01-02T03:04:05.678901Z").unwrap().with_timezone(&Utc);
+        let event = || TelemetryEvent {
+            time,
+            event_type: "function".to_string(),
+            record: serde_json::json!("log"),
+            request_id: None,
+        };
+        
+        let mut aggregator = TelemetryAggregator::from_config(&Config::default());
+        aggregator.add_batch(vec![event()]);
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert_eq!(batch[0]["_timestamp"], 1_704_164_645_678_901_i64);
+        
+        let config = Config {
+            timestamp_format: TimestampFormat::Rfc3339,
+            ..Config::default()
+        };
+        let mut aggregator = TelemetryAggregator::from_config(&config);
+        aggregator.add_batch(vec![event()]);
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&aggregator.get_batch()).unwrap();
+        assert_eq!(batch[0]["_timestamp"], "2024-01-02T03:04:05.678901+00:00");
     }
     
     #[test]
@@ -295,4 +2904,270 @@ mod tests {
         assert!(json.contains("\"type\":\"function\""));
         assert!(json.contains("\"record\":\"Test telemetry message\""));
     }
+    
+    #[tokio::test]
+    async fn test_port_fallback_when_taken() {
+        // Occupy a port so the subscriber has to move on
+        let taken = std::net::TcpListener::bind("0.0.0.0:0").unwrap();
+        let taken_port = taken.local_addr().unwrap().port();
+        
+        let aggregator = Arc::new(Mutex::new(TelemetryAggregator::new(1024, 10)));
+        let mut subscriber = TelemetrySubscriber::new(taken_port, aggregator);
+        subscriber.start().await.expect("subscriber should fall back to another port");
+        
+        assert_ne!(subscriber.port, taken_port);
+        assert!(subscriber.port > taken_port);
+        assert!(subscriber.port < taken_port.saturating_add(PORT_FALLBACK_ATTEMPTS));
+        
+        subscriber.shutdown().await;
+    }
+    
+    #[tokio::test]
+    async fn test_concurrent_deliveries_under_limit() {
+        let aggregator = Arc::new(Mutex::new(TelemetryAggregator::new(1024 * 1024, 1000)));
+        let mut subscriber = TelemetrySubscriber::new(0, Arc::clone(&aggregator));
+        subscriber.set_concurrency_limit(4);
+        subscriber.start().await.unwrap();
+        
+        let url = format!("http://127.0.0.1:{}", subscriber.port);
+        let client = reqwest::Client::new();
+        let deliveries = (0..50).map(|i| {
+            let body = format!(r#"[{{"time":"2024-01-01T00:00:00Z","type":"function","record":"log {i}"}}]"#);
+            client.post(&url).body(body).send()
+        });
+        for response in futures::future::join_all(deliveries).await {
+            assert_eq!(response.unwrap().status(), reqwest::StatusCode::OK);
+        }
+        
+        let mut guard = aggregator.lock().await;
+        let batch: Vec<serde_json::Value> = serde_json::from_slice(&guard.get_batch()).unwrap();
+        assert_eq!(batch.len(), 50);
+        assert_eq!(subscriber.concurrency_limit.as_ref().unwrap().available_permits(), 4);
+        drop(guard);
+        
+        subscriber.shutdown().await;
+    }
+    
+    #[tokio::test]
+    async fn test_immediate_forward_posts_each_delivery() {
+        let server = MockServer::start().await;
+        let config = Config {
+            o2_endpoint: server.endpoint(),
+            o2_organization_id: "test_org".to_string(),
+            o2_authorization_header: "Basic dGVzdA==".to_string(),
+            immediate_forward: true,
+            ..Config::default()
+        };
+        let aggregator = Arc::new(Mutex::new(TelemetryAggregator::from_config(&config)));
+        let mut subscriber = TelemetrySubscriber::new(0, Arc::clone(&aggregator));
+        subscriber.set_immediate_forward(Arc::new(config)).unwrap();
+        subscriber.start().await.unwrap();
+        
+        let url = format!("http://127.0.0.1:{}", subscriber.port);
+        let client = reqwest::Client::new();
+        for i in 0..3 {
+            let body = format!(r#"[{{"time":"2024-01-01T00:00:00Z","type":"function","record":"log {i}"}}]"#);
+            let response = client.post(&url).body(body).send().await.unwrap();
+            assert_eq!(response.status(), reqwest::StatusCode::OK);
+            assert_eq!(server.request_count().await, i + 1);
+        }
+        
+        let requests = server.requests().await;
+        let sent: Vec<serde_json::Value> = serde_json::from_slice(&requests[2].body).unwrap();
+        assert_eq!(sent[0]["record"], "log 2");
+        assert!(sent[0]["_timestamp"].is_i64());
+        
+        // Nothing was left in the shared buffer
+        assert!(aggregator.lock().await.is_empty());
+        subscriber.shutdown().await;
+    }
+    
+    #[tokio::test]
+    async fn test_immediate_forward_resends_redelivery_after_failure() {
+        let server = MockServer::start().await;
+        server.push_status(StatusCode::SERVICE_UNAVAILABLE).await;
+        let config = Config {
+            o2_endpoint: server.endpoint(),
+            o2_organization_id: "test_org".to_string(),
+            o2_authorization_header: "Basic dGVzdA==".to_string(),
+            immediate_forward: true,
+            telemetry_dedup: true,
+            max_retries: 0,
+            ..Config::default()
+        };
+        let forwarder = ImmediateForwarder::new(Arc::new(config)).unwrap();
+        let delivery = || parse_telemetry_events(br#"[{"time":"2024-01-01T00:00:00Z","type":"function","record":"log"}]"#).unwrap();
+        
+        assert!(forwarder.forward(delivery()).await.is_err());
+        // The Telemetry API retries the delivery the failed send answered 500 to
+        assert!(forwarder.forward(delivery()).await.is_ok());
+        // Once delivered, a further redelivery is a duplicate
+        assert_eq!(forwarder.forward(delivery()).await.unwrap(), 0);
+        
+        let requests = server.requests().await;
+        assert_eq!(requests.len(), 2);
+        let sent: Vec<serde_json::Value> = serde_json::from_slice(&requests[1].body).unwrap();
+        assert_eq!(sent[0]["record"], "log");
+    }
+    
+    #[tokio::test]
+    async fn test_immediate_forward_drops_oversized_event() {
+        let server = MockServer::start().await;
+        let config = Config {
+            o2_endpoint: server.endpoint(),
+            o2_organization_id: "test_org".to_string(),
+            o2_authorization_header: "Basic dGVzdA==".to_string(),
+            immediate_forward: true,
+            max_buffer_size_mb: 1,
+            ..Config::default()
+        };
+        let forwarder = ImmediateForwarder::new(Arc::new(config)).unwrap();
+        let oversized = "x".repeat(2 * 1024 * 1024);
+        let events = parse_telemetry_events(format!(
+            r#"[{{"time":"2024-01-01T00:00:00Z","type":"function","record":"{oversized}"}},
+               {{"time":"2024-01-01T00:00:01Z","type":"function","record":"fits"}}]"#
+        ).as_bytes()).unwrap();
+        
+        // Returns instead of waiting for the oversized event to fit
+        tokio::time::timeout(Duration::from_secs(5), forwarder.forward(events)).await.unwrap().unwrap();
+        
+        let requests = server.requests().await;
+        assert_eq!(requests.len(), 1);
+        let sent: Vec<serde_json::Value> = serde_json::from_slice(&requests[0].body).unwrap();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0]["record"], "fits");
+        assert_eq!(forwarder.aggregator.lock().await.oversized_count(), 1);
+    }
+    
+    #[tokio::test]
+    async fn test_immediate_forward_marks_each_sent_batch_seen() {
+        let server = MockServer::start().await;
+        let config = Config {
+            o2_endpoint: server.endpoint(),
+            o2_organization_id: "test_org".to_string(),
+            o2_authorization_header: "Basic dGVzdA==".to_string(),
+            immediate_forward: true,
+            telemetry_dedup: true,
+            max_retries: 0,
+            ..Config::default()
+        };
+        let forwarder = ImmediateForwarder::new(Arc::new(config)).unwrap();
+        forwarder.aggregator.lock().await.max_batch_entries_size = 1;
+        let delivery = || parse_telemetry_events(
+            br#"[{"time":"2024-01-01T00:00:00Z","type":"function","record":"first"},
+                {"time":"2024-01-01T00:00:01Z","type":"function","record":"second"}]"#
+        ).unwrap();
+        
+        // The first batch goes out, the second fails, so the delivery is retried
+        server.push_status(StatusCode::OK).await;
+        server.push_status(StatusCode::SERVICE_UNAVAILABLE).await;
+        assert!(forwarder.forward(delivery()).await.is_err());
+        assert!(forwarder.forward(delivery()).await.is_ok());
+        
+        let records: Vec<serde_json::Value> = server.requests().await.iter()
+            .map(|request| serde_json::from_slice::<Vec<serde_json::Value>>(&request.body).unwrap()[0]["record"].clone())
+            .collect();
+        assert_eq!(records, vec!["first", "second", "second"]);
+    }
+    
+    #[tokio::test]
+    async fn test_resubscribe_when_no_telemetry_arrives() {
+        let runtime_api = MockServer::start().await;
+        let aggregator = Arc::new(Mutex::new(TelemetryAggregator::new(1024, 10)));
+        let mut subscriber = TelemetrySubscriber::new(0, aggregator);
+        subscriber.runtime_api_endpoint = runtime_api.endpoint();
+        
+        subscriber.subscribe_to_telemetry_api("test-extension-id").await.unwrap();
+        assert_eq!(runtime_api.request_count().await, 1);
+        
+        // First invoke only starts the watch
+        let window = Duration::from_millis(50);
+        assert!(!subscriber.resubscribe_if_silent(window).await.unwrap());
+        
+        // Nothing arrives within the window - next invoke re-subscribes with the stored ID
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(subscriber.resubscribe_if_silent(window).await.unwrap());
+        
+        let requests = runtime_api.requests().await;
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].method, "PUT");
+        assert!(requests[1].uri.ends_with("/2022-07-01/telemetry"));
+        assert_eq!(requests[1].headers["Lambda-Extension-Identifier"], "test-extension-id");
+    }
+    
+    #[tokio::test]
+    async fn test_subscribe_times_out() {
+        let runtime_api = MockServer::start().await;
+        runtime_api.set_delay(Duration::from_secs(2)).await;
+        let aggregator = Arc::new(Mutex::new(TelemetryAggregator::new(1024, 10)));
+        let mut subscriber = TelemetrySubscriber::new(0, aggregator);
+        subscriber.runtime_api_endpoint = runtime_api.endpoint();
+        subscriber.set_subscribe_retries(&Config { request_timeout_ms: 100, max_retries: 0, ..Config::default() });
+        
+        let started = Instant::now();
+        assert!(subscriber.subscribe_to_telemetry_api("test-extension-id").await.is_err());
+        assert!(started.elapsed() < Duration::from_secs(1), "{:?}", started.elapsed());
+    }
+    
+    #[tokio::test]
+    async fn test_subscription_uses_callback_host() {
+        let runtime_api = MockServer::start().await;
+        let aggregator = Arc::new(Mutex::new(TelemetryAggregator::new(1024, 10)));
+        let mut subscriber = TelemetrySubscriber::new(8080, aggregator);
+        subscriber.runtime_api_endpoint = runtime_api.endpoint();
+        
+        subscriber.subscribe_to_telemetry_api("test-extension-id").await.unwrap();
+        subscriber.set_callback_host("127.0.0.1");
+        subscriber.subscribe_to_telemetry_api("test-extension-id").await.unwrap();
+        
+        let requests = runtime_api.requests().await;
+        let destination = |index: usize| {
+            let body: serde_json::Value = serde_json::from_slice(&requests[index].body).unwrap();
+            body["destination"]["URI"].as_str().unwrap().to_string()
+        };
+        assert_eq!(destination(0), "http://sandbox.localdomain:8080");
+        assert_eq!(destination(1), "http://127.0.0.1:8080");
+    }
+    
+    #[tokio::test]
+    async fn test_subscribe_retries_until_accepted() {
+        let runtime_api = MockServer::start().await;
+        runtime_api.push_status(StatusCode::INTERNAL_SERVER_ERROR).await;
+        let aggregator = Arc::new(Mutex::new(TelemetryAggregator::new(1024, 10)));
+        let mut subscriber = TelemetrySubscriber::new(8080, aggregator);
+        subscriber.runtime_api_endpoint = runtime_api.endpoint();
+        subscriber.set_subscribe_retries(&Config {
+            max_retries: 2,
+            initial_retry_delay_ms: 10,
+            max_retry_delay_ms: 10,
+            ..Default::default()
+        });
+        
+        subscriber.subscribe_to_telemetry_api("test-extension-id").await.unwrap();
+        assert_eq!(runtime_api.request_count().await, 2);
+        
+        // Retries are bounded
+        for _ in 0..3 {
+            runtime_api.push_status(StatusCode::INTERNAL_SERVER_ERROR).await;
+        }
+        assert!(subscriber.subscribe_to_telemetry_api("test-extension-id").await.is_err());
+        assert_eq!(runtime_api.request_count().await, 5);
+    }
+    
+    #[tokio::test]
+    async fn test_no_resubscribe_when_telemetry_flowing() {
+        let runtime_api = MockServer::start().await;
+        let aggregator = Arc::new(Mutex::new(TelemetryAggregator::new(1024, 10)));
+        let mut subscriber = TelemetrySubscriber::new(0, aggregator);
+        subscriber.runtime_api_endpoint = runtime_api.endpoint();
+        subscriber.subscribe_to_telemetry_api("test-extension-id").await.unwrap();
+        
+        let window = Duration::from_millis(50);
+        assert!(!subscriber.resubscribe_if_silent(window).await.unwrap());
+        *subscriber.last_telemetry_received.lock().await = Some(Instant::now());
+        
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(!subscriber.resubscribe_if_silent(window).await.unwrap());
+        assert_eq!(runtime_api.request_count().await, 1);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,228 @@
+#![allow(dead_code)]
+
+// Lightweight mock HTTP server for unit tests that need to observe outbound requests
+use http::{Request, Response, StatusCode};
+use hyper::{body, Body, Server};
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub uri: String,
+    pub version: http::Version,
+    pub headers: http::HeaderMap,
+    pub body: Vec<u8>,
+    pub received_at: std::time::Instant,
+}
+
+// A queued response: status, plus a body to send instead of "{}"
+pub type QueuedResponse = (StatusCode, Option<String>);
+
+pub struct MockServer {
+    pub addr: SocketAddr,
+    pub requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    pub status: Arc<Mutex<StatusCode>>,
+    pub queued_statuses: Arc<Mutex<VecDeque<QueuedResponse>>>,
+    pub delay: Arc<Mutex<std::time::Duration>>,
+    pub max_body_bytes: Arc<Mutex<Option<usize>>>,
+    pub responses_sent: Arc<std::sync::atomic::AtomicUsize>,
+    pub connections: Arc<std::sync::atomic::AtomicUsize>,
+    handle: tokio::task::JoinHandle<()>,
+}
+
+impl MockServer {
+    pub async fn start() -> Self {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let status = Arc::new(Mutex::new(StatusCode::OK));
+        let queued_statuses = Arc::new(Mutex::new(VecDeque::new()));
+        let delay = Arc::new(Mutex::new(std::time::Duration::ZERO));
+        let max_body_bytes = Arc::new(Mutex::new(None));
+        let responses_sent = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let connections = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        let requests_clone = Arc::clone(&requests);
+        let status_clone = Arc::clone(&status);
+        let queued_clone = Arc::clone(&queued_statuses);
+        let delay_clone = Arc::clone(&delay);
+        let max_body_clone = Arc::clone(&max_body_bytes);
+        let responses_clone = Arc::clone(&responses_sent);
+        let connections_clone = Arc::clone(&connections);
+        let make_svc = hyper::service::make_service_fn(move |_conn| {
+            connections_clone.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let requests = Arc::clone(&requests_clone);
+            let status = Arc::clone(&status_clone);
+            let queued_statuses = Arc::clone(&queued_clone);
+            let delay = Arc::clone(&delay_clone);
+            let max_body_bytes = Arc::clone(&max_body_clone);
+            let responses_sent = Arc::clone(&responses_clone);
+            async move {
+                Ok::<_, Infallible>(hyper::service::service_fn(move |req: Request<Body>| {
+                    let requests = Arc::clone(&requests);
+                    let status = Arc::clone(&status);
+                    let queued_statuses = Arc::clone(&queued_statuses);
+                    let delay = Arc::clone(&delay);
+                    let max_body_bytes = Arc::clone(&max_body_bytes);
+                    let responses_sent = Arc::clone(&responses_sent);
+                    async move {
+                        let method = req.method().to_string();
+                        let uri = req.uri().to_string();
+                        let version = req.version();
+                        let headers = req.headers().clone();
+                        let body = body::to_bytes(req.into_body()).await.unwrap_or_default().to_vec();
+                        let too_large = max_body_bytes.lock().await.is_some_and(|max| body.len() > max);
+                        requests.lock().await.push(RecordedRequest {
+                            method,
+                            uri,
+                            version,
+                            headers,
+                            body,
+                            received_at: std::time::Instant::now(),
+                        });
+
+                        let delay = *delay.lock().await;
+                        if !delay.is_zero() {
+                            tokio::time::sleep(delay).await;
+                        }
+
+                        let queued = queued_statuses.lock().await.pop_front();
+                        let (status, response_body) = match queued {
+                            Some((status, response_body)) => (status, response_body),
+                            None if too_large => (StatusCode::PAYLOAD_TOO_LARGE, None),
+                            None => (*status.lock().await, None),
+                        };
+                        responses_sent.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                        Ok::<_, Infallible>(
+                            Response::builder()
+                                .status(status)
+                                .header("Lambda-Extension-Identifier", "test-extension-id")
+                                .body(Body::from(response_body.unwrap_or_else(|| "{}".to_string())))
+                                .unwrap(),
+                        )
+                    }
+                }))
+            }
+        });
+
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_svc);
+        let addr = server.local_addr();
+        let handle = tokio::spawn(async move {
+            let _ = server.await;
+        });
+
+        Self { addr, requests, status, queued_statuses, delay, max_body_bytes, responses_sent, connections, handle }
+    }
+
+    pub fn endpoint(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    pub async fn set_status(&self, status: StatusCode) {
+        *self.status.lock().await = status;
+    }
+
+    // Answer the next request with `status`, then fall back to the regular status
+    pub async fn push_status(&self, status: StatusCode) {
+        self.queued_statuses.lock().await.push_back((status, None));
+    }
+
+    // Answer the next request with `status` and `body` (e.g. a runtime API event)
+    pub async fn push_response(&self, status: StatusCode, body: &str) {
+        self.queued_statuses.lock().await.push_back((status, Some(body.to_string())));
+    }
+
+    pub async fn set_delay(&self, delay: std::time::Duration) {
+        *self.delay.lock().await = delay;
+    }
+
+    // Reject request bodies larger than `max` with 413, like a gateway body limit
+    pub async fn set_max_body_bytes(&self, max: usize) {
+        *self.max_body_bytes.lock().await = Some(max);
+    }
+
+    pub fn responses_sent(&self) -> usize {
+        self.responses_sent.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    // Number of TCP connections accepted so far
+    pub fn connection_count(&self) -> usize {
+        self.connections.load(std::sync::atomic::Ordering::SeqCst)
+    }
+
+    pub async fn request_count(&self) -> usize {
+        self.requests.lock().await.len()
+    }
+
+    pub async fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().await.clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+// Held by tests that compare instance IDs, since a SnapStart restore test replaces the
+// process-wide one
+pub static INSTANCE_ID_LOCK: Mutex<()> = Mutex::const_new(());
+
+// Log output sink for tests asserting on what was logged
+#[derive(Clone, Default)]
+pub struct CapturedLogs(pub Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl CapturedLogs {
+    pub fn output(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+impl std::io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// Counts heap allocations made by the current thread, for allocation-sensitive tests
+pub struct CountingAllocator;
+
+thread_local! {
+    static THREAD_ALLOCATIONS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        // try_with: the thread-local may already be gone while a thread shuts down
+        let _ = THREAD_ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        std::alloc::System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        std::alloc::System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: std::alloc::Layout, new_size: usize) -> *mut u8 {
+        let _ = THREAD_ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+        std::alloc::System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Heap allocations made by the current thread while running `f`
+pub fn count_allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = THREAD_ALLOCATIONS.with(|count| count.get());
+    let result = f();
+    let after = THREAD_ALLOCATIONS.with(|count| count.get());
+    (result, after - before)
+}
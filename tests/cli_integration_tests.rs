@@ -15,6 +15,8 @@ async fn test_help_command() {
     assert!(stdout.contains("COMMANDS:"));
     assert!(stdout.contains("--health-check"));
     assert!(stdout.contains("--version"));
+    assert!(stdout.contains("--parse-file"));
+    assert!(stdout.contains("--diagnose"));
     assert!(stdout.contains("--help"));
 }
 
@@ -81,6 +83,46 @@ async fn test_health_check_success() {
     test_env.shutdown().await;
 }
 
+// Multi-threaded so the mock server keeps serving while the command blocks this test
+#[tokio::test(flavor = "multi_thread")]
+async fn test_diagnose_command() {
+    let mut test_env = TestEnvironment::new().await
+        .expect("Failed to create test environment");
+    
+    let mock_port = test_env.mock_server.port;
+    
+    let env_vars = [
+        ("O2_ORGANIZATION_ID", "test_org"),
+        ("O2_AUTHORIZATION_HEADER", "Basic dGVzdA=="),
+        ("O2_ENDPOINT", &format!("http://127.0.0.1:{mock_port}")),
+    ];
+
+    let output = run_extension_command_with_env(&["--diagnose"], &env_vars)
+        .expect("Failed to run command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "diagnose failed: {stdout}");
+    assert!(stdout.contains("[PASS] Configuration"));
+    assert!(stdout.contains("[PASS] DNS resolution"));
+    assert!(stdout.contains("[PASS] TCP connect"));
+    assert!(stdout.contains("[SKIP] TLS handshake"));
+    assert!(stdout.contains("[PASS] Send test event"));
+    assert!(!stdout.contains("[FAIL]"));
+    
+    test_env.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_diagnose_stops_at_config_failure() {
+    let output = run_extension_command_with_env(&["--diagnose"], &[("O2_ENDPOINT", "http://127.0.0.1:1")])
+        .expect("Failed to run command");
+
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("[FAIL] Configuration"));
+    assert!(!stdout.contains("DNS resolution"));
+}
+
 #[tokio::test]
 async fn test_health_check_auth_failure() {
     let mut test_env = TestEnvironment::new().await
@@ -154,6 +196,65 @@ async fn test_health_check_short_flag() {
     test_env.shutdown().await;
 }
 
+#[tokio::test]
+async fn test_parse_file_command() {
+    let output = run_extension_command(&["--parse-file", "tests/fixtures/telemetry_sample.json"])
+        .expect("Failed to run command");
+
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let batch: serde_json::Value = serde_json::from_str(stdout.trim())
+        .expect("Output should be a single JSON batch");
+
+    let events = batch.as_array().expect("Batch should be a JSON array");
+    assert_eq!(events.len(), 3);
+
+    // Aggregated events carry _timestamp in place of time
+    assert_eq!(events[0]["type"], "platform.start");
+    assert_eq!(events[0]["_timestamp"], 1_704_164_645_678_000_i64);
+    assert!(events[0].get("time").is_none());
+    assert_eq!(events[1]["type"], "function");
+    assert!(events[1]["record"].as_str().unwrap().contains("Hello from the function"));
+    assert_eq!(events[2]["record"]["status"], "success");
+}
+
+#[tokio::test]
+async fn test_parse_file_invalid_json() {
+    let mut file = tempfile::NamedTempFile::new().expect("Failed to create temp file");
+    std::io::Write::write_all(&mut file, b"{ not telemetry").unwrap();
+
+    let output = run_extension_command(&["--parse-file", file.path().to_str().unwrap()])
+        .expect("Failed to run command");
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Failed to parse telemetry events"));
+}
+
+#[tokio::test]
+async fn test_parse_file_invalid_setting() {
+    let output = run_extension_command_with_env(
+        &["--parse-file", "tests/fixtures/telemetry_sample.json"],
+        &[("O2_TIMESTAMP_FORMAT", "sometimes")],
+    )
+    .expect("Failed to run command");
+
+    // Previewing with defaults would not match what the extension sends
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Invalid O2_TIMESTAMP_FORMAT"), "stderr: {stderr}");
+    
+    let output = run_extension_command_with_env(
+        &["--parse-file", "tests/fixtures/telemetry_sample.json"],
+        &[("O2_TLS_MIN_VERSION", "1.1")],
+    )
+    .expect("Failed to run command");
+    
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Invalid O2_TLS_MIN_VERSION"), "stderr: {stderr}");
+}
+
 #[tokio::test]
 async fn test_invalid_command() {
     let output = run_extension_command(&["--invalid-command"])
@@ -196,4 +297,17 @@ async fn test_normal_mode_with_missing_config() {
         Ok(()) => (), // Expected config error occurred
         Err(e) => panic!("Normal mode missing config test failed: {e}"),
     }
-}
\ No newline at end of file
+}
+#[tokio::test]
+async fn test_invalid_self_log_sample_is_reported() {
+    let env_vars = [
+        ("O2_ORGANIZATION_ID", "test_org"),
+        ("O2_AUTHORIZATION_HEADER", "Basic dGVzdA=="),
+        ("O2_SELF_LOG_SAMPLE", "often"),
+    ];
+
+    match run_extension_command_with_expectation(&[], &env_vars, ExpectedResult::Failure("Invalid O2_SELF_LOG_SAMPLE".to_string())) {
+        Ok(()) => (),
+        Err(e) => panic!("Invalid O2_SELF_LOG_SAMPLE test failed: {e}"),
+    }
+}